@@ -0,0 +1,77 @@
+-- Schema of the first versioned release. Databases from before it only have `users` and
+-- `refresh_tokens`, those are kept and get the columns added since
+
+CREATE TABLE IF NOT EXISTS `users` (
+  `id` int(11) NOT NULL AUTO_INCREMENT,
+  `username` varchar(255) NOT NULL,
+  `email` varchar(255) NOT NULL,
+  `password_hash` varchar(255) NOT NULL,
+  `created_at` timestamp NULL DEFAULT current_timestamp(),
+  PRIMARY KEY (`id`),
+  UNIQUE KEY `username` (`username`),
+  UNIQUE KEY `email` (`email`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci;
+
+ALTER TABLE `users`
+  ADD COLUMN IF NOT EXISTS `storage_quota` bigint(20) unsigned DEFAULT NULL AFTER `created_at`,
+  ADD COLUMN IF NOT EXISTS `data_key` varbinary(60) DEFAULT NULL AFTER `storage_quota`;
+
+CREATE TABLE IF NOT EXISTS `refresh_tokens` (
+  `id` int(11) NOT NULL AUTO_INCREMENT,
+  `user_id` int(11) NOT NULL,
+  `token` varchar(255) NOT NULL,
+  PRIMARY KEY (`id`),
+  KEY `user_id` (`user_id`),
+  CONSTRAINT `refresh_tokens_ibfk_1` FOREIGN KEY (`user_id`) REFERENCES `users` (`id`) ON DELETE CASCADE
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci;
+
+CREATE TABLE IF NOT EXISTS `vaults` (
+  `id` int(10) unsigned NOT NULL AUTO_INCREMENT,
+  `user_id` int(11) NOT NULL,
+  `name` varchar(64) NOT NULL,
+  `created_at` timestamp NOT NULL DEFAULT current_timestamp(),
+  PRIMARY KEY (`id`),
+  UNIQUE KEY `user_name` (`user_id`,`name`),
+  CONSTRAINT `vaults_ibfk_1` FOREIGN KEY (`user_id`) REFERENCES `users` (`id`) ON DELETE CASCADE
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci;
+
+CREATE TABLE IF NOT EXISTS `vault_versions` (
+  `id` int(11) NOT NULL AUTO_INCREMENT,
+  `vault_id` int(10) unsigned NOT NULL,
+  `version` int(10) unsigned NOT NULL,
+  `size` bigint(20) unsigned NOT NULL,
+  `uploaded_by` int(11) NOT NULL,
+  `checksum` char(64) NOT NULL,
+  `object_key` varchar(255) NOT NULL,
+  `encrypted` tinyint(1) NOT NULL DEFAULT 0,
+  `created_at` timestamp NOT NULL DEFAULT current_timestamp(),
+  PRIMARY KEY (`id`),
+  UNIQUE KEY `vault_version` (`vault_id`,`version`),
+  CONSTRAINT `vault_versions_ibfk_1` FOREIGN KEY (`vault_id`) REFERENCES `vaults` (`id`) ON DELETE CASCADE
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci;
+
+CREATE TABLE IF NOT EXISTS `upload_sessions` (
+  `id` char(32) NOT NULL,
+  `vault_id` int(10) unsigned NOT NULL,
+  `user_id` int(11) NOT NULL,
+  `object_key` varchar(255) NOT NULL,
+  `store_upload_id` varchar(255) NOT NULL,
+  `base_revision` int(10) unsigned NOT NULL,
+  `length` bigint(20) unsigned NOT NULL,
+  `upload_offset` bigint(20) unsigned NOT NULL DEFAULT 0,
+  `expected_checksum` char(64) DEFAULT NULL,
+  `created_at` timestamp NOT NULL DEFAULT current_timestamp(),
+  `expires_at` timestamp NOT NULL,
+  PRIMARY KEY (`id`),
+  KEY `expires_at` (`expires_at`),
+  CONSTRAINT `upload_sessions_ibfk_1` FOREIGN KEY (`vault_id`) REFERENCES `vaults` (`id`) ON DELETE CASCADE
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci;
+
+CREATE TABLE IF NOT EXISTS `upload_parts` (
+  `session_id` char(32) NOT NULL,
+  `part_number` smallint(5) unsigned NOT NULL,
+  `etag` varchar(255) NOT NULL,
+  `size` bigint(20) unsigned NOT NULL,
+  PRIMARY KEY (`session_id`,`part_number`),
+  CONSTRAINT `upload_parts_ibfk_1` FOREIGN KEY (`session_id`) REFERENCES `upload_sessions` (`id`) ON DELETE CASCADE
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci;
@@ -0,0 +1,7 @@
+-- Tokens issued before families can't be rotated, their owners log in again
+DELETE FROM `refresh_tokens`;
+
+ALTER TABLE `refresh_tokens`
+  ADD COLUMN IF NOT EXISTS `family_id` char(32) NOT NULL AFTER `token`,
+  ADD COLUMN IF NOT EXISTS `retired` tinyint(1) NOT NULL DEFAULT 0 AFTER `family_id`,
+  ADD KEY IF NOT EXISTS `family_id` (`family_id`);
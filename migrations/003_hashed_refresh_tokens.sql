@@ -0,0 +1,11 @@
+-- Only hashes of opaque tokens are stored from now on, the old tokens are useless
+DELETE FROM `refresh_tokens`;
+
+ALTER TABLE `refresh_tokens`
+  DROP COLUMN IF EXISTS `token`,
+  ADD COLUMN IF NOT EXISTS `token_hash` char(64) NOT NULL AFTER `user_id`,
+  ADD COLUMN IF NOT EXISTS `created_at` timestamp NOT NULL DEFAULT current_timestamp(),
+  ADD COLUMN IF NOT EXISTS `expires_at` timestamp NOT NULL,
+  ADD COLUMN IF NOT EXISTS `last_used_at` timestamp NULL DEFAULT NULL,
+  ADD UNIQUE KEY IF NOT EXISTS `token_hash` (`token_hash`),
+  ADD KEY IF NOT EXISTS `expires_at` (`expires_at`);
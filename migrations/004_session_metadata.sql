@@ -0,0 +1,4 @@
+ALTER TABLE `refresh_tokens`
+  ADD COLUMN IF NOT EXISTS `device_name` varchar(64) DEFAULT NULL,
+  ADD COLUMN IF NOT EXISTS `user_agent` varchar(255) DEFAULT NULL,
+  ADD COLUMN IF NOT EXISTS `ip_address` varchar(45) DEFAULT NULL;
@@ -0,0 +1,15 @@
+ALTER TABLE `users`
+  ADD COLUMN IF NOT EXISTS `totp_secret` varbinary(64) DEFAULT NULL,
+  ADD COLUMN IF NOT EXISTS `totp_nonce` varbinary(12) DEFAULT NULL,
+  ADD COLUMN IF NOT EXISTS `totp_enabled_at` timestamp NULL DEFAULT NULL,
+  ADD COLUMN IF NOT EXISTS `totp_last_step` bigint(20) unsigned DEFAULT NULL;
+
+CREATE TABLE IF NOT EXISTS `recovery_codes` (
+  `id` int(11) NOT NULL AUTO_INCREMENT,
+  `user_id` int(11) NOT NULL,
+  `code_hash` varchar(255) NOT NULL,
+  `used_at` timestamp NULL DEFAULT NULL,
+  PRIMARY KEY (`id`),
+  KEY `user_id` (`user_id`),
+  CONSTRAINT `recovery_codes_ibfk_1` FOREIGN KEY (`user_id`) REFERENCES `users` (`id`) ON DELETE CASCADE
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci;
@@ -0,0 +1,26 @@
+CREATE TABLE IF NOT EXISTS `webauthn_credentials` (
+  `id` int(11) NOT NULL AUTO_INCREMENT,
+  `user_id` int(11) NOT NULL,
+  `credential_id` varbinary(255) NOT NULL,
+  `public_key` varbinary(255) NOT NULL,
+  `algorithm` int(11) NOT NULL,
+  `sign_count` int(10) unsigned NOT NULL DEFAULT 0,
+  `name` varchar(64) NOT NULL,
+  `created_at` timestamp NOT NULL DEFAULT current_timestamp(),
+  `last_used_at` timestamp NULL DEFAULT NULL,
+  PRIMARY KEY (`id`),
+  UNIQUE KEY `credential_id` (`credential_id`),
+  KEY `user_id` (`user_id`),
+  CONSTRAINT `webauthn_credentials_ibfk_1` FOREIGN KEY (`user_id`) REFERENCES `users` (`id`) ON DELETE CASCADE
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci;
+
+CREATE TABLE IF NOT EXISTS `webauthn_challenges` (
+  `id` char(32) NOT NULL,
+  `user_id` int(11) DEFAULT NULL,
+  `purpose` varchar(16) NOT NULL,
+  `challenge` binary(32) NOT NULL,
+  `expires_at` timestamp NOT NULL,
+  PRIMARY KEY (`id`),
+  KEY `expires_at` (`expires_at`),
+  CONSTRAINT `webauthn_challenges_ibfk_1` FOREIGN KEY (`user_id`) REFERENCES `users` (`id`) ON DELETE CASCADE
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci;
@@ -0,0 +1,3 @@
+ALTER TABLE `users`
+  ADD COLUMN IF NOT EXISTS `purge_at` timestamp NULL DEFAULT NULL,
+  ADD KEY IF NOT EXISTS `purge_at` (`purge_at`);
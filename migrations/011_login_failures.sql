@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS `login_failures` (
+  `scope` varchar(8) NOT NULL,
+  `subject` varchar(45) NOT NULL,
+  `failures` int(10) unsigned NOT NULL DEFAULT 0,
+  `last_failure_at` timestamp NOT NULL DEFAULT current_timestamp(),
+  `locked_until` timestamp NULL DEFAULT NULL,
+  PRIMARY KEY (`scope`,`subject`),
+  KEY `last_failure_at` (`last_failure_at`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci;
@@ -0,0 +1,20 @@
+CREATE TABLE IF NOT EXISTS `srp_verifiers` (
+  `user_id` int(11) NOT NULL,
+  `salt` varbinary(64) NOT NULL,
+  `verifier` varbinary(384) NOT NULL,
+  `created_at` timestamp NOT NULL DEFAULT current_timestamp(),
+  PRIMARY KEY (`user_id`),
+  CONSTRAINT `srp_verifiers_ibfk_1` FOREIGN KEY (`user_id`) REFERENCES `users` (`id`) ON DELETE CASCADE
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci;
+
+CREATE TABLE IF NOT EXISTS `srp_sessions` (
+  `id` char(32) NOT NULL,
+  `user_id` int(11) DEFAULT NULL,
+  `client_proof` binary(32) NOT NULL,
+  `server_proof` binary(32) NOT NULL,
+  `expires_at` timestamp NOT NULL,
+  PRIMARY KEY (`id`),
+  KEY `user_id` (`user_id`),
+  KEY `expires_at` (`expires_at`),
+  CONSTRAINT `srp_sessions_ibfk_1` FOREIGN KEY (`user_id`) REFERENCES `users` (`id`) ON DELETE CASCADE
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci;
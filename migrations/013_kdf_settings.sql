@@ -0,0 +1,6 @@
+ALTER TABLE `users`
+  ADD COLUMN IF NOT EXISTS `kdf_algorithm` varchar(16) NOT NULL DEFAULT 'argon2id',
+  ADD COLUMN IF NOT EXISTS `kdf_iterations` int(10) unsigned NOT NULL DEFAULT 3,
+  ADD COLUMN IF NOT EXISTS `kdf_memory` int(10) unsigned DEFAULT 65536,
+  ADD COLUMN IF NOT EXISTS `kdf_parallelism` int(10) unsigned DEFAULT 4,
+  ADD COLUMN IF NOT EXISTS `kdf_salt` varbinary(64) DEFAULT NULL;
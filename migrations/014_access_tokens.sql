@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS `access_tokens` (
+  `jti` char(32) NOT NULL,
+  `user_id` int(11) NOT NULL,
+  `session_id` char(32) NOT NULL,
+  `expires_at` timestamp NOT NULL,
+  `revoked` tinyint(1) NOT NULL DEFAULT 0,
+  PRIMARY KEY (`jti`),
+  KEY `user_id` (`user_id`),
+  KEY `session_id` (`session_id`),
+  KEY `revoked` (`revoked`,`expires_at`),
+  KEY `expires_at` (`expires_at`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci;
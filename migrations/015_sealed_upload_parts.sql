@@ -0,0 +1,5 @@
+-- Parts of unfinished uploads were stored unencrypted, those uploads start over
+DELETE FROM `upload_sessions`;
+
+ALTER TABLE `upload_sessions`
+  ADD COLUMN IF NOT EXISTS `stream_nonce` binary(7) NOT NULL;
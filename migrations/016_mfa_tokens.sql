@@ -0,0 +1,10 @@
+CREATE TABLE IF NOT EXISTS `mfa_tokens` (
+  `jti` char(32) NOT NULL,
+  `user_id` int(11) NOT NULL,
+  `attempts` int(10) unsigned NOT NULL DEFAULT 0,
+  `expires_at` timestamp NOT NULL,
+  PRIMARY KEY (`jti`),
+  KEY `user_id` (`user_id`),
+  KEY `expires_at` (`expires_at`),
+  CONSTRAINT `mfa_tokens_ibfk_1` FOREIGN KEY (`user_id`) REFERENCES `users` (`id`) ON DELETE CASCADE
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci;
@@ -0,0 +1,6 @@
+-- One-off jobs the server runs at startup that need more than SQL, done once each
+CREATE TABLE IF NOT EXISTS `data_migrations` (
+  `name` varchar(64) NOT NULL,
+  `completed_at` timestamp NOT NULL DEFAULT current_timestamp(),
+  PRIMARY KEY (`name`)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci;
@@ -0,0 +1,7 @@
+-- How far the encryption stream of an upload was used, and a digest of the chunk sealed
+-- last, so a part can only be sealed again with the same bytes
+ALTER TABLE `upload_sessions`
+  ADD COLUMN IF NOT EXISTS `sealed_offset` bigint(20) unsigned NOT NULL DEFAULT 0 AFTER `upload_offset`,
+  ADD COLUMN IF NOT EXISTS `sealed_digest` binary(32) DEFAULT NULL AFTER `sealed_offset`;
+
+UPDATE `upload_sessions` SET `sealed_offset` = `upload_offset` WHERE `sealed_offset` < `upload_offset`;
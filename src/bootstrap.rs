@@ -0,0 +1,240 @@
+use std::{future::Future, time::Duration};
+
+use anyhow::Context;
+use futures::TryStreamExt;
+use sha2::{Digest, Sha256};
+use sqlx::{mysql::MySqlPoolOptions, MySqlConnection, MySqlPool};
+
+use crate::{controllers, store::VaultStore};
+
+/// Where the server kept the one vault every user had before named vaults
+const LEGACY_VAULT_KEY: &str = "pmanager.pm";
+const LEGACY_VAULT_NAME: &str = "default";
+const LEGACY_VAULTS_MIGRATION: &str = "legacy_vaults";
+const KDF_SALTS_MIGRATION: &str = "kdf_salts";
+
+/// Schema changes in order, the one at index `i` brings the database to version `i + 1`.
+/// Released migrations are never edited, changes go into a new one at the end
+const MIGRATIONS: &[&str] = &[
+    include_str!("../migrations/001_baseline.sql"),
+    include_str!("../migrations/002_token_families.sql"),
+    include_str!("../migrations/003_hashed_refresh_tokens.sql"),
+    include_str!("../migrations/004_session_metadata.sql"),
+    include_str!("../migrations/005_totp.sql"),
+    include_str!("../migrations/006_webauthn.sql"),
+    include_str!("../migrations/007_password_resets.sql"),
+    include_str!("../migrations/008_email_verification.sql"),
+    include_str!("../migrations/009_account_changes.sql"),
+    include_str!("../migrations/010_account_deletion.sql"),
+    include_str!("../migrations/011_login_failures.sql"),
+    include_str!("../migrations/012_srp.sql"),
+    include_str!("../migrations/013_kdf_settings.sql"),
+    include_str!("../migrations/014_access_tokens.sql"),
+    include_str!("../migrations/015_sealed_upload_parts.sql"),
+    include_str!("../migrations/016_mfa_tokens.sql"),
+    include_str!("../migrations/017_data_migrations.sql"),
+    include_str!("../migrations/018_sealed_offsets.sql"),
+];
+
+/// Version of the schema this build expects
+pub const SCHEMA_VERSION: u32 = MIGRATIONS.len() as u32;
+
+fn attempts() -> u32 {
+    std::env::var("BOOTSTRAP_ATTEMPTS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(8)
+        .max(1)
+}
+
+/// Runs `op` until it succeeds, doubling the delay between attempts up to 30 seconds.
+/// Dependencies started by the same compose file are often not ready yet when we are
+async fn with_backoff<T, F, Fut>(what: &str, mut op: F) -> anyhow::Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = anyhow::Result<T>>,
+{
+    let attempts = attempts();
+    let mut delay = Duration::from_secs(1);
+    for attempt in 1..=attempts {
+        match op().await {
+            Ok(value) => return Ok(value),
+            Err(why) if attempt < attempts => {
+                tracing::warn!(
+                    "{} failed (attempt {}/{}): {}, retrying in {:?}",
+                    what,
+                    attempt,
+                    attempts,
+                    why,
+                    delay
+                );
+                tokio::time::sleep(delay).await;
+                delay = (delay * 2).min(Duration::from_secs(30));
+            }
+            Err(why) => {
+                return Err(why.context(format!("{} failed after {} attempts", what, attempts)))
+            }
+        }
+    }
+    unreachable!()
+}
+
+/// Brings the schema up to `SCHEMA_VERSION` with the migrations it hasn't seen yet. Databases
+/// from before versioning start at the baseline, which keeps their users. A lock keeps
+/// servers starting at the same time from migrating twice
+pub async fn migrate(pool: &MySqlPool) -> anyhow::Result<()> {
+    let mut conn = pool.acquire().await?;
+    let locked = sqlx::query_scalar!("SELECT GET_LOCK('schema_migration', 300)")
+        .fetch_one(&mut *conn)
+        .await?;
+    anyhow::ensure!(
+        locked == Some(1),
+        "Timed out waiting for another server to migrate the schema"
+    );
+    let migrated = apply_migrations(&mut conn).await;
+    sqlx::query!("DO RELEASE_LOCK('schema_migration')")
+        .execute(&mut *conn)
+        .await?;
+    migrated
+}
+
+async fn apply_migrations(conn: &mut MySqlConnection) -> anyhow::Result<()> {
+    sqlx::raw_sql(
+        "CREATE TABLE IF NOT EXISTS `schema_version` (
+          `version` int(10) unsigned NOT NULL,
+          PRIMARY KEY (`version`)
+        ) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4 COLLATE=utf8mb4_unicode_ci",
+    )
+    .execute(&mut *conn)
+    .await?;
+    let current =
+        sqlx::query_scalar!(r#"SELECT MAX(version) AS "version: u32" FROM schema_version"#)
+            .fetch_one(&mut *conn)
+            .await?
+            .unwrap_or(0);
+    anyhow::ensure!(
+        current <= SCHEMA_VERSION,
+        "Database schema is at version {}, newer than the {} this server knows. Run a newer server",
+        current,
+        SCHEMA_VERSION
+    );
+
+    for (version, sql) in (1u32..).zip(MIGRATIONS).skip(current as usize) {
+        // DDL commits as it goes, a migration that fails halfway is picked up again on the
+        // next start, which the `IF NOT EXISTS` clauses make safe
+        sqlx::raw_sql(sql)
+            .execute(&mut *conn)
+            .await
+            .with_context(|| format!("Migration to schema version {} failed", version))?;
+        sqlx::query!("INSERT INTO schema_version (version) VALUES (?)", version)
+            .execute(&mut *conn)
+            .await?;
+        tracing::info!("Migrated the database schema to version {}", version);
+    }
+    Ok(())
+}
+
+/// Connects to MySQL and migrates the schema to the one this build was written against
+pub async fn database(connection_str: &str) -> anyhow::Result<MySqlPool> {
+    let pool = with_backoff("Connecting to database", || async {
+        let pool = MySqlPoolOptions::new()
+            .max_connections(10)
+            .acquire_timeout(Duration::from_secs(10))
+            .connect(connection_str)
+            .await?;
+        Ok(pool)
+    })
+    .await?;
+
+    // A failing migration won't fix itself, so no retrying here
+    migrate(&pool).await?;
+    Ok(pool)
+}
+
+pub async fn store(store: &dyn VaultStore) -> anyhow::Result<()> {
+    with_backoff("Preparing vault storage", || store.prepare()).await
+}
+
+/// Gives users from before named vaults a vault whose first version is their old
+/// `{user_id}/pmanager.pm` object, so the data stays where it is and nothing is copied.
+/// Runs once, an object written before its user registered belonged to an earlier account
+/// with the same id and is left alone
+pub async fn legacy_vaults(pool: &MySqlPool, store: &dyn VaultStore) -> anyhow::Result<()> {
+    if controllers::data_migrations::is_done(pool, LEGACY_VAULTS_MIGRATION).await? {
+        return Ok(());
+    }
+
+    for (user_id, created_at) in controllers::vaults::users_without_vaults(pool).await? {
+        let key = format!("{}/{}", user_id, LEGACY_VAULT_KEY);
+        let Some(info) = store.head(&key).await? else {
+            continue;
+        };
+        let owned = match (info.last_modified, created_at) {
+            (Some(last_modified), Some(created_at)) => last_modified >= created_at,
+            _ => false,
+        };
+        if !owned {
+            tracing::warn!(
+                "Not moving {} into a vault, it may be older than user {}",
+                key,
+                user_id
+            );
+            continue;
+        }
+
+        let mut hasher = Sha256::new();
+        let mut body = store.get(&key).await?;
+        while let Some(chunk) = body.try_next().await? {
+            hasher.update(&chunk);
+        }
+        let checksum = hex::encode(hasher.finalize());
+
+        let vault_id = controllers::vaults::create_vault(pool, user_id, LEGACY_VAULT_NAME).await?;
+        let created = controllers::versions::create_version(
+            pool, vault_id, 1, info.size, user_id, &checksum, &key, false,
+        )
+        .await;
+        if !matches!(created, Ok(true)) {
+            // Without the version the user would look migrated and never get retried
+            controllers::vaults::delete_vault(pool, user_id, vault_id).await?;
+            created?;
+            anyhow::bail!("Vault {} got a version before the migration", vault_id);
+        }
+        tracing::info!(
+            "Moved the vault of user {} into vault {}",
+            user_id,
+            vault_id
+        );
+    }
+    controllers::data_migrations::mark_done(pool, LEGACY_VAULTS_MIGRATION).await
+}
+
+/// Stores the salt of accounts from before KDF settings, so prelogin never has to write.
+/// Needs the secrets loaded, the salts are derived from `SECRET_WORD_SALT`
+pub async fn kdf_salts(pool: &MySqlPool) -> anyhow::Result<()> {
+    if controllers::data_migrations::is_done(pool, KDF_SALTS_MIGRATION).await? {
+        return Ok(());
+    }
+    let count = controllers::kdf::backfill_salts(pool).await?;
+    if count > 0 {
+        tracing::info!("Stored the KDF salt of {} accounts", count);
+    }
+    controllers::data_migrations::mark_done(pool, KDF_SALTS_MIGRATION).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migrations_keep_tables() {
+        for (version, sql) in (1..).zip(MIGRATIONS) {
+            let sql = sql.to_uppercase();
+            assert!(
+                !sql.contains("DROP TABLE") && !sql.contains("TRUNCATE"),
+                "migration {} throws away a table",
+                version
+            );
+        }
+    }
+}
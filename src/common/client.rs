@@ -0,0 +1,106 @@
+use std::net::SocketAddr;
+
+use axum::{
+    extract::{ConnectInfo, FromRequestParts},
+    http::{header::USER_AGENT, request::Parts},
+};
+
+/// Who is on the other end of the request, as far as we can tell
+pub struct ClientInfo {
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    /// Browser clients send `X-Session-Mode: cookie` to get the refresh token as an
+    /// HttpOnly cookie instead of in the response body
+    pub session_cookies: bool,
+}
+
+/// How many reverse proxies in front of us append to X-Forwarded-For, `true` meaning one.
+/// Without any the header is ignored, clients can put anything in it
+fn trusted_proxies() -> usize {
+    match std::env::var("TRUST_FORWARDED_FOR").as_deref() {
+        Ok("true") => 1,
+        Ok(value) => value.parse().unwrap_or(0),
+        Err(_) => 0,
+    }
+}
+
+/// Each proxy appends the address it got the request from, so the client is the entry our
+/// nearest `proxies` added. Whatever is left of it came from the client and can be made up
+fn forwarded_client<'a>(
+    values: impl DoubleEndedIterator<Item = &'a str>,
+    proxies: usize,
+) -> Option<String> {
+    let entry = values
+        .rev()
+        .flat_map(|value| value.rsplit(','))
+        .nth(proxies.checked_sub(1)?)?
+        .trim();
+    (!entry.is_empty()).then(|| entry.to_owned())
+}
+
+impl<S: std::marker::Sync> FromRequestParts<S> for ClientInfo {
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        let user_agent = parts
+            .headers
+            .get(USER_AGENT)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.chars().take(255).collect());
+
+        let forwarded_for = forwarded_client(
+            parts
+                .headers
+                .get_all("X-Forwarded-For")
+                .iter()
+                .filter_map(|value| value.to_str().ok()),
+            trusted_proxies(),
+        );
+        let ip_address = forwarded_for.or_else(|| {
+            parts
+                .extensions
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(addr)| addr.ip().to_string())
+        });
+
+        let session_cookies = parts
+            .headers
+            .get("X-Session-Mode")
+            .is_some_and(|value| value == "cookie");
+
+        Ok(ClientInfo {
+            user_agent,
+            ip_address,
+            session_cookies,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn client_is_what_the_nearest_proxy_saw() {
+        let header = ["203.0.113.7, 198.51.100.1, 192.0.2.10"];
+        assert_eq!(
+            forwarded_client(header.into_iter(), 1).as_deref(),
+            Some("192.0.2.10")
+        );
+        assert_eq!(
+            forwarded_client(header.into_iter(), 2).as_deref(),
+            Some("198.51.100.1")
+        );
+        assert_eq!(forwarded_client(header.into_iter(), 0), None);
+        assert_eq!(forwarded_client(header.into_iter(), 4), None);
+    }
+
+    #[test]
+    fn repeated_headers_are_one_list() {
+        let headers = ["203.0.113.7", "192.0.2.10"];
+        assert_eq!(
+            forwarded_client(headers.into_iter(), 1).as_deref(),
+            Some("192.0.2.10")
+        );
+    }
+}
@@ -0,0 +1,84 @@
+use std::fmt;
+
+use axum::http::{HeaderMap, HeaderName, HeaderValue};
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+pub const CONTENT_DIGEST: HeaderName = HeaderName::from_static("content-digest");
+pub const DIGEST: HeaderName = HeaderName::from_static("digest");
+
+#[derive(Debug)]
+pub struct ChecksumMismatch {
+    pub expected: String,
+    pub checksum: String,
+}
+
+impl fmt::Display for ChecksumMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Expected sha-256 {} but received {}",
+            self.expected, self.checksum
+        )
+    }
+}
+
+impl std::error::Error for ChecksumMismatch {}
+
+#[derive(Debug)]
+pub struct InvalidDigest;
+
+fn decode_sha256(value: &str) -> Result<String, InvalidDigest> {
+    let raw = STANDARD.decode(value.trim()).map_err(|_| InvalidDigest)?;
+    if raw.len() != 32 {
+        return Err(InvalidDigest);
+    }
+    Ok(hex::encode(raw))
+}
+
+/// Hex encoded SHA-256 the client says it sent, taken from `Content-Digest` (RFC 9530)
+/// or the older `Digest` (RFC 3230). For vault uploads the digest covers the vault file
+/// itself, not the multipart envelope around it. Errors if the sha-256 entry is malformed
+pub fn expected_sha256(headers: &HeaderMap) -> Result<Option<String>, InvalidDigest> {
+    let entries = |name: &HeaderName| {
+        headers
+            .get_all(name)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .filter_map(|entry| entry.trim().split_once('='))
+            .filter(|(algorithm, _)| algorithm.trim().eq_ignore_ascii_case("sha-256"))
+            .map(|(_, value)| value.trim().to_owned())
+            .collect::<Vec<_>>()
+    };
+
+    if let Some(value) = entries(&CONTENT_DIGEST).first() {
+        // Structured field byte sequence, wrapped in colons
+        let value = value
+            .strip_prefix(':')
+            .and_then(|value| value.strip_suffix(':'))
+            .ok_or(InvalidDigest)?;
+        return decode_sha256(value).map(Some);
+    }
+    if let Some(value) = entries(&DIGEST).first() {
+        return decode_sha256(value).map(Some);
+    }
+    Ok(None)
+}
+
+/// `Content-Digest` and `Digest` headers for a hex encoded SHA-256
+pub fn sha256_headers(checksum: &str) -> Vec<(HeaderName, HeaderValue)> {
+    let Ok(raw) = hex::decode(checksum) else {
+        return Vec::new();
+    };
+    let encoded = STANDARD.encode(raw);
+    vec![
+        (
+            CONTENT_DIGEST,
+            HeaderValue::from_str(&format!("sha-256=:{}:", encoded)).unwrap(),
+        ),
+        (
+            DIGEST,
+            HeaderValue::from_str(&format!("SHA-256={}", encoded)).unwrap(),
+        ),
+    ]
+}
@@ -7,6 +7,7 @@ use axum::{
 use serde::{Deserialize, Serialize};
 use utoipa::ToSchema;
 
+#[derive(Debug)]
 pub struct AppError(anyhow::Error);
 impl IntoResponse for AppError {
     fn into_response(self) -> axum::response::Response {
@@ -53,9 +54,31 @@ pub enum ErrorTypes {
     InvalidResetToken,
     UserAlreadyExists,
     RefreshTokenExpired,
+    RefreshTokenReused,
     InvalidCreds,
     NoAuthHeader,
     FileNotExists,
+    VersionNotExists,
+    VaultConflict,
+    VaultNotExists,
+    VaultAlreadyExists,
+    UploadSessionNotExists,
+    QuotaExceeded,
+    ChecksumMismatch,
+    SessionNotExists,
+    MfaTokenExpired,
+    InvalidTwoFactorCode,
+    TwoFactorAlreadyEnabled,
+    TwoFactorNotEnabled,
+    WebauthnRejected,
+    CredentialNotExists,
+    EmailNotVerified,
+    EmailAlreadyVerified,
+    InvalidVerificationToken,
+    TooManyRequests,
+    AccountPendingDeletion,
+    AccountLocked,
+    CsrfTokenMismatch,
 }
 
 impl ErrorTypes {
@@ -69,9 +92,31 @@ impl ErrorTypes {
             ErrorTypes::InvalidResetToken => "invalid_reset_token",
             ErrorTypes::UserAlreadyExists => "user_already_exists",
             ErrorTypes::RefreshTokenExpired => "refresh_token_expired",
+            ErrorTypes::RefreshTokenReused => "refresh_token_reused",
             ErrorTypes::InvalidCreds => "invalid_creds",
             ErrorTypes::NoAuthHeader => "no_auth_header",
             ErrorTypes::FileNotExists => "file_not_exists",
+            ErrorTypes::VersionNotExists => "version_not_exists",
+            ErrorTypes::VaultConflict => "vault_conflict",
+            ErrorTypes::VaultNotExists => "vault_not_exists",
+            ErrorTypes::VaultAlreadyExists => "vault_already_exists",
+            ErrorTypes::UploadSessionNotExists => "upload_session_not_exists",
+            ErrorTypes::QuotaExceeded => "quota_exceeded",
+            ErrorTypes::ChecksumMismatch => "checksum_mismatch",
+            ErrorTypes::SessionNotExists => "session_not_exists",
+            ErrorTypes::MfaTokenExpired => "mfa_token_expired",
+            ErrorTypes::InvalidTwoFactorCode => "invalid_two_factor_code",
+            ErrorTypes::TwoFactorAlreadyEnabled => "two_factor_already_enabled",
+            ErrorTypes::TwoFactorNotEnabled => "two_factor_not_enabled",
+            ErrorTypes::WebauthnRejected => "webauthn_rejected",
+            ErrorTypes::CredentialNotExists => "credential_not_exists",
+            ErrorTypes::EmailNotVerified => "email_not_verified",
+            ErrorTypes::EmailAlreadyVerified => "email_already_verified",
+            ErrorTypes::InvalidVerificationToken => "invalid_verification_token",
+            ErrorTypes::TooManyRequests => "too_many_requests",
+            ErrorTypes::AccountPendingDeletion => "account_pending_deletion",
+            ErrorTypes::AccountLocked => "account_locked",
+            ErrorTypes::CsrfTokenMismatch => "csrf_token_mismatch",
         }
     }
 }
@@ -1,3 +1,8 @@
+pub mod client;
+pub mod digest;
 pub mod error;
+pub mod rate_limit;
 pub mod router;
 pub mod swagger;
+#[cfg(test)]
+pub mod testing;
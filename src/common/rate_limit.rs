@@ -0,0 +1,127 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{Request, State},
+    http::{header::RETRY_AFTER, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+
+use crate::{
+    common::{client::ClientInfo, error::ErrorTypes},
+    error_response,
+};
+
+// Addresses whose window is over are forgotten once this many are tracked
+const PRUNE_AT: usize = 10_000;
+
+/// Fixed window request budget per client address, kept in memory of this instance
+pub struct RateLimiter {
+    limit: u32,
+    window: Duration,
+    windows: Mutex<HashMap<String, (Instant, u32)>>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: u32, window: Duration) -> Self {
+        Self {
+            limit,
+            window,
+            windows: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// `RATE_LIMIT_REQUESTS` per `RATE_LIMIT_WINDOW` seconds, 20 a minute by default
+    pub fn from_env() -> Self {
+        let env_or = |var: &str, default: u64| {
+            std::env::var(var)
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(default)
+        };
+        Self::new(
+            env_or("RATE_LIMIT_REQUESTS", 20) as u32,
+            Duration::from_secs(env_or("RATE_LIMIT_WINDOW", 60)),
+        )
+    }
+
+    /// Counts a request from `key`. Over the budget it isn't counted, and the time until
+    /// the window starts over is returned instead
+    pub fn hit(&self, key: &str, now: Instant) -> Option<Duration> {
+        let mut windows = self.windows.lock().unwrap();
+        if windows.len() >= PRUNE_AT {
+            windows.retain(|_, (started, _)| now.duration_since(*started) < self.window);
+        }
+
+        let (started, count) = windows.entry(key.to_owned()).or_insert((now, 0));
+        if now.duration_since(*started) >= self.window {
+            *started = now;
+            *count = 0;
+        }
+        if *count >= self.limit {
+            return Some(self.window - now.duration_since(*started));
+        }
+        *count += 1;
+        None
+    }
+}
+
+/// Turns a client away once its address used up the budget. Requests we can't tell the
+/// address of go through, there is nothing to count them against
+pub async fn limit_by_ip(
+    State(limiter): State<Arc<RateLimiter>>,
+    client: ClientInfo,
+    request: Request,
+    next: Next,
+) -> Response {
+    let wait = client
+        .ip_address
+        .as_deref()
+        .and_then(|ip_address| limiter.hit(ip_address, Instant::now()));
+    if let Some(wait) = wait {
+        let retry_after = wait.as_secs_f64().ceil().max(1.0) as u64;
+        let mut resp = error_response!(
+            StatusCode::TOO_MANY_REQUESTS,
+            ErrorTypes::TooManyRequests,
+            "Too many requests, try again in {} seconds",
+            retry_after
+        );
+        resp.headers_mut().insert(RETRY_AFTER, retry_after.into());
+        return resp;
+    }
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn budget_is_per_address() {
+        let limiter = RateLimiter::new(2, Duration::from_secs(60));
+        let now = Instant::now();
+        assert_eq!(limiter.hit("192.0.2.1", now), None);
+        assert_eq!(limiter.hit("192.0.2.1", now), None);
+        assert_eq!(
+            limiter.hit("192.0.2.1", now + Duration::from_secs(15)),
+            Some(Duration::from_secs(45))
+        );
+        assert_eq!(limiter.hit("192.0.2.2", now), None);
+    }
+
+    #[test]
+    fn window_starts_over() {
+        let limiter = RateLimiter::new(1, Duration::from_secs(60));
+        let now = Instant::now();
+        assert_eq!(limiter.hit("192.0.2.1", now), None);
+        assert!(limiter.hit("192.0.2.1", now).is_some());
+        assert_eq!(
+            limiter.hit("192.0.2.1", now + Duration::from_secs(60)),
+            None
+        );
+    }
+}
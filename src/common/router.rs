@@ -1,63 +1,210 @@
-use std::time::Duration;
+use std::sync::Arc;
 
-use axum::{
-    error_handling::HandleErrorLayer, extract::DefaultBodyLimit, http::StatusCode, BoxError, Router,
-};
+use axum::{extract::DefaultBodyLimit, middleware, Router};
 use sqlx::{MySql, MySqlPool, Pool};
-use tower::{buffer::BufferLayer, limit::RateLimitLayer, ServiceBuilder};
 use tower_http::{catch_panic::CatchPanicLayer, cors::CorsLayer};
 use utoipa::OpenApi;
 use utoipa_swagger_ui::SwaggerUi;
 
 use crate::{
     common::{
-        error::{internal_server_error_handler, ErrorResponse, ErrorTypes},
+        error::internal_server_error_handler,
+        rate_limit::{self, RateLimiter},
         swagger::ApiDoc,
     },
     handlers, AppState,
 };
 
-fn auth_routes() -> Router<AppState> {
+/// What anyone can call without an access token: logging in, registering and the links from
+/// emails. Limited per client address, on top of the lockouts for wrong credentials
+fn public_routes() -> Router<AppState> {
     Router::new()
         .route("/register", axum::routing::post(handlers::auth::register))
+        .route("/prelogin", axum::routing::post(handlers::kdf::prelogin))
+        .route(
+            "/login/srp/start",
+            axum::routing::post(handlers::srp::login_start),
+        )
+        .route(
+            "/login/webauthn/start",
+            axum::routing::post(handlers::webauthn::login_start),
+        )
+        .route(
+            "/password/forgot",
+            axum::routing::post(handlers::password::forgot_password),
+        )
         .route("/login", axum::routing::post(handlers::auth::login))
+        .route("/login/2fa", axum::routing::post(handlers::auth::login_2fa))
+        .route(
+            "/login/srp/finish",
+            axum::routing::post(handlers::srp::login_finish),
+        )
+        .route(
+            "/login/webauthn/finish",
+            axum::routing::post(handlers::webauthn::login_finish),
+        )
+        .route(
+            "/password/reset",
+            axum::routing::post(handlers::password::reset_password),
+        )
+        .route(
+            "/email/verify",
+            axum::routing::post(handlers::email::verify_email),
+        )
+        .route(
+            "/account/restore",
+            axum::routing::post(handlers::account::restore_account),
+        )
+        .route(
+            "/account/email/confirm",
+            axum::routing::post(handlers::account::confirm_email_change),
+        )
+}
+
+fn auth_routes() -> Router<AppState> {
+    Router::new()
+        .route(
+            "/email/verify/resend",
+            axum::routing::post(handlers::email::resend_verification),
+        )
+        .route(
+            "/account",
+            axum::routing::delete(handlers::account::delete_account),
+        )
+        .route(
+            "/account/password",
+            axum::routing::post(handlers::account::change_password),
+        )
+        .route(
+            "/account/email",
+            axum::routing::post(handlers::account::change_email),
+        )
+        .route(
+            "/account/kdf",
+            axum::routing::post(handlers::kdf::change_kdf),
+        )
+        .route(
+            "/account/srp",
+            axum::routing::post(handlers::srp::set_verifier),
+        )
+        .route(
+            "/account/username",
+            axum::routing::post(handlers::account::change_username),
+        )
+        .route(
+            "/account/activity",
+            axum::routing::get(handlers::account::list_activity),
+        )
+        .route(
+            "/2fa/totp/enroll",
+            axum::routing::post(handlers::mfa::enroll_totp),
+        )
+        .route(
+            "/2fa/totp/confirm",
+            axum::routing::post(handlers::mfa::confirm_totp),
+        )
+        .route(
+            "/2fa/totp/disable",
+            axum::routing::post(handlers::mfa::disable_totp),
+        )
         .route(
             "/token",
-            axum::routing::get(handlers::auth::update_jwt_token),
+            axum::routing::get(handlers::auth::update_jwt_token)
+                .delete(handlers::auth::logout_cookie),
         )
         .route("/validate", axum::routing::get(handlers::auth::validate))
+        .route(
+            "/.well-known/jwks.json",
+            axum::routing::get(handlers::auth::jwks),
+        )
         .route("/logout", axum::routing::post(handlers::auth::logout))
+        .route(
+            "/webauthn/register/start",
+            axum::routing::post(handlers::webauthn::register_start),
+        )
+        .route(
+            "/webauthn/register/finish",
+            axum::routing::post(handlers::webauthn::register_finish),
+        )
+        .route(
+            "/webauthn/credentials",
+            axum::routing::get(handlers::webauthn::list_credentials),
+        )
+        .route(
+            "/webauthn/credentials/{credential_id}",
+            axum::routing::delete(handlers::webauthn::delete_credential),
+        )
+        .route(
+            "/logout-all",
+            axum::routing::post(handlers::sessions::logout_all),
+        )
+        .route(
+            "/sessions",
+            axum::routing::get(handlers::sessions::list_sessions),
+        )
+        .route(
+            "/sessions/{session_id}",
+            axum::routing::delete(handlers::sessions::delete_session),
+        )
 }
 
 fn storage_routes() -> Router<AppState> {
     Router::new()
-        .route("/download", axum::routing::get(handlers::storage::download))
-        .route("/upload", axum::routing::post(handlers::storage::upload))
+        .route(
+            "/vaults",
+            axum::routing::post(handlers::vaults::create_vault).get(handlers::vaults::list_vaults),
+        )
+        .route(
+            "/vaults/{vault_id}",
+            axum::routing::patch(handlers::vaults::rename_vault)
+                .delete(handlers::vaults::delete_vault),
+        )
+        .route(
+            "/storage/usage",
+            axum::routing::get(handlers::storage::usage),
+        )
+        .route(
+            "/vaults/{vault_id}/download",
+            axum::routing::get(handlers::storage::download),
+        )
+        .route(
+            "/vaults/{vault_id}/upload",
+            axum::routing::post(handlers::storage::upload),
+        )
+        .route(
+            "/vaults/{vault_id}/uploads",
+            axum::routing::post(handlers::uploads::create_upload),
+        )
+        .route(
+            "/vaults/{vault_id}/uploads/{upload_id}",
+            axum::routing::head(handlers::uploads::upload_offset)
+                .patch(handlers::uploads::append_upload)
+                .delete(handlers::uploads::cancel_upload),
+        )
+        .route(
+            "/vaults/{vault_id}/versions",
+            axum::routing::get(handlers::storage::list_versions),
+        )
+        .route(
+            "/vaults/{vault_id}/versions/{version}/restore",
+            axum::routing::post(handlers::storage::restore_version),
+        )
 }
 
 pub fn get_router(state: AppState) -> Router {
     Router::new()
+        .merge(public_routes().route_layer(middleware::from_fn_with_state(
+            Arc::new(RateLimiter::from_env()),
+            rate_limit::limit_by_ip,
+        )))
         .merge(auth_routes())
-        .merge(storage_routes())
+        .merge(storage_routes().route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            handlers::email::require_verified_email,
+        )))
         .layer(DefaultBodyLimit::max(1 * 1024 * 1024 * 1024 * 2))
         .layer(CorsLayer::permissive()) // Для того чтоб CORS мозг не ебал
         .layer(CatchPanicLayer::custom(internal_server_error_handler))
-        .layer(
-            ServiceBuilder::new()
-                .layer(HandleErrorLayer::new(|err: BoxError| async move {
-                    // So compiler wont complain about some Infallable Trait shit
-                    eprintln!("{}", err);
-                    (
-                        StatusCode::INTERNAL_SERVER_ERROR,
-                        axum::Json(ErrorResponse::new(
-                            ErrorTypes::InternalError,
-                            "Internal error occured",
-                        )),
-                    )
-                }))
-                .layer(BufferLayer::new(1024)) // Means it can process 1024 messages before backpressure is applied TODO: Adjust
-                .layer(RateLimitLayer::new(5, Duration::from_secs(1))), // Rate limti does not impl Clone, so we need to use BufferLayer TODO: Adjust
-        )
         .merge(SwaggerUi::new("/docs").url("/api-doc/openapi.json", ApiDoc::openapi()))
         .with_state(state)
 }
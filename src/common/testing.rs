@@ -0,0 +1,64 @@
+//! Helpers for tests that need a database. `#[sqlx::test]` gives every test a fresh one on
+//! the server from `DATABASE_URL`, these migrate it and fill in the rest
+
+use axum::{body::to_bytes, response::Response};
+use sqlx::MySqlPool;
+
+use crate::{
+    bootstrap,
+    common::client::ClientInfo,
+    controllers,
+    crypt::{
+        self,
+        token::{AuthHeader, Claims},
+    },
+};
+
+/// Migrates the schema and creates a user logging in with `password`
+pub async fn setup(pool: &MySqlPool, email: &str, password: &str) -> u32 {
+    bootstrap::migrate(pool).await.unwrap();
+    // Tests share the process, whoever comes first loads the keys
+    for var in ["SECRET_WORD_SALT", "SECRET_WORD_MFA"] {
+        if std::env::var(var).is_err() {
+            std::env::set_var(var, "tests");
+        }
+    }
+    let _ = crypt::keys::load();
+    controllers::users::create_user(
+        pool,
+        email,
+        email,
+        &crypt::password::hash_password(password),
+    )
+    .await
+    .unwrap()
+}
+
+pub fn client() -> ClientInfo {
+    ClientInfo {
+        user_agent: Some("tests".to_owned()),
+        ip_address: Some("192.0.2.1".to_owned()),
+        session_cookies: false,
+    }
+}
+
+/// What the extractor would produce for an access token of session `sid`
+pub fn auth_header(user_id: u32, sid: &str) -> AuthHeader {
+    AuthHeader {
+        claims: Claims {
+            id: user_id,
+            sid: sid.to_owned(),
+            jti: hex::encode(rand::random::<[u8; 16]>()),
+            iss: crypt::token::issuer(),
+            aud: crypt::token::audience(),
+            iat: 0,
+            exp: 0,
+        },
+        token: String::new(),
+    }
+}
+
+pub async fn json(response: Response) -> serde_json::Value {
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    serde_json::from_slice(&body).unwrap()
+}
@@ -0,0 +1,67 @@
+use sqlx::MySqlPool;
+
+use crate::{
+    common::client::ClientInfo,
+    database::{self, activity::ActivityEntry},
+};
+
+const LIST_LIMIT: u32 = 100;
+
+/// Account changes users can look back at
+pub enum Activity {
+    PasswordChanged,
+    PasswordReset,
+    EmailChangeRequested,
+    EmailChanged,
+    UsernameChanged,
+    KdfChanged,
+    DeletionScheduled,
+    DeletionCancelled,
+}
+
+impl Activity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Activity::PasswordChanged => "password_changed",
+            Activity::PasswordReset => "password_reset",
+            Activity::EmailChangeRequested => "email_change_requested",
+            Activity::EmailChanged => "email_changed",
+            Activity::UsernameChanged => "username_changed",
+            Activity::KdfChanged => "kdf_changed",
+            Activity::DeletionScheduled => "deletion_scheduled",
+            Activity::DeletionCancelled => "deletion_cancelled",
+        }
+    }
+}
+
+/// The change itself already happened, so failing to log it only gets logged
+pub async fn record(
+    state: &MySqlPool,
+    user_id: u32,
+    activity: Activity,
+    detail: Option<&str>,
+    client: &ClientInfo,
+) {
+    if let Err(why) = database::activity::add_entry(
+        state,
+        user_id,
+        activity.as_str(),
+        detail,
+        client.user_agent.as_deref(),
+        client.ip_address.as_deref(),
+    )
+    .await
+    {
+        tracing::error!(
+            "Could not record {} for user {}: {}",
+            activity.as_str(),
+            user_id,
+            why
+        );
+    }
+}
+
+/// Most recent entries, newest first
+pub async fn list(state: &MySqlPool, user_id: u32) -> anyhow::Result<Vec<ActivityEntry>> {
+    database::activity::list_entries(state, user_id, LIST_LIMIT).await
+}
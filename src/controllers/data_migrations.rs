@@ -0,0 +1,11 @@
+use sqlx::MySqlPool;
+
+use crate::database;
+
+pub async fn is_done(state: &MySqlPool, name: &str) -> anyhow::Result<bool> {
+    database::data_migrations::is_done(state, name).await
+}
+
+pub async fn mark_done(state: &MySqlPool, name: &str) -> anyhow::Result<()> {
+    database::data_migrations::mark_done(state, name).await
+}
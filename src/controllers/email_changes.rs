@@ -0,0 +1,107 @@
+use chrono::{Duration, Utc};
+use sqlx::MySqlPool;
+
+use crate::{crypt, database, mail::Email};
+
+const CHANGE_TTL: Duration = Duration::days(1);
+
+pub enum Confirmation {
+    /// Token is unknown or expired
+    Invalid,
+    /// The other address still has to confirm
+    Pending,
+    /// Someone registered the new address in the meantime
+    Taken,
+    Changed {
+        user_id: u32,
+        new_email: String,
+    },
+}
+
+fn confirm_link(token: &str) -> String {
+    // `EMAIL_CHANGE_URL` is the client page that sends the token back
+    match std::env::var("EMAIL_CHANGE_URL") {
+        Ok(url) => format!("{}?token={}", url, token),
+        Err(_) => token.to_owned(),
+    }
+}
+
+/// Starts moving the account to `new_email`. Both the current and the new address get
+/// a token and the change only happens once both are confirmed.
+/// Returns `None` if the new address already belongs to someone
+pub async fn start_change(
+    state: &MySqlPool,
+    user_id: u32,
+    new_email: &str,
+) -> anyhow::Result<Option<[Email; 2]>> {
+    if database::users::find_by_email(state, new_email)
+        .await?
+        .is_some()
+    {
+        return Ok(None);
+    }
+    let old_email = database::users::get_email(state, user_id).await?;
+
+    let old_token = crypt::token::make_refresh_token();
+    let new_token = crypt::token::make_refresh_token();
+    database::email_changes::create_change(
+        state,
+        user_id,
+        new_email,
+        &crypt::token::hash_refresh_token(&old_token),
+        &crypt::token::hash_refresh_token(&new_token),
+        Utc::now() + CHANGE_TTL,
+    )
+    .await?;
+
+    let hours = CHANGE_TTL.num_hours();
+    Ok(Some([
+        Email {
+            to: old_email,
+            subject: "Email change requested".to_owned(),
+            body: format!(
+                "Somebody asked to move your account to {}. If it was you, confirm within {} hours:\n\n{}\n\nIf it wasn't, change your password, nothing happens without this confirmation.",
+                new_email,
+                hours,
+                confirm_link(&old_token)
+            ),
+        },
+        Email {
+            to: new_email.to_owned(),
+            subject: "Confirm your new email".to_owned(),
+            body: format!(
+                "Confirm this address for your account within {} hours:\n\n{}",
+                hours,
+                confirm_link(&new_token)
+            ),
+        },
+    ]))
+}
+
+/// Confirms one side of a pending change, applying it once both sides are in
+pub async fn confirm(state: &MySqlPool, token: &str) -> anyhow::Result<Confirmation> {
+    let token_hash = crypt::token::hash_refresh_token(token);
+    let Some(change) = database::email_changes::confirm_side(state, &token_hash).await? else {
+        return Ok(Confirmation::Invalid);
+    };
+    if change.old_confirmed_at.is_none() || change.new_confirmed_at.is_none() {
+        return Ok(Confirmation::Pending);
+    }
+
+    let user_id = change.user_id as u32;
+    if database::users::find_by_email(state, &change.new_email)
+        .await?
+        .is_some_and(|owner| owner != user_id)
+    {
+        return Ok(Confirmation::Taken);
+    }
+    database::email_changes::apply_change(state, &change).await?;
+    Ok(Confirmation::Changed {
+        user_id,
+        new_email: change.new_email,
+    })
+}
+
+pub async fn delete_expired(state: &MySqlPool) -> anyhow::Result<u64> {
+    database::email_changes::delete_expired(state).await
+}
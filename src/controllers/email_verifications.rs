@@ -0,0 +1,96 @@
+use chrono::{DateTime, Duration, Utc};
+use sqlx::MySqlPool;
+
+use crate::{crypt, database, mail::Email};
+
+const VERIFICATION_TTL: Duration = Duration::days(1);
+
+/// What an unverified user can't do, `EMAIL_VERIFICATION`: `off` (default), `storage` or `login`
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Enforcement {
+    Off,
+    /// Can log in and manage the account, but not touch vaults
+    Storage,
+    /// Can't get tokens at all
+    Login,
+}
+
+pub fn enforcement() -> Enforcement {
+    match std::env::var("EMAIL_VERIFICATION").as_deref() {
+        Ok("storage") => Enforcement::Storage,
+        Ok("login") => Enforcement::Login,
+        _ => Enforcement::Off,
+    }
+}
+
+/// Minimal time between two verification emails for one user, `EMAIL_RESEND_INTERVAL` seconds
+pub fn resend_interval() -> Duration {
+    std::env::var("EMAIL_RESEND_INTERVAL")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .map(Duration::seconds)
+        .unwrap_or(Duration::minutes(1))
+}
+
+pub async fn is_verified(state: &MySqlPool, user_id: u32) -> anyhow::Result<bool> {
+    database::users::is_email_verified(state, user_id).await
+}
+
+/// When the user may ask for another email, `None` if they may right away
+pub async fn resend_allowed_at(
+    state: &MySqlPool,
+    user_id: u32,
+) -> anyhow::Result<Option<DateTime<Utc>>> {
+    let allowed_at = database::email_verifications::last_sent_at(state, user_id)
+        .await?
+        .map(|sent_at| sent_at + resend_interval());
+    Ok(allowed_at.filter(|allowed_at| *allowed_at > Utc::now()))
+}
+
+/// Creates a token proving the user owns `email` and returns the email carrying it
+pub async fn start_verification(
+    state: &MySqlPool,
+    user_id: u32,
+    email: &str,
+) -> anyhow::Result<Email> {
+    let token = crypt::token::make_refresh_token();
+    database::email_verifications::create_verification(
+        state,
+        user_id,
+        email,
+        &crypt::token::hash_refresh_token(&token),
+        Utc::now() + VERIFICATION_TTL,
+    )
+    .await?;
+
+    // `EMAIL_VERIFY_URL` is the client page that sends the token back
+    let link = match std::env::var("EMAIL_VERIFY_URL") {
+        Ok(url) => format!("{}?token={}", url, token),
+        Err(_) => token,
+    };
+    Ok(Email {
+        to: email.to_owned(),
+        subject: "Confirm your email".to_owned(),
+        body: format!(
+            "Confirm this address for your account within {} hours:\n\n{}\n\nIf you didn't sign up, just ignore this email.",
+            VERIFICATION_TTL.num_hours(),
+            link
+        ),
+    })
+}
+
+/// Returns false if the token is unknown, expired, used, or the user changed their email since
+pub async fn verify(state: &MySqlPool, token: &str) -> anyhow::Result<bool> {
+    let token_hash = crypt::token::hash_refresh_token(token);
+    let Some(verification) =
+        database::email_verifications::take_verification(state, &token_hash).await?
+    else {
+        return Ok(false);
+    };
+    database::users::set_email_verified(state, verification.user_id as u32, &verification.email)
+        .await
+}
+
+pub async fn delete_expired(state: &MySqlPool) -> anyhow::Result<u64> {
+    database::email_verifications::delete_expired(state).await
+}
@@ -0,0 +1,109 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::MySqlPool;
+
+use crate::{
+    crypt,
+    database::{self, users::KdfSettings},
+};
+
+const ARGON2ID: &str = "argon2id";
+const PBKDF2_SHA256: &str = "pbkdf2-sha256";
+
+/// KDF settings as clients see them. `memory` is in KiB and, like `parallelism`,
+/// only used by argon2id. `salt` is base64
+#[derive(Serialize, Deserialize)]
+pub struct KdfParams {
+    pub algorithm: String,
+    pub iterations: u32,
+    #[serde(default)]
+    pub memory: Option<u32>,
+    #[serde(default)]
+    pub parallelism: Option<u32>,
+    pub salt: String,
+}
+
+impl KdfParams {
+    /// What accounts get unless they pick something else
+    pub fn default_for(email: &str) -> Self {
+        Self {
+            algorithm: ARGON2ID.to_owned(),
+            iterations: 3,
+            memory: Some(64 * 1024),
+            parallelism: Some(4),
+            salt: STANDARD.encode(default_salt(email)),
+        }
+    }
+
+    /// Rejects settings too weak to protect a vault, or too heavy for clients to run
+    pub fn to_settings(&self) -> Option<KdfSettings> {
+        let salt = STANDARD.decode(&self.salt).ok()?;
+        if !(16..=64).contains(&salt.len()) {
+            return None;
+        }
+        let valid = match self.algorithm.as_str() {
+            ARGON2ID => {
+                (1..=10).contains(&self.iterations)
+                    && self
+                        .memory
+                        .is_some_and(|memory| (16 * 1024..=1024 * 1024).contains(&memory))
+                    && self
+                        .parallelism
+                        .is_some_and(|parallelism| (1..=16).contains(&parallelism))
+            }
+            PBKDF2_SHA256 => {
+                (600_000..=10_000_000).contains(&self.iterations)
+                    && self.memory.is_none()
+                    && self.parallelism.is_none()
+            }
+            _ => false,
+        };
+        valid.then(|| KdfSettings {
+            kdf_algorithm: self.algorithm.clone(),
+            kdf_iterations: self.iterations,
+            kdf_memory: self.memory,
+            kdf_parallelism: self.parallelism,
+            kdf_salt: Some(salt),
+        })
+    }
+}
+
+/// Same for an email every time, so unknown emails can get stable made up parameters and a
+/// client that asked before registering ends up with the salt it already used
+fn default_salt(email: &str) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(&crypt::keys::secrets().salt);
+    hasher.update(b"kdf-salt");
+    hasher.update(email.to_lowercase().as_bytes());
+    hasher.finalize()[..16].to_vec()
+}
+
+/// Parameters for the account behind `email`, or believable fake ones if there is none.
+/// Only reads, anyone can ask
+pub async fn for_email(state: &MySqlPool, email: &str) -> anyhow::Result<KdfParams> {
+    let Some(settings) = database::users::get_kdf_by_email(state, email).await? else {
+        return Ok(KdfParams::default_for(email));
+    };
+    Ok(KdfParams {
+        algorithm: settings.kdf_algorithm,
+        iterations: settings.kdf_iterations,
+        memory: settings.kdf_memory,
+        parallelism: settings.kdf_parallelism,
+        salt: STANDARD.encode(settings.kdf_salt.unwrap_or_else(|| default_salt(email))),
+    })
+}
+
+/// Accounts from before KDF settings get the salt prelogin showed them so far, pinned so it
+/// survives an email change. Run once at startup, returns how many accounts got one
+pub async fn backfill_salts(state: &MySqlPool) -> anyhow::Result<usize> {
+    let users = database::users::without_kdf_salt(state).await?;
+    for (user_id, email) in &users {
+        database::users::init_kdf_salt(state, *user_id, &default_salt(email)).await?;
+    }
+    Ok(users.len())
+}
+
+pub async fn set(state: &MySqlPool, user_id: u32, settings: &KdfSettings) -> anyhow::Result<()> {
+    database::users::set_kdf(state, user_id, settings).await
+}
@@ -0,0 +1,84 @@
+use chrono::{DateTime, Duration, Utc};
+use sqlx::MySqlPool;
+
+use crate::database;
+
+/// What failed logins are counted against
+#[derive(Clone, Copy)]
+pub enum Scope {
+    Account,
+    Ip,
+}
+
+impl Scope {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Scope::Account => "account",
+            Scope::Ip => "ip",
+        }
+    }
+
+    /// Failures allowed before the first lockout. One address may try several accounts,
+    /// so it gets more room: `LOGIN_FREE_ATTEMPTS` and `LOGIN_IP_FREE_ATTEMPTS`
+    fn free_attempts(&self) -> u32 {
+        let (var, default) = match self {
+            Scope::Account => ("LOGIN_FREE_ATTEMPTS", 5),
+            Scope::Ip => ("LOGIN_IP_FREE_ATTEMPTS", 20),
+        };
+        env_or(var, default)
+    }
+}
+
+fn env_or<T: std::str::FromStr>(var: &str, default: T) -> T {
+    std::env::var(var)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+/// Failures are forgotten after this long without a new one, `LOGIN_FAILURE_WINDOW` seconds
+fn failure_window() -> i64 {
+    env_or("LOGIN_FAILURE_WINDOW", 60 * 60)
+}
+
+/// Lockouts start at a second and double with every failure up to `LOGIN_MAX_LOCKOUT` seconds
+fn lockout_for(excess: u32) -> Duration {
+    let max = env_or("LOGIN_MAX_LOCKOUT", 15 * 60);
+    Duration::seconds(2_i64.saturating_pow(excess.saturating_sub(1)).min(max))
+}
+
+pub async fn locked_until(
+    state: &MySqlPool,
+    scope: Scope,
+    subject: &str,
+) -> anyhow::Result<Option<DateTime<Utc>>> {
+    database::login_failures::get_locked_until(state, scope.as_str(), subject).await
+}
+
+pub async fn record_failure(state: &MySqlPool, scope: Scope, subject: &str) -> anyhow::Result<()> {
+    let failures =
+        database::login_failures::add_failure(state, scope.as_str(), subject, failure_window())
+            .await?;
+    let free = scope.free_attempts();
+    if failures > free {
+        let locked_until = Utc::now() + lockout_for(failures - free);
+        database::login_failures::set_locked_until(state, scope.as_str(), subject, locked_until)
+            .await?;
+        tracing::warn!(
+            "Login locked for {} {} until {} after {} failures",
+            scope.as_str(),
+            subject,
+            locked_until,
+            failures
+        );
+    }
+    Ok(())
+}
+
+pub async fn clear(state: &MySqlPool, scope: Scope, subject: &str) -> anyhow::Result<()> {
+    database::login_failures::clear(state, scope.as_str(), subject).await
+}
+
+pub async fn delete_stale(state: &MySqlPool) -> anyhow::Result<u64> {
+    database::login_failures::delete_stale(state, failure_window()).await
+}
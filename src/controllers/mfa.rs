@@ -0,0 +1,151 @@
+use chrono::Utc;
+use serde::Serialize;
+use sqlx::MySqlPool;
+
+use crate::{
+    crypt::{self, encryption, token},
+    database,
+};
+
+const RECOVERY_CODES: usize = 10;
+/// Wrong codes one MFA token takes before the password has to be entered again
+const MFA_TOKEN_ATTEMPTS: u32 = 5;
+
+#[derive(Serialize)]
+pub struct TotpEnrollment {
+    pub secret: String,
+    pub otpauth_url: String,
+}
+
+/// A pending secret only counts while confirming it, logins need a confirmed one
+async fn totp_secret(
+    state: &MySqlPool,
+    user_id: u32,
+    confirmed: bool,
+) -> anyhow::Result<Option<String>> {
+    let totp = database::mfa::get_totp(state, user_id).await?;
+    if confirmed && totp.totp_enabled_at.is_none() {
+        return Ok(None);
+    }
+    match (totp.totp_secret, totp.totp_nonce) {
+        (Some(secret), Some(nonce)) => Ok(Some(encryption::aes_decrypt_text(&secret, &nonce)?)),
+        _ => Ok(None),
+    }
+}
+
+pub async fn is_enabled(state: &MySqlPool, user_id: u32) -> anyhow::Result<bool> {
+    let totp = database::mfa::get_totp(state, user_id).await?;
+    Ok(totp.totp_enabled_at.is_some())
+}
+
+/// Generates a secret that stays pending until a code from it is confirmed.
+/// Returns `None` if TOTP is already enabled
+pub async fn start_enrollment(
+    state: &MySqlPool,
+    user_id: u32,
+) -> anyhow::Result<Option<TotpEnrollment>> {
+    let secret = crypt::totp::generate_secret();
+    let (encrypted, nonce) = encryption::aes_encrypt_text(&secret)?;
+    if !database::mfa::set_totp_secret(state, user_id, &encrypted, &nonce).await? {
+        return Ok(None);
+    }
+
+    let email = database::users::get_email(state, user_id).await?;
+    let otpauth_url = crypt::totp::otpauth_url(&secret, &email)?;
+    Ok(Some(TotpEnrollment {
+        secret,
+        otpauth_url,
+    }))
+}
+
+async fn verify_totp(
+    state: &MySqlPool,
+    user_id: u32,
+    code: &str,
+    confirmed: bool,
+) -> anyhow::Result<bool> {
+    let Some(secret) = totp_secret(state, user_id, confirmed).await? else {
+        return Ok(false);
+    };
+    match crypt::totp::verify_code(&secret, code)? {
+        Some(step) => database::mfa::use_totp_step(state, user_id, step).await,
+        None => Ok(false),
+    }
+}
+
+/// Enables TOTP if `code` matches the pending secret and returns fresh recovery codes.
+/// They are only ever shown this once
+pub async fn confirm_enrollment(
+    state: &MySqlPool,
+    user_id: u32,
+    code: &str,
+) -> anyhow::Result<Option<Vec<String>>> {
+    if !verify_totp(state, user_id, code, false).await? {
+        return Ok(None);
+    }
+    database::mfa::enable_totp(state, user_id).await?;
+
+    let codes: Vec<String> = (0..RECOVERY_CODES)
+        .map(|_| {
+            let raw = hex::encode(rand::random::<[u8; 6]>());
+            format!("{}-{}-{}", &raw[..4], &raw[4..8], &raw[8..])
+        })
+        .collect();
+    let hashes: Vec<String> = codes
+        .iter()
+        .map(|code| crypt::password::hash_password(code))
+        .collect();
+    database::mfa::replace_recovery_codes(state, user_id, &hashes).await?;
+    Ok(Some(codes))
+}
+
+/// Accepts either a current TOTP code or an unused recovery code, which is used up
+pub async fn verify(state: &MySqlPool, user_id: u32, code: &str) -> anyhow::Result<bool> {
+    let code = code.trim();
+    if code.len() == 6 && code.chars().all(|c| c.is_ascii_digit()) {
+        return verify_totp(state, user_id, code, true).await;
+    }
+
+    let code = code.to_ascii_lowercase();
+    for recovery_code in database::mfa::unused_recovery_codes(state, user_id).await? {
+        if crypt::password::verify_password(&code, &recovery_code.code_hash).is_ok() {
+            return database::mfa::use_recovery_code(state, recovery_code.id).await;
+        }
+    }
+    Ok(false)
+}
+
+pub async fn disable(state: &MySqlPool, user_id: u32) -> anyhow::Result<()> {
+    database::mfa::disable_totp(state, user_id).await
+}
+
+/// Hands out the token for the second login step, it's good for one login
+pub async fn issue_token(
+    state: &MySqlPool,
+    user_id: u32,
+    device_name: Option<String>,
+) -> anyhow::Result<String> {
+    let jti = hex::encode(rand::random::<[u8; 16]>());
+    let expires_at = Utc::now() + token::MFA_TOKEN_TTL;
+    database::mfa::create_mfa_token(state, &jti, user_id, expires_at).await?;
+    Ok(token::make_mfa_token(
+        user_id,
+        &jti,
+        expires_at,
+        device_name,
+    ))
+}
+
+/// Counts a go at the second factor against the token, false once it has none left
+pub async fn count_attempt(state: &MySqlPool, jti: &str) -> anyhow::Result<bool> {
+    database::mfa::count_mfa_attempt(state, jti, MFA_TOKEN_ATTEMPTS).await
+}
+
+/// Uses the token up after a successful second factor, false if somebody else already did
+pub async fn redeem_token(state: &MySqlPool, jti: &str) -> anyhow::Result<bool> {
+    database::mfa::use_mfa_token(state, jti).await
+}
+
+pub async fn delete_expired_tokens(state: &MySqlPool) -> anyhow::Result<u64> {
+    database::mfa::delete_expired_mfa_tokens(state).await
+}
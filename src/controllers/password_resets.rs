@@ -0,0 +1,66 @@
+use chrono::Utc;
+use sqlx::MySqlPool;
+
+use crate::{
+    controllers,
+    crypt::{self, token::RESET_TOKEN_TTL},
+    database,
+    mail::Email,
+};
+
+/// Creates a reset token for the account behind `email` and returns the email carrying it.
+/// Unknown addresses give `None` and return sooner, the caller mustn't let the client wait
+/// for either
+pub async fn request_reset(state: &MySqlPool, email: &str) -> anyhow::Result<Option<Email>> {
+    let Some(user_id) = database::users::find_by_email(state, email).await? else {
+        return Ok(None);
+    };
+
+    let token = crypt::token::make_refresh_token();
+    database::password_resets::create_reset(
+        state,
+        user_id,
+        &crypt::token::hash_refresh_token(&token),
+        Utc::now() + RESET_TOKEN_TTL,
+    )
+    .await?;
+
+    // `PASSWORD_RESET_URL` is the client page that finishes the reset
+    let link = match std::env::var("PASSWORD_RESET_URL") {
+        Ok(url) => format!("{}?token={}", url, token),
+        Err(_) => token,
+    };
+    Ok(Some(Email {
+        to: email.to_owned(),
+        subject: "Password reset".to_owned(),
+        body: format!(
+            "Somebody asked to reset the password of your account. If it was you, use this within {} minutes:\n\n{}\n\nOtherwise just ignore this email.",
+            RESET_TOKEN_TTL.num_minutes(),
+            link
+        ),
+    }))
+}
+
+/// Returns whose password was reset, `None` if the token is unknown, expired or already used.
+/// On success every session of the user is signed out
+pub async fn reset_password(
+    state: &MySqlPool,
+    token: &str,
+    password: &str,
+) -> anyhow::Result<Option<u32>> {
+    let token_hash = crypt::token::hash_refresh_token(token);
+    let Some(user_id) = database::password_resets::take_reset(state, &token_hash).await? else {
+        return Ok(None);
+    };
+
+    let password_hash = crypt::password::hash_password(password);
+    database::users::set_password_hash(state, user_id, &password_hash).await?;
+    // Verifier was derived from the old password
+    database::srp::delete_verifier(state, user_id).await?;
+    controllers::tokens::delete_user_tokens(state, user_id).await?;
+    Ok(Some(user_id))
+}
+
+pub async fn delete_expired(state: &MySqlPool) -> anyhow::Result<u64> {
+    database::password_resets::delete_expired(state).await
+}
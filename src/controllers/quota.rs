@@ -0,0 +1,67 @@
+use std::fmt;
+
+use futures::StreamExt;
+use serde::Serialize;
+use sqlx::MySqlPool;
+
+use crate::{database, store::ByteStream};
+
+#[derive(Debug)]
+pub struct QuotaExceeded;
+
+impl fmt::Display for QuotaExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Storage quota exceeded")
+    }
+}
+
+impl std::error::Error for QuotaExceeded {}
+
+/// Quota for users without their own, `DEFAULT_STORAGE_QUOTA` in bytes
+pub fn default_quota() -> u64 {
+    std::env::var("DEFAULT_STORAGE_QUOTA")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(1024 * 1024 * 1024)
+}
+
+#[derive(Serialize)]
+pub struct StorageUsage {
+    pub used: u64,
+    /// Reserved by resumable uploads that haven't finished yet
+    pub pending: u64,
+    pub quota: u64,
+}
+
+impl StorageUsage {
+    pub fn remaining(&self) -> u64 {
+        self.quota.saturating_sub(self.used + self.pending)
+    }
+}
+
+pub async fn usage(state: &MySqlPool, user_id: u32) -> anyhow::Result<StorageUsage> {
+    let used = database::versions::user_usage(state, user_id).await?;
+    let pending = database::uploads::pending_bytes(state, user_id).await?;
+    let quota = database::users::get_storage_quota(state, user_id)
+        .await?
+        .unwrap_or_else(default_quota);
+    Ok(StorageUsage {
+        used,
+        pending,
+        quota,
+    })
+}
+
+/// Fails the stream with [`QuotaExceeded`] as soon as more than `allowed` bytes went through
+pub fn limit(body: ByteStream<'_>, allowed: u64) -> ByteStream<'_> {
+    let mut total: u64 = 0;
+    body.map(move |chunk| {
+        let chunk = chunk?;
+        total += chunk.len() as u64;
+        if total > allowed {
+            return Err(QuotaExceeded.into());
+        }
+        Ok(chunk)
+    })
+    .boxed()
+}
@@ -0,0 +1,117 @@
+use chrono::{Duration, Utc};
+use serde::Serialize;
+use sqlx::MySqlPool;
+use subtle::ConstantTimeEq;
+
+use crate::{
+    crypt::srp::{self, N_LEN},
+    database,
+};
+
+const SESSION_TTL: Duration = Duration::minutes(2);
+
+/// First server message, everything is hex
+#[derive(Serialize)]
+pub struct LoginChallenge {
+    pub session_id: String,
+    pub salt: String,
+    pub b: String,
+}
+
+/// Salt and verifier as the client sends them, hex. Returns false if they are unusable
+pub async fn set_verifier(
+    state: &MySqlPool,
+    user_id: u32,
+    salt: &str,
+    verifier: &str,
+) -> anyhow::Result<bool> {
+    let (Ok(salt), Ok(verifier)) = (hex::decode(salt), hex::decode(verifier)) else {
+        return Ok(false);
+    };
+    if !(16..=64).contains(&salt.len())
+        || verifier.len() > N_LEN
+        || !srp::is_valid_verifier(&verifier)
+    {
+        return Ok(false);
+    }
+    database::srp::set_verifier(state, user_id, &salt, &verifier).await?;
+    Ok(true)
+}
+
+/// The verifier is derived from the password, so it has to go whenever the password changes
+pub async fn delete_verifier(state: &MySqlPool, user_id: u32) -> anyhow::Result<()> {
+    database::srp::delete_verifier(state, user_id).await
+}
+
+/// Answers the client's public value `a` (hex). Unknown users and users who didn't migrate yet
+/// get a made up challenge that can't be finished, so nobody learns which emails exist.
+/// `None` if `a` is unusable
+pub async fn start_login(
+    state: &MySqlPool,
+    user_id: Option<u32>,
+    email: &str,
+    a: &str,
+) -> anyhow::Result<Option<LoginChallenge>> {
+    let Ok(a_pub) = hex::decode(a) else {
+        return Ok(None);
+    };
+    if a_pub.len() > N_LEN {
+        return Ok(None);
+    }
+
+    let record = match user_id {
+        Some(user_id) => database::srp::get_verifier(state, user_id).await?,
+        None => None,
+    };
+    let (user_id, salt, verifier) = match record {
+        Some(record) => (user_id, record.salt, record.verifier),
+        None => (
+            None,
+            srp::fake_salt(email),
+            rand::random::<[u8; 32]>().to_vec(),
+        ),
+    };
+    let Some(session) = srp::start(&salt, &verifier, &a_pub) else {
+        return Ok(None);
+    };
+
+    let session_id = hex::encode(rand::random::<[u8; 16]>());
+    database::srp::create_session(
+        state,
+        &session_id,
+        user_id,
+        &session.client_proof,
+        &session.server_proof,
+        Utc::now() + SESSION_TTL,
+    )
+    .await?;
+    Ok(Some(LoginChallenge {
+        session_id,
+        salt: hex::encode(salt),
+        b: hex::encode(session.b_pub),
+    }))
+}
+
+pub enum Proof {
+    /// `server_proof` is for the client to check that it talked to the real server
+    Verified { user_id: u32, server_proof: String },
+    /// `user_id` is known if the session was real, i.e. somebody got the password wrong
+    Rejected { user_id: Option<u32> },
+}
+
+/// Checks the client's proof `m1` (hex)
+pub async fn finish_login(state: &MySqlPool, session_id: &str, m1: &str) -> anyhow::Result<Proof> {
+    let Some(session) = database::srp::take_session(state, session_id).await? else {
+        return Ok(Proof::Rejected { user_id: None });
+    };
+    let user_id = session.user_id.map(|user_id| user_id as u32);
+    let m1 = hex::decode(m1).unwrap_or_default();
+    let matches: bool = session.client_proof.ct_eq(&m1).into();
+    match user_id {
+        Some(user_id) if matches => Ok(Proof::Verified {
+            user_id,
+            server_proof: hex::encode(session.server_proof),
+        }),
+        _ => Ok(Proof::Rejected { user_id }),
+    }
+}
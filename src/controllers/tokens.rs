@@ -1,20 +1,204 @@
+use std::collections::HashSet;
+
+use chrono::{DateTime, Utc};
 use sqlx::MySqlPool;
 
-use crate::database;
+use crate::{
+    common::client::ClientInfo,
+    crypt,
+    database::{
+        self,
+        tokens::{Session, SessionInfo},
+    },
+};
+
+pub enum Rotation {
+    /// Successor of the presented token, which is retired now
+    Rotated {
+        user_id: u32,
+        session_id: String,
+        refresh_token: String,
+    },
+    /// Token was never issued, expired or its family was revoked
+    Unknown,
+    /// Token was retired before, the whole family got revoked
+    Reused,
+}
+
+/// A new login, `session_id` is the family id every refresh token of it shares
+pub struct NewSession {
+    pub session_id: String,
+    pub refresh_token: String,
+}
 
+/// Optional cap on concurrent sessions per user, `MAX_SESSIONS`
+pub fn max_sessions() -> Option<usize> {
+    std::env::var("MAX_SESSIONS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .filter(|max| *max > 0)
+}
+
+/// Issues the first refresh token of a new family, i.e. a new login.
+/// If the user is at the session cap, the least recently used sessions are signed out
 pub async fn create_token(
     state: &MySqlPool,
     user_id: u32,
-    refresh_token: &str,
-) -> anyhow::Result<u32> {
-    let id = database::tokens::create_token(state, user_id, refresh_token).await?;
-    Ok(id)
+    device_name: Option<&str>,
+    client: &ClientInfo,
+) -> anyhow::Result<NewSession> {
+    if let Some(max) = max_sessions() {
+        let sessions = database::tokens::list_sessions(state, user_id).await?;
+        for session in sessions.iter().skip(max - 1) {
+            revoke_family(state, &session.id).await?;
+        }
+    }
+
+    let family_id = hex::encode(rand::random::<[u8; 16]>());
+    let refresh_token = crypt::token::make_refresh_token();
+    let info = SessionInfo {
+        device_name,
+        user_agent: client.user_agent.as_deref(),
+        ip_address: client.ip_address.as_deref(),
+    };
+    database::tokens::create_token(
+        state,
+        user_id,
+        &family_id,
+        &crypt::token::hash_refresh_token(&refresh_token),
+        Utc::now() + crypt::token::REFRESH_TOKEN_TTL,
+        &info,
+    )
+    .await?;
+    Ok(NewSession {
+        session_id: family_id,
+        refresh_token,
+    })
 }
 
-pub async fn token_exists(state: &MySqlPool, token: &str) -> anyhow::Result<()> {
-    database::tokens::token_exists(state, token).await
+/// Signs an access token for the session and remembers its `jti`, so it can be revoked
+pub async fn issue_access_token(
+    state: &MySqlPool,
+    user_id: u32,
+    session_id: &str,
+) -> anyhow::Result<String> {
+    let (token, claims) = crypt::token::make_jwt_token(user_id, session_id);
+    let expires_at = DateTime::from_timestamp(claims.exp, 0).unwrap_or_else(Utc::now);
+    database::access_tokens::add_token(state, &claims.jti, user_id, session_id, expires_at).await?;
+    Ok(token)
+}
+
+/// Reloads the in-memory denylist, picking up what other instances revoked
+pub async fn sync_denylist(state: &MySqlPool) -> anyhow::Result<()> {
+    let jtis = database::access_tokens::list_revoked(state).await?;
+    crypt::token::set_denylist(jtis.into_iter().collect::<HashSet<_>>());
+    Ok(())
+}
+
+/// Ends the login: its refresh tokens are deleted and its access tokens denied
+async fn revoke_family(state: &MySqlPool, family_id: &str) -> anyhow::Result<()> {
+    database::tokens::delete_family(state, family_id).await?;
+    if database::access_tokens::revoke_session(state, family_id).await? > 0 {
+        sync_denylist(state).await?;
+    }
+    Ok(())
 }
 
+pub async fn rotate_token(
+    state: &MySqlPool,
+    token: &str,
+    client: &ClientInfo,
+) -> anyhow::Result<Rotation> {
+    let token_hash = crypt::token::hash_refresh_token(token);
+    let Some(current) = database::tokens::get_token(state, &token_hash).await? else {
+        return Ok(Rotation::Unknown);
+    };
+
+    if !current.retired {
+        let refresh_token = crypt::token::make_refresh_token();
+        let info = SessionInfo {
+            device_name: current.device_name.as_deref(),
+            user_agent: client.user_agent.as_deref(),
+            ip_address: client.ip_address.as_deref(),
+        };
+        let rotated = database::tokens::rotate_token(
+            state,
+            &current,
+            &crypt::token::hash_refresh_token(&refresh_token),
+            Utc::now() + crypt::token::REFRESH_TOKEN_TTL,
+            &info,
+        )
+        .await?;
+        if rotated {
+            return Ok(Rotation::Rotated {
+                user_id: current.user_id as u32,
+                session_id: current.family_id,
+                refresh_token,
+            });
+        }
+    }
+
+    // Either a replay of an old token or two clients racing with the same one.
+    // We can't tell the thief from the owner, so both lose the session
+    tracing::warn!(
+        "Refresh token reuse detected for user {}, revoking token family {}",
+        current.user_id,
+        current.family_id
+    );
+    revoke_family(state, &current.family_id).await?;
+    Ok(Rotation::Reused)
+}
+
+/// Revokes the token and every token rotated from the same login
 pub async fn delete_token(state: &MySqlPool, token: &str) -> anyhow::Result<()> {
-    database::tokens::delete_token(state, token).await
+    let token_hash = crypt::token::hash_refresh_token(token);
+    if let Some(current) = database::tokens::get_token(state, &token_hash).await? {
+        revoke_family(state, &current.family_id).await?;
+    }
+    Ok(())
+}
+
+pub async fn list_sessions(state: &MySqlPool, user_id: u32) -> anyhow::Result<Vec<Session>> {
+    database::tokens::list_sessions(state, user_id).await
+}
+
+pub async fn delete_session(state: &MySqlPool, user_id: u32, id: &str) -> anyhow::Result<bool> {
+    if !database::tokens::delete_session(state, user_id, id).await? {
+        return Ok(false);
+    }
+    if database::access_tokens::revoke_session(state, id).await? > 0 {
+        sync_denylist(state).await?;
+    }
+    Ok(true)
+}
+
+/// Signs the user out everywhere
+pub async fn delete_user_tokens(state: &MySqlPool, user_id: u32) -> anyhow::Result<()> {
+    database::tokens::delete_user_tokens(state, user_id).await?;
+    if database::access_tokens::revoke_user(state, user_id).await? > 0 {
+        sync_denylist(state).await?;
+    }
+    Ok(())
+}
+
+/// Signs the user out of every session but `current_session`, the login the request's
+/// access token belongs to
+pub async fn delete_other_sessions(
+    state: &MySqlPool,
+    user_id: u32,
+    current_session: &str,
+) -> anyhow::Result<()> {
+    database::tokens::delete_other_sessions(state, user_id, current_session).await?;
+    if database::access_tokens::revoke_other_sessions(state, user_id, current_session).await? > 0 {
+        sync_denylist(state).await?;
+    }
+    Ok(())
+}
+
+pub async fn delete_expired(state: &MySqlPool) -> anyhow::Result<u64> {
+    database::tokens::delete_expired(state).await
+}
+
+pub async fn delete_expired_access_tokens(state: &MySqlPool) -> anyhow::Result<u64> {
+    database::access_tokens::delete_expired(state).await
 }
@@ -0,0 +1,96 @@
+use chrono::{Duration, Utc};
+use sqlx::MySqlPool;
+
+use crate::{
+    crypt::encryption,
+    database::{
+        self,
+        uploads::{UploadPart, UploadSession},
+    },
+};
+
+/// How long an unfinished upload can be resumed, `UPLOAD_SESSION_TTL` in seconds
+pub fn session_ttl() -> Duration {
+    let secs = std::env::var("UPLOAD_SESSION_TTL")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(24 * 60 * 60);
+    Duration::seconds(secs)
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn create_session(
+    state: &MySqlPool,
+    user_id: u32,
+    vault_id: u32,
+    object_key: &str,
+    store_upload_id: &str,
+    base_revision: u32,
+    length: u64,
+    expected_checksum: Option<String>,
+) -> anyhow::Result<UploadSession> {
+    let session = UploadSession {
+        id: hex::encode(rand::random::<[u8; 16]>()),
+        vault_id,
+        user_id: user_id as i32,
+        object_key: object_key.to_owned(),
+        store_upload_id: store_upload_id.to_owned(),
+        base_revision,
+        length,
+        upload_offset: 0,
+        expected_checksum,
+        stream_nonce: encryption::stream_nonce().to_vec(),
+        expires_at: Utc::now() + session_ttl(),
+    };
+    database::uploads::create_session(state, &session).await?;
+    Ok(session)
+}
+
+pub async fn get_session(
+    state: &MySqlPool,
+    user_id: u32,
+    vault_id: u32,
+    id: &str,
+) -> anyhow::Result<Option<UploadSession>> {
+    database::uploads::get_session(state, user_id, vault_id, id).await
+}
+
+pub async fn expired_sessions(state: &MySqlPool) -> anyhow::Result<Vec<UploadSession>> {
+    database::uploads::expired_sessions(state).await
+}
+
+pub async fn user_sessions(state: &MySqlPool, user_id: u32) -> anyhow::Result<Vec<UploadSession>> {
+    database::uploads::user_sessions(state, user_id).await
+}
+
+pub async fn claim_seal(
+    state: &MySqlPool,
+    id: &str,
+    from_offset: u64,
+    to_offset: u64,
+    digest: &[u8],
+) -> anyhow::Result<bool> {
+    database::uploads::claim_seal(state, id, from_offset, to_offset, digest).await
+}
+
+pub async fn add_part(
+    state: &MySqlPool,
+    id: &str,
+    from_offset: u64,
+    appended: u64,
+    part: &UploadPart,
+) -> anyhow::Result<bool> {
+    database::uploads::add_part(state, id, from_offset, appended, part).await
+}
+
+pub async fn list_parts(state: &MySqlPool, id: &str) -> anyhow::Result<Vec<UploadPart>> {
+    database::uploads::list_parts(state, id).await
+}
+
+pub async fn delete_session(state: &MySqlPool, id: &str) -> anyhow::Result<()> {
+    database::uploads::delete_session(state, id).await
+}
+
+pub async fn rebase_session(state: &MySqlPool, id: &str, base_revision: u32) -> anyhow::Result<()> {
+    database::uploads::rebase_session(state, id, base_revision).await
+}
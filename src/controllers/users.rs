@@ -1,6 +1,13 @@
+use chrono::{DateTime, Duration, Utc};
 use sqlx::MySqlPool;
 
-use crate::database;
+use crate::{
+    crypt::{
+        self,
+        encryption::{self, DataKey},
+    },
+    database,
+};
 
 pub async fn create_user(
     state: &MySqlPool,
@@ -12,12 +19,87 @@ pub async fn create_user(
     Ok(id)
 }
 
-pub async fn id_by_email(state: &MySqlPool, email: &str) -> anyhow::Result<u32> {
-    let id = database::users::id_by_email(state, email).await?;
-    Ok(id)
+pub async fn find_by_email(state: &MySqlPool, email: &str) -> anyhow::Result<Option<u32>> {
+    database::users::find_by_email(state, email).await
 }
 
 pub async fn get_password_hash(state: &MySqlPool, id: u32) -> anyhow::Result<String> {
     let pwd_hash = database::users::get_password_hash(state, id).await?;
     Ok(pwd_hash)
 }
+
+pub async fn get_email(state: &MySqlPool, id: u32) -> anyhow::Result<String> {
+    database::users::get_email(state, id).await
+}
+
+/// Checks `password` against the stored hash, for endpoints that ask to re-enter it
+pub async fn verify_password(state: &MySqlPool, id: u32, password: &str) -> anyhow::Result<bool> {
+    let hash = database::users::get_password_hash(state, id).await?;
+    Ok(crypt::password::verify_password(password, &hash).is_ok())
+}
+
+pub async fn change_password(state: &MySqlPool, id: u32, password: &str) -> anyhow::Result<()> {
+    let password_hash = crypt::password::hash_password(password);
+    database::users::set_password_hash(state, id, &password_hash).await
+}
+
+/// Returns false if somebody else already has the username
+pub async fn change_username(state: &MySqlPool, id: u32, username: &str) -> anyhow::Result<bool> {
+    if database::users::find_by_username(state, username)
+        .await?
+        .is_some_and(|owner| owner != id)
+    {
+        return Ok(false);
+    }
+    database::users::set_username(state, id, username).await?;
+    Ok(true)
+}
+
+/// How long a deleted account can still be restored, `ACCOUNT_DELETION_GRACE_DAYS`.
+/// Zero (the default) purges it right away
+pub fn deletion_grace() -> Duration {
+    std::env::var("ACCOUNT_DELETION_GRACE_DAYS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .map(Duration::days)
+        .unwrap_or(Duration::zero())
+}
+
+/// `None` unless the account is waiting to be purged
+pub async fn purge_at(state: &MySqlPool, id: u32) -> anyhow::Result<Option<DateTime<Utc>>> {
+    database::users::get_purge_at(state, id).await
+}
+
+/// Returns when the account is going to be purged
+pub async fn schedule_deletion(state: &MySqlPool, id: u32) -> anyhow::Result<DateTime<Utc>> {
+    let purge_at = Utc::now() + deletion_grace();
+    database::users::set_purge_at(state, id, Some(purge_at)).await?;
+    Ok(purge_at)
+}
+
+pub async fn cancel_deletion(state: &MySqlPool, id: u32) -> anyhow::Result<()> {
+    database::users::set_purge_at(state, id, None).await
+}
+
+pub async fn due_for_purge(state: &MySqlPool) -> anyhow::Result<Vec<u32>> {
+    database::users::due_for_purge(state).await
+}
+
+/// Only the database side, vault objects have to be gone already
+pub async fn delete_user(state: &MySqlPool, id: u32) -> anyhow::Result<()> {
+    database::users::delete_user(state, id).await
+}
+
+/// Key the user's vault blobs are encrypted with, created on first use
+pub async fn data_key(state: &MySqlPool, id: u32) -> anyhow::Result<DataKey> {
+    if let Some(wrapped) = database::users::get_data_key(state, id).await? {
+        return encryption::unwrap_data_key(&wrapped);
+    }
+
+    let wrapped = encryption::wrap_data_key(&encryption::generate_data_key())?;
+    database::users::init_data_key(state, id, &wrapped).await?;
+    match database::users::get_data_key(state, id).await? {
+        Some(wrapped) => encryption::unwrap_data_key(&wrapped),
+        None => anyhow::bail!("Could not store data key for user {}", id),
+    }
+}
@@ -0,0 +1,49 @@
+use chrono::{DateTime, Utc};
+use sqlx::MySqlPool;
+
+use crate::database::{self, vaults::Vault};
+
+/// Vault names end up in the Content-Disposition header, so keep them boring
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 64
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, ' ' | '-' | '_' | '.'))
+}
+
+pub async fn create_vault(state: &MySqlPool, user_id: u32, name: &str) -> anyhow::Result<u32> {
+    let id = database::vaults::create_vault(state, user_id, name).await?;
+    Ok(id)
+}
+
+pub async fn users_without_vaults(
+    state: &MySqlPool,
+) -> anyhow::Result<Vec<(u32, Option<DateTime<Utc>>)>> {
+    database::vaults::users_without_vaults(state).await
+}
+
+pub async fn get_vault(
+    state: &MySqlPool,
+    user_id: u32,
+    vault_id: u32,
+) -> anyhow::Result<Option<Vault>> {
+    database::vaults::get_vault(state, user_id, vault_id).await
+}
+
+pub async fn list_vaults(state: &MySqlPool, user_id: u32) -> anyhow::Result<Vec<Vault>> {
+    database::vaults::list_vaults(state, user_id).await
+}
+
+pub async fn rename_vault(
+    state: &MySqlPool,
+    user_id: u32,
+    vault_id: u32,
+    name: &str,
+) -> anyhow::Result<()> {
+    database::vaults::rename_vault(state, user_id, vault_id, name).await
+}
+
+pub async fn delete_vault(state: &MySqlPool, user_id: u32, vault_id: u32) -> anyhow::Result<()> {
+    database::vaults::delete_vault(state, user_id, vault_id).await
+}
@@ -0,0 +1,215 @@
+use std::collections::HashSet;
+
+use chrono::Datelike;
+use sqlx::MySqlPool;
+
+use crate::database::{self, versions::VaultVersion};
+
+#[allow(clippy::too_many_arguments)]
+pub async fn create_version(
+    state: &MySqlPool,
+    vault_id: u32,
+    version: u32,
+    size: u64,
+    uploaded_by: u32,
+    checksum: &str,
+    object_key: &str,
+    encrypted: bool,
+) -> anyhow::Result<bool> {
+    database::versions::create_version(
+        state,
+        vault_id,
+        version,
+        size,
+        uploaded_by,
+        checksum,
+        object_key,
+        encrypted,
+    )
+    .await
+}
+
+/// Monotonic revision of the vault, 0 means nothing was uploaded yet
+pub async fn current_revision(state: &MySqlPool, vault_id: u32) -> anyhow::Result<u32> {
+    let latest = database::versions::latest_version(state, vault_id).await?;
+    Ok(latest.unwrap_or(0))
+}
+
+pub async fn latest_version(
+    state: &MySqlPool,
+    vault_id: u32,
+) -> anyhow::Result<Option<VaultVersion>> {
+    match database::versions::latest_version(state, vault_id).await? {
+        Some(version) => database::versions::get_version(state, vault_id, version).await,
+        None => Ok(None),
+    }
+}
+
+pub async fn get_version(
+    state: &MySqlPool,
+    vault_id: u32,
+    version: u32,
+) -> anyhow::Result<Option<VaultVersion>> {
+    database::versions::get_version(state, vault_id, version).await
+}
+
+pub async fn list_versions(state: &MySqlPool, vault_id: u32) -> anyhow::Result<Vec<VaultVersion>> {
+    database::versions::list_versions(state, vault_id).await
+}
+
+pub async fn delete_version(state: &MySqlPool, vault_id: u32, version: u32) -> anyhow::Result<()> {
+    database::versions::delete_version(state, vault_id, version).await
+}
+
+pub async fn object_committed(state: &MySqlPool, object_key: &str) -> anyhow::Result<bool> {
+    database::versions::object_committed(state, object_key).await
+}
+
+pub struct RetentionPolicy {
+    pub keep_last: usize,
+    pub keep_daily: usize,
+    pub keep_weekly: usize,
+}
+
+impl RetentionPolicy {
+    pub fn from_env() -> Self {
+        fn var_or(name: &str, default: usize) -> usize {
+            std::env::var(name)
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(default)
+        }
+
+        Self {
+            keep_last: var_or("VAULT_KEEP_LAST", 10),
+            keep_daily: var_or("VAULT_KEEP_DAILY", 7),
+            keep_weekly: var_or("VAULT_KEEP_WEEKLY", 4),
+        }
+    }
+
+    /// Returns versions that fall out of the policy.
+    /// `versions` must be sorted from newest to oldest
+    pub fn versions_to_prune<'a>(&self, versions: &'a [VaultVersion]) -> Vec<&'a VaultVersion> {
+        let mut keep = HashSet::new();
+        let mut days = HashSet::new();
+        let mut weeks = HashSet::new();
+
+        for (idx, version) in versions.iter().enumerate() {
+            if idx < self.keep_last {
+                keep.insert(version.version);
+            }
+
+            // Newest version of each day/week is the snapshot for that period
+            let day = version.created_at.date_naive();
+            if days.len() < self.keep_daily && days.insert(day) {
+                keep.insert(version.version);
+            }
+            let week = day.iso_week();
+            if weeks.len() < self.keep_weekly && weeks.insert((week.year(), week.week())) {
+                keep.insert(version.version);
+            }
+        }
+
+        versions
+            .iter()
+            .filter(|version| !keep.contains(&version.version))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, TimeZone, Utc};
+
+    use super::*;
+
+    fn version(version: u32, created_at: DateTime<Utc>) -> VaultVersion {
+        VaultVersion {
+            version,
+            size: 0,
+            uploaded_by: 1,
+            checksum: String::new(),
+            created_at,
+            object_key: format!("vaults/1/{}", version),
+            encrypted: true,
+        }
+    }
+
+    fn at(month: u32, day: u32, hour: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2026, month, day, hour, 0, 0).unwrap()
+    }
+
+    fn pruned(policy: &RetentionPolicy, versions: &[VaultVersion]) -> Vec<u32> {
+        policy
+            .versions_to_prune(versions)
+            .into_iter()
+            .map(|version| version.version)
+            .collect()
+    }
+
+    fn policy(keep_last: usize, keep_daily: usize, keep_weekly: usize) -> RetentionPolicy {
+        RetentionPolicy {
+            keep_last,
+            keep_daily,
+            keep_weekly,
+        }
+    }
+
+    #[test]
+    fn empty_list_prunes_nothing() {
+        assert!(pruned(&policy(10, 7, 4), &[]).is_empty());
+        assert!(pruned(&policy(0, 0, 0), &[]).is_empty());
+    }
+
+    #[test]
+    fn keeps_exactly_keep_last() {
+        let versions: Vec<_> = (1..=3)
+            .rev()
+            .map(|number| version(number, at(10, number, 12)))
+            .collect();
+        assert!(pruned(&policy(3, 0, 0), &versions).is_empty());
+
+        let versions: Vec<_> = (1..=4)
+            .rev()
+            .map(|number| version(number, at(10, number, 12)))
+            .collect();
+        assert_eq!(pruned(&policy(3, 0, 0), &versions), vec![1]);
+    }
+
+    #[test]
+    fn keeps_newest_version_of_each_day() {
+        let versions = [
+            version(5, at(10, 3, 18)),
+            version(4, at(10, 3, 9)),
+            version(3, at(10, 2, 18)),
+            version(2, at(10, 2, 8)),
+            version(1, at(10, 1, 12)),
+        ];
+        assert_eq!(pruned(&policy(0, 2, 0), &versions), vec![4, 2, 1]);
+    }
+
+    #[test]
+    fn keeps_newest_version_of_each_week() {
+        // ISO weeks start on Monday: the 12th and 14th share week 42, the 5th and 11th week 41
+        let versions = [
+            version(5, at(10, 14, 12)),
+            version(4, at(10, 12, 12)),
+            version(3, at(10, 11, 12)),
+            version(2, at(10, 5, 12)),
+            version(1, at(9, 30, 12)),
+        ];
+        assert_eq!(pruned(&policy(0, 0, 2), &versions), vec![4, 2, 1]);
+    }
+
+    #[test]
+    fn periods_count_only_once_with_keep_last() {
+        let versions = [
+            version(4, at(10, 3, 18)),
+            version(3, at(10, 3, 9)),
+            version(2, at(10, 2, 12)),
+            version(1, at(10, 1, 12)),
+        ];
+        // The newest version is both the last one and the one for its day
+        assert_eq!(pruned(&policy(1, 2, 0), &versions), vec![3, 1]);
+    }
+}
@@ -0,0 +1,286 @@
+use chrono::{Duration, Utc};
+use serde::Serialize;
+use sqlx::MySqlPool;
+
+use crate::{
+    crypt::webauthn::{self, Rejected, RelyingParty, ES256},
+    database::{self, webauthn::WebauthnCredential},
+};
+
+const REGISTER: &str = "register";
+const LOGIN: &str = "login";
+const CEREMONY_TIMEOUT_SECS: i64 = 5 * 60;
+
+/// Passwordless login with a passkey, `WEBAUTHN_PASSWORDLESS`
+pub fn passwordless_enabled() -> bool {
+    std::env::var("WEBAUTHN_PASSWORDLESS")
+        .map(|value| value == "true" || value == "1")
+        .unwrap_or(false)
+}
+
+#[derive(Serialize)]
+pub struct RelyingPartyEntity {
+    id: String,
+    name: String,
+}
+
+#[derive(Serialize)]
+pub struct UserEntity {
+    id: String,
+    name: String,
+    #[serde(rename = "displayName")]
+    display_name: String,
+}
+
+#[derive(Serialize)]
+pub struct CredentialParameters {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    alg: i32,
+}
+
+#[derive(Serialize)]
+pub struct CredentialDescriptor {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    id: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthenticatorSelection {
+    resident_key: &'static str,
+    user_verification: &'static str,
+}
+
+/// `PublicKeyCredentialCreationOptions`, binary fields base64url encoded
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreationOptions {
+    challenge: String,
+    rp: RelyingPartyEntity,
+    user: UserEntity,
+    pub_key_cred_params: Vec<CredentialParameters>,
+    timeout: i64,
+    attestation: &'static str,
+    exclude_credentials: Vec<CredentialDescriptor>,
+    authenticator_selection: AuthenticatorSelection,
+}
+
+/// `PublicKeyCredentialRequestOptions`, binary fields base64url encoded
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestOptions {
+    challenge: String,
+    rp_id: String,
+    allow_credentials: Vec<CredentialDescriptor>,
+    timeout: i64,
+    user_verification: &'static str,
+}
+
+#[derive(Serialize)]
+pub struct Ceremony<T> {
+    pub challenge_id: String,
+    pub public_key: T,
+}
+
+fn descriptors(credentials: &[WebauthnCredential]) -> Vec<CredentialDescriptor> {
+    credentials
+        .iter()
+        .map(|credential| CredentialDescriptor {
+            kind: "public-key",
+            id: webauthn::encode_b64(&credential.credential_id),
+        })
+        .collect()
+}
+
+async fn new_challenge(
+    state: &MySqlPool,
+    user_id: Option<u32>,
+    purpose: &str,
+) -> anyhow::Result<(String, [u8; 32])> {
+    let id = hex::encode(rand::random::<[u8; 16]>());
+    let challenge = rand::random::<[u8; 32]>();
+    database::webauthn::create_challenge(
+        state,
+        &id,
+        user_id,
+        purpose,
+        &challenge,
+        Utc::now() + Duration::seconds(CEREMONY_TIMEOUT_SECS),
+    )
+    .await?;
+    Ok((id, challenge))
+}
+
+pub async fn start_registration(
+    state: &MySqlPool,
+    user_id: u32,
+) -> anyhow::Result<Ceremony<CreationOptions>> {
+    let rp = RelyingParty::from_env();
+    let email = database::users::get_email(state, user_id).await?;
+    let existing = database::webauthn::list_credentials(state, user_id).await?;
+    let (challenge_id, challenge) = new_challenge(state, Some(user_id), REGISTER).await?;
+
+    Ok(Ceremony {
+        challenge_id,
+        public_key: CreationOptions {
+            challenge: webauthn::encode_b64(&challenge),
+            rp: RelyingPartyEntity {
+                id: rp.id,
+                name: rp.name,
+            },
+            user: UserEntity {
+                // The user handle is what passwordless login gets back, so it's just the id
+                id: webauthn::encode_b64(user_id.to_string().as_bytes()),
+                name: email.clone(),
+                display_name: email,
+            },
+            pub_key_cred_params: vec![CredentialParameters {
+                kind: "public-key",
+                alg: ES256,
+            }],
+            timeout: CEREMONY_TIMEOUT_SECS * 1000,
+            attestation: "none",
+            exclude_credentials: descriptors(&existing),
+            authenticator_selection: AuthenticatorSelection {
+                resident_key: "preferred",
+                user_verification: "preferred",
+            },
+        },
+    })
+}
+
+pub async fn finish_registration(
+    state: &MySqlPool,
+    user_id: u32,
+    challenge_id: &str,
+    name: &str,
+    client_data_json: &[u8],
+    attestation_object: &[u8],
+) -> anyhow::Result<u32> {
+    let Some(challenge) = database::webauthn::take_challenge(state, challenge_id, REGISTER).await?
+    else {
+        return Err(Rejected("Challenge expired or was already used".to_owned()).into());
+    };
+    if challenge.user_id != Some(user_id as i32) {
+        return Err(Rejected("Challenge belongs to someone else".to_owned()).into());
+    }
+
+    let credential = webauthn::verify_registration(
+        &RelyingParty::from_env(),
+        &challenge.challenge,
+        client_data_json,
+        attestation_object,
+    )?;
+    if database::webauthn::get_credential(state, &credential.credential_id)
+        .await?
+        .is_some()
+    {
+        return Err(Rejected("Credential is already registered".to_owned()).into());
+    }
+
+    database::webauthn::create_credential(
+        state,
+        user_id,
+        &credential.credential_id,
+        &credential.public_key,
+        ES256,
+        credential.sign_count,
+        name,
+    )
+    .await
+}
+
+/// With `user_id` only that user's credentials are allowed (second factor), without it
+/// any discoverable credential can answer (passwordless)
+pub async fn start_login(
+    state: &MySqlPool,
+    user_id: Option<u32>,
+) -> anyhow::Result<Ceremony<RequestOptions>> {
+    let allowed = match user_id {
+        Some(user_id) => database::webauthn::list_credentials(state, user_id).await?,
+        None => Vec::new(),
+    };
+    let (challenge_id, challenge) = new_challenge(state, user_id, LOGIN).await?;
+
+    Ok(Ceremony {
+        challenge_id,
+        public_key: RequestOptions {
+            challenge: webauthn::encode_b64(&challenge),
+            rp_id: RelyingParty::from_env().id,
+            allow_credentials: descriptors(&allowed),
+            timeout: CEREMONY_TIMEOUT_SECS * 1000,
+            user_verification: if user_id.is_some() {
+                "discouraged"
+            } else {
+                "required"
+            },
+        },
+    })
+}
+
+/// Verifies the assertion and returns whose credential it was
+pub async fn finish_login(
+    state: &MySqlPool,
+    challenge_id: &str,
+    credential_id: &[u8],
+    client_data_json: &[u8],
+    authenticator_data: &[u8],
+    signature: &[u8],
+) -> anyhow::Result<u32> {
+    let Some(challenge) = database::webauthn::take_challenge(state, challenge_id, LOGIN).await?
+    else {
+        return Err(Rejected("Challenge expired or was already used".to_owned()).into());
+    };
+    let Some(credential) = database::webauthn::get_credential(state, credential_id).await? else {
+        return Err(Rejected("Unknown credential".to_owned()).into());
+    };
+    if challenge
+        .user_id
+        .is_some_and(|user_id| user_id != credential.user_id)
+    {
+        return Err(Rejected("Credential belongs to someone else".to_owned()).into());
+    }
+
+    let assertion = webauthn::verify_assertion(
+        &RelyingParty::from_env(),
+        &challenge.challenge,
+        &credential.public_key,
+        client_data_json,
+        authenticator_data,
+        signature,
+    )?;
+    // A passkey replaces both the password and the second factor, so it has to verify the user
+    if challenge.user_id.is_none() && !assertion.user_verified {
+        return Err(Rejected("User verification is required".to_owned()).into());
+    }
+    // The update checks the counter once more, in case two logins with it raced
+    let moved_on = webauthn::sign_count_increased(credential.sign_count, assertion.sign_count)
+        && database::webauthn::use_credential(state, credential.id, assertion.sign_count).await?;
+    if !moved_on {
+        tracing::warn!(
+            "Signature counter of credential {} went backwards, it may be cloned",
+            credential.id
+        );
+        return Err(Rejected("Signature counter did not increase".to_owned()).into());
+    }
+    Ok(credential.user_id as u32)
+}
+
+pub async fn has_credentials(state: &MySqlPool, user_id: u32) -> anyhow::Result<bool> {
+    Ok(!database::webauthn::list_credentials(state, user_id)
+        .await?
+        .is_empty())
+}
+
+pub async fn list_credentials(
+    state: &MySqlPool,
+    user_id: u32,
+) -> anyhow::Result<Vec<WebauthnCredential>> {
+    database::webauthn::list_credentials(state, user_id).await
+}
+
+pub async fn delete_credential(state: &MySqlPool, user_id: u32, id: i32) -> anyhow::Result<bool> {
+    database::webauthn::delete_credential(state, user_id, id).await
+}
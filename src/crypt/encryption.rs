@@ -1,11 +1,38 @@
-use aes_gcm::{aead::Aead, AeadCore, Aes256Gcm, Key, KeyInit, Nonce};
+use aes_gcm::{
+    aead::{
+        generic_array::GenericArray,
+        stream::{DecryptorBE32, EncryptorBE32, NewStream, StreamBE32, StreamPrimitive},
+        Aead, OsRng,
+    },
+    AeadCore, Aes256Gcm, Key, KeyInit, Nonce,
+};
+use bytes::{Bytes, BytesMut};
+use futures::StreamExt;
 
-pub fn aes_encrypt_text(plaintext: &str) -> anyhow::Result<(Vec<u8>, Vec<u8>)> {
-    // returns Encrypted password, nonce
+use crate::store::ByteStream;
+
+// Vault blobs are sealed in chunks of this size, each carrying its own tag
+const CHUNK_SIZE: usize = 64 * 1024;
+const TAG_SIZE: usize = 16;
+// Format version followed by the 7 byte STREAM nonce prefix
+const STREAM_VERSION: u8 = 1;
+const HEADER_SIZE: usize = 8;
+pub const STREAM_NONCE_SIZE: usize = HEADER_SIZE - 1;
+// Parts sealed one by one have to end on a chunk boundary, except the last one
+pub const PART_ALIGNMENT: usize = CHUNK_SIZE;
+
+pub type DataKey = Key<Aes256Gcm>;
+
+fn master_cipher() -> Aes256Gcm {
     let aes_key = std::env::var("AES_KEY").unwrap();
     let key = Key::<Aes256Gcm>::from_slice(aes_key.as_bytes());
-    let cipher = Aes256Gcm::new(&key);
-    let nonce = Aes256Gcm::generate_nonce(&mut aes_gcm::aead::OsRng);
+    Aes256Gcm::new(key)
+}
+
+pub fn aes_encrypt_text(plaintext: &str) -> anyhow::Result<(Vec<u8>, Vec<u8>)> {
+    // returns Encrypted password, nonce
+    let cipher = master_cipher();
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
     let ciphertext = cipher
         .encrypt(&nonce, plaintext.as_ref())
         .map_err(|_| anyhow::anyhow!("Could not encrypt"))?;
@@ -14,9 +41,7 @@ pub fn aes_encrypt_text(plaintext: &str) -> anyhow::Result<(Vec<u8>, Vec<u8>)> {
 }
 
 pub fn aes_decrypt_text(ciphertext: &[u8], nonce: &[u8]) -> anyhow::Result<String> {
-    let aes_key = std::env::var("AES_KEY").unwrap();
-    let key = Key::<Aes256Gcm>::from_slice(aes_key.as_bytes());
-    let cipher = Aes256Gcm::new(&key);
+    let cipher = master_cipher();
 
     let nonce = Nonce::from_slice(nonce);
     let plaintext = cipher
@@ -25,3 +50,280 @@ pub fn aes_decrypt_text(ciphertext: &[u8], nonce: &[u8]) -> anyhow::Result<Strin
 
     Ok(String::from_utf8_lossy(&plaintext).to_string())
 }
+
+pub fn generate_data_key() -> DataKey {
+    Aes256Gcm::generate_key(OsRng)
+}
+
+/// Seals a data key with the server master key, returns nonce followed by ciphertext
+pub fn wrap_data_key(key: &DataKey) -> anyhow::Result<Vec<u8>> {
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = master_cipher()
+        .encrypt(&nonce, key.as_slice())
+        .map_err(|_| anyhow::anyhow!("Could not wrap data key"))?;
+
+    let mut wrapped = nonce.to_vec();
+    wrapped.extend_from_slice(&ciphertext);
+    Ok(wrapped)
+}
+
+pub fn unwrap_data_key(wrapped: &[u8]) -> anyhow::Result<DataKey> {
+    if wrapped.len() < 12 {
+        anyhow::bail!("Wrapped data key is too short");
+    }
+    let (nonce, ciphertext) = wrapped.split_at(12);
+    let key = master_cipher()
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| anyhow::anyhow!("Could not unwrap data key"))?;
+    if key.len() != 32 {
+        anyhow::bail!("Unwrapped data key has wrong length");
+    }
+    Ok(*DataKey::from_slice(&key))
+}
+
+pub fn stream_nonce() -> [u8; STREAM_NONCE_SIZE] {
+    rand::random()
+}
+
+/// Seals one part of a vault that arrives in pieces, concatenated the parts read exactly like
+/// `encrypt_stream` output. `offset` is where the part starts in the plaintext, the first part
+/// carries the header and `last` marks the one that ends the vault. Positions come from
+/// `offset`, so callers must never seal different data at the same offset with one nonce
+pub fn encrypt_part(
+    key: &DataKey,
+    nonce: &[u8],
+    offset: u64,
+    data: &[u8],
+    last: bool,
+) -> anyhow::Result<Vec<u8>> {
+    if nonce.len() != STREAM_NONCE_SIZE {
+        anyhow::bail!("Stream nonce has wrong length");
+    }
+    if !offset.is_multiple_of(PART_ALIGNMENT as u64)
+        || (!last && !data.len().is_multiple_of(PART_ALIGNMENT))
+    {
+        anyhow::bail!("Part does not end on a chunk boundary");
+    }
+
+    let stream = StreamBE32::from_aead(Aes256Gcm::new(key), GenericArray::from_slice(nonce));
+    let first = u32::try_from(offset / CHUNK_SIZE as u64)?;
+    let mut chunks: Vec<&[u8]> = data.chunks(CHUNK_SIZE).collect();
+    if chunks.is_empty() && last {
+        chunks.push(&[]);
+    }
+
+    let mut sealed = Vec::with_capacity(HEADER_SIZE + data.len() + chunks.len() * TAG_SIZE);
+    if offset == 0 {
+        sealed.push(STREAM_VERSION);
+        sealed.extend_from_slice(nonce);
+    }
+    let count = chunks.len();
+    for (index, chunk) in chunks.into_iter().enumerate() {
+        let position = first
+            .checked_add(index as u32)
+            .ok_or_else(|| anyhow::anyhow!("Vault is too large to encrypt"))?;
+        let ciphertext = stream
+            .encrypt(position, last && index + 1 == count, chunk)
+            .map_err(|_| anyhow::anyhow!("Could not encrypt"))?;
+        sealed.extend_from_slice(&ciphertext);
+    }
+    Ok(sealed)
+}
+
+struct Sealing<'a> {
+    body: ByteStream<'a>,
+    header: Option<Bytes>,
+    encryptor: Option<EncryptorBE32<Aes256Gcm>>,
+    buffer: BytesMut,
+}
+
+/// Encrypts `body` chunk by chunk, only one chunk is held in memory at a time
+pub fn encrypt_stream<'a>(key: &DataKey, body: ByteStream<'a>) -> ByteStream<'a> {
+    let nonce = stream_nonce();
+    let mut header = BytesMut::with_capacity(HEADER_SIZE);
+    header.extend_from_slice(&[STREAM_VERSION]);
+    header.extend_from_slice(&nonce);
+
+    let state = Sealing {
+        body,
+        header: Some(header.freeze()),
+        encryptor: Some(EncryptorBE32::new(key, GenericArray::from_slice(&nonce))),
+        buffer: BytesMut::new(),
+    };
+
+    futures::stream::try_unfold(state, |mut state| async move {
+        if let Some(header) = state.header.take() {
+            return Ok(Some((header, state)));
+        }
+        if state.encryptor.is_none() {
+            return Ok(None);
+        }
+
+        loop {
+            // A full chunk is only sealed once more data follows it, the last one is sealed differently
+            if state.buffer.len() > CHUNK_SIZE {
+                let chunk = state.buffer.split_to(CHUNK_SIZE);
+                let sealed = state
+                    .encryptor
+                    .as_mut()
+                    .unwrap()
+                    .encrypt_next(chunk.as_ref())
+                    .map_err(|_| anyhow::anyhow!("Could not encrypt"))?;
+                return Ok(Some((Bytes::from(sealed), state)));
+            }
+
+            match state.body.next().await {
+                Some(chunk) => state.buffer.extend_from_slice(&chunk?),
+                None => {
+                    let chunk = state.buffer.split();
+                    let sealed = state
+                        .encryptor
+                        .take()
+                        .unwrap()
+                        .encrypt_last(chunk.as_ref())
+                        .map_err(|_| anyhow::anyhow!("Could not encrypt"))?;
+                    return Ok(Some((Bytes::from(sealed), state)));
+                }
+            }
+        }
+    })
+    .boxed()
+}
+
+struct Opening<'a> {
+    body: ByteStream<'a>,
+    key: DataKey,
+    decryptor: Option<DecryptorBE32<Aes256Gcm>>,
+    buffer: BytesMut,
+    finished: bool,
+}
+
+/// Reverses `encrypt_stream`. Fails if the object was truncated, reordered or tampered with
+pub fn decrypt_stream<'a>(key: &DataKey, body: ByteStream<'a>) -> ByteStream<'a> {
+    let state = Opening {
+        body,
+        key: *key,
+        decryptor: None,
+        buffer: BytesMut::new(),
+        finished: false,
+    };
+
+    futures::stream::try_unfold(state, |mut state| async move {
+        if state.finished {
+            return Ok(None);
+        }
+
+        loop {
+            if state.decryptor.is_none() && state.buffer.len() >= HEADER_SIZE {
+                let header = state.buffer.split_to(HEADER_SIZE);
+                if header[0] != STREAM_VERSION {
+                    anyhow::bail!("Unknown vault encryption format {}", header[0]);
+                }
+                state.decryptor = Some(DecryptorBE32::new(
+                    &state.key,
+                    GenericArray::from_slice(&header[1..]),
+                ));
+            }
+
+            if let Some(decryptor) = state.decryptor.as_mut() {
+                if state.buffer.len() > CHUNK_SIZE + TAG_SIZE {
+                    let chunk = state.buffer.split_to(CHUNK_SIZE + TAG_SIZE);
+                    let opened = decryptor
+                        .decrypt_next(chunk.as_ref())
+                        .map_err(|_| anyhow::anyhow!("Could not decrypt vault"))?;
+                    return Ok(Some((Bytes::from(opened), state)));
+                }
+            }
+
+            match state.body.next().await {
+                Some(chunk) => state.buffer.extend_from_slice(&chunk?),
+                None => {
+                    let Some(decryptor) = state.decryptor.take() else {
+                        anyhow::bail!("Encrypted vault is truncated");
+                    };
+                    let chunk = state.buffer.split();
+                    let opened = decryptor
+                        .decrypt_last(chunk.as_ref())
+                        .map_err(|_| anyhow::anyhow!("Could not decrypt vault"))?;
+                    state.finished = true;
+                    return Ok(Some((Bytes::from(opened), state)));
+                }
+            }
+        }
+    })
+    .boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::TryStreamExt;
+
+    use super::*;
+
+    fn body(data: Vec<u8>) -> ByteStream<'static> {
+        futures::stream::iter([Ok(Bytes::from(data))]).boxed()
+    }
+
+    async fn open(key: &DataKey, sealed: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+        decrypt_stream(key, body(sealed))
+            .try_fold(Vec::new(), |mut data, chunk| async move {
+                data.extend_from_slice(&chunk);
+                Ok(data)
+            })
+            .await
+    }
+
+    fn seal_parts(key: &DataKey, data: &[u8], part_size: usize) -> Vec<u8> {
+        let nonce = stream_nonce();
+        let mut sealed = Vec::new();
+        let mut offset = 0;
+        for part in data.chunks(part_size) {
+            let last = offset + part.len() == data.len();
+            sealed.extend(encrypt_part(key, &nonce, offset as u64, part, last).unwrap());
+            offset += part.len();
+        }
+        sealed
+    }
+
+    #[tokio::test]
+    async fn parts_open_like_a_stream() {
+        let key = generate_data_key();
+        for length in [100, CHUNK_SIZE, 2 * CHUNK_SIZE, 5 * CHUNK_SIZE + 100] {
+            let data: Vec<u8> = (0..length).map(|byte| byte as u8).collect();
+            let sealed = seal_parts(&key, &data, 2 * CHUNK_SIZE);
+            assert_eq!(open(&key, sealed).await.unwrap(), data);
+        }
+    }
+
+    #[tokio::test]
+    async fn stream_round_trip() {
+        let key = generate_data_key();
+        let data = vec![7u8; 3 * CHUNK_SIZE + 1];
+        let sealed: Vec<u8> = encrypt_stream(&key, body(data.clone()))
+            .try_fold(Vec::new(), |mut sealed, chunk| async move {
+                sealed.extend_from_slice(&chunk);
+                Ok(sealed)
+            })
+            .await
+            .unwrap();
+        assert_eq!(open(&key, sealed).await.unwrap(), data);
+    }
+
+    #[tokio::test]
+    async fn missing_last_part_is_detected() {
+        let key = generate_data_key();
+        let data = vec![1u8; 3 * CHUNK_SIZE];
+        let nonce = stream_nonce();
+        let first = encrypt_part(&key, &nonce, 0, &data[..2 * CHUNK_SIZE], false).unwrap();
+        assert!(open(&key, first).await.is_err());
+    }
+
+    #[test]
+    fn misaligned_part_is_rejected() {
+        let key = generate_data_key();
+        let nonce = stream_nonce();
+        assert!(encrypt_part(&key, &nonce, 0, &[0; 100], false).is_err());
+        assert!(encrypt_part(&key, &nonce, 100, &[0; 100], true).is_err());
+        assert!(encrypt_part(&key, &nonce[1..], 0, &[0; 100], true).is_err());
+    }
+}
@@ -0,0 +1,247 @@
+//! Keys access tokens are signed with. `JWT_KEYS_DIR` holds one PEM file per key, the file
+//! name without `.pem` is its `kid`. Private keys (PKCS#8 Ed25519, PKCS#8 or PKCS#1 RSA) can
+//! sign, public keys (SPKI) only verify. `JWT_SIGNING_KID` picks the signing key, it can be
+//! left out when there is only one private key.
+//!
+//! Rotating: drop the new public key in first so other services pick it up from the JWKS,
+//! then switch `JWT_SIGNING_KID` to the new private key, and remove the old one once the
+//! tokens it signed have expired
+//!
+//! The secrets that aren't keys are loaded here too: `SECRET_WORD_SALT` derives the made up
+//! salts for unknown emails, `SECRET_WORD_MFA` signs the short lived tokens between password
+//! and second factor
+
+use std::{collections::HashMap, path::Path, sync::OnceLock};
+
+use anyhow::Context;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use ed25519_dalek::{
+    pkcs8::{DecodePrivateKey, DecodePublicKey, EncodePrivateKey},
+    SigningKey, VerifyingKey,
+};
+use jsonwebtoken::{
+    jwk::{
+        AlgorithmParameters, CommonParameters, EllipticCurve, Jwk, JwkSet, KeyAlgorithm,
+        OctetKeyPairParameters, OctetKeyPairType, PublicKeyUse, RSAKeyParameters, RSAKeyType,
+    },
+    Algorithm, DecodingKey, EncodingKey,
+};
+use rsa::{
+    pkcs1::{DecodeRsaPrivateKey, EncodeRsaPrivateKey},
+    traits::PublicKeyParts,
+    RsaPrivateKey, RsaPublicKey,
+};
+
+static KEYS: OnceLock<JwtKeys> = OnceLock::new();
+static SECRETS: OnceLock<Secrets> = OnceLock::new();
+
+pub struct Secrets {
+    pub salt: Vec<u8>,
+    pub mfa_encoding: EncodingKey,
+    pub mfa_decoding: DecodingKey,
+}
+
+pub struct SigningKeyEntry {
+    pub kid: String,
+    pub algorithm: Algorithm,
+    pub key: EncodingKey,
+}
+
+pub struct JwtKeys {
+    pub signing: SigningKeyEntry,
+    verifying: HashMap<String, (Algorithm, DecodingKey)>,
+    pub jwks: JwkSet,
+}
+
+impl JwtKeys {
+    pub fn verifying(&self, kid: &str) -> Option<&(Algorithm, DecodingKey)> {
+        self.verifying.get(kid)
+    }
+}
+
+enum Parsed {
+    Ed25519(VerifyingKey, Option<SigningKey>),
+    Rsa(RsaPublicKey, Option<RsaPrivateKey>),
+}
+
+fn parse_pem(pem: &str) -> Option<Parsed> {
+    if let Ok(key) = SigningKey::from_pkcs8_pem(pem) {
+        return Some(Parsed::Ed25519(key.verifying_key(), Some(key)));
+    }
+    if let Ok(key) = VerifyingKey::from_public_key_pem(pem) {
+        return Some(Parsed::Ed25519(key, None));
+    }
+    if let Ok(key) =
+        RsaPrivateKey::from_pkcs8_pem(pem).or_else(|_| RsaPrivateKey::from_pkcs1_pem(pem))
+    {
+        return Some(Parsed::Rsa(key.to_public_key(), Some(key)));
+    }
+    if let Ok(key) = RsaPublicKey::from_public_key_pem(pem) {
+        return Some(Parsed::Rsa(key, None));
+    }
+    None
+}
+
+fn jwk(kid: &str, key_algorithm: KeyAlgorithm, algorithm: AlgorithmParameters) -> Jwk {
+    Jwk {
+        common: CommonParameters {
+            public_key_use: Some(PublicKeyUse::Signature),
+            key_algorithm: Some(key_algorithm),
+            key_id: Some(kid.to_owned()),
+            ..Default::default()
+        },
+        algorithm,
+    }
+}
+
+#[derive(Default)]
+struct Builder {
+    signing: Vec<SigningKeyEntry>,
+    verifying: HashMap<String, (Algorithm, DecodingKey)>,
+    jwks: Vec<Jwk>,
+}
+
+impl Builder {
+    fn add(&mut self, kid: &str, parsed: Parsed) -> anyhow::Result<()> {
+        let (algorithm, jwk, signing) = match parsed {
+            Parsed::Ed25519(public, private) => {
+                let jwk = jwk(
+                    kid,
+                    KeyAlgorithm::EdDSA,
+                    AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                        key_type: OctetKeyPairType::OctetKeyPair,
+                        curve: EllipticCurve::Ed25519,
+                        x: URL_SAFE_NO_PAD.encode(public.as_bytes()),
+                    }),
+                );
+                let signing = match private {
+                    Some(key) => Some(EncodingKey::from_ed_der(key.to_pkcs8_der()?.as_bytes())),
+                    None => None,
+                };
+                (Algorithm::EdDSA, jwk, signing)
+            }
+            Parsed::Rsa(public, private) => {
+                let jwk = jwk(
+                    kid,
+                    KeyAlgorithm::RS256,
+                    AlgorithmParameters::RSA(RSAKeyParameters {
+                        key_type: RSAKeyType::RSA,
+                        n: URL_SAFE_NO_PAD.encode(public.n().to_bytes_be()),
+                        e: URL_SAFE_NO_PAD.encode(public.e().to_bytes_be()),
+                    }),
+                );
+                let signing = match private {
+                    Some(key) => Some(EncodingKey::from_rsa_der(key.to_pkcs1_der()?.as_bytes())),
+                    None => None,
+                };
+                (Algorithm::RS256, jwk, signing)
+            }
+        };
+
+        self.verifying
+            .insert(kid.to_owned(), (algorithm, DecodingKey::from_jwk(&jwk)?));
+        self.jwks.push(jwk);
+        if let Some(key) = signing {
+            self.signing.push(SigningKeyEntry {
+                kid: kid.to_owned(),
+                algorithm,
+                key,
+            });
+        }
+        Ok(())
+    }
+
+    fn build(mut self, signing_kid: Option<String>) -> anyhow::Result<JwtKeys> {
+        let position = match signing_kid {
+            Some(kid) => self
+                .signing
+                .iter()
+                .position(|entry| entry.kid == kid)
+                .with_context(|| format!("JWT_SIGNING_KID {} is not a private key", kid))?,
+            None if self.signing.len() == 1 => 0,
+            None if self.signing.is_empty() => anyhow::bail!("No private key to sign tokens with"),
+            None => anyhow::bail!("Several private keys found, set JWT_SIGNING_KID"),
+        };
+        Ok(JwtKeys {
+            signing: self.signing.swap_remove(position),
+            verifying: self.verifying,
+            jwks: JwkSet { keys: self.jwks },
+        })
+    }
+}
+
+fn load_dir(dir: &Path) -> anyhow::Result<Builder> {
+    let mut builder = Builder::default();
+    let entries = std::fs::read_dir(dir)
+        .with_context(|| format!("Could not read JWT_KEYS_DIR {}", dir.display()))?;
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_none_or(|extension| extension != "pem") {
+            continue;
+        }
+        let Some(kid) = path.file_stem().and_then(|stem| stem.to_str()) else {
+            continue;
+        };
+        let pem = std::fs::read_to_string(&path)
+            .with_context(|| format!("Could not read {}", path.display()))?;
+        let parsed = parse_pem(&pem).with_context(|| {
+            format!(
+                "{} is not an Ed25519 or RSA key in a supported format",
+                path.display()
+            )
+        })?;
+        builder.add(kid, parsed)?;
+    }
+    Ok(builder)
+}
+
+fn required(var: &str) -> anyhow::Result<Vec<u8>> {
+    let value = std::env::var(var).with_context(|| format!("{} is not set", var))?;
+    anyhow::ensure!(!value.is_empty(), "{} is empty", var);
+    Ok(value.into_bytes())
+}
+
+fn load_secrets() -> anyhow::Result<Secrets> {
+    let mfa = required("SECRET_WORD_MFA")?;
+    Ok(Secrets {
+        salt: required("SECRET_WORD_SALT")?,
+        mfa_encoding: EncodingKey::from_secret(&mfa),
+        mfa_decoding: DecodingKey::from_secret(&mfa),
+    })
+}
+
+/// Loads the keys and secrets once at startup, without `JWT_KEYS_DIR` an Ed25519 key is generated
+/// and access tokens stop working on restart (clients just refresh)
+pub fn load() -> anyhow::Result<()> {
+    let secrets = load_secrets()?;
+    let keys = match std::env::var("JWT_KEYS_DIR") {
+        Ok(dir) => load_dir(Path::new(&dir))?.build(std::env::var("JWT_SIGNING_KID").ok())?,
+        Err(_) => {
+            tracing::warn!("JWT_KEYS_DIR is not set, signing tokens with a throwaway key");
+            let key = SigningKey::from_bytes(&rand::random::<[u8; 32]>());
+            let kid = hex::encode(rand::random::<[u8; 8]>());
+            let mut builder = Builder::default();
+            builder.add(&kid, Parsed::Ed25519(key.verifying_key(), Some(key)))?;
+            builder.build(None)?
+        }
+    };
+    tracing::info!(
+        "Signing tokens with {} ({:?}), {} verification keys",
+        keys.signing.kid,
+        keys.signing.algorithm,
+        keys.jwks.keys.len()
+    );
+    KEYS.set(keys)
+        .map_err(|_| anyhow::anyhow!("JWT keys are already loaded"))?;
+    SECRETS
+        .set(secrets)
+        .map_err(|_| anyhow::anyhow!("Secrets are already loaded"))
+}
+
+pub fn get() -> &'static JwtKeys {
+    KEYS.get().expect("JWT keys are loaded at startup")
+}
+
+pub fn secrets() -> &'static Secrets {
+    SECRETS.get().expect("Secrets are loaded at startup")
+}
@@ -1,3 +1,7 @@
 pub mod encryption;
+pub mod keys;
 pub mod password;
+pub mod srp;
 pub mod token;
+pub mod totp;
+pub mod webauthn;
@@ -0,0 +1,113 @@
+//! SRP-6a (RFC 5054 3072-bit group, SHA-256) for logging in without sending the password.
+//!
+//! What the client computes, `H` is SHA-256 and `|` concatenation, numbers are big-endian
+//! without leading zeroes unless `PAD`ded to the length of `N`:
+//! - `x = H(s | H(":" | P))`, `v = g^x`. The identity is left out of `x` so changing
+//!   the email doesn't invalidate the verifier
+//! - `k = H(N | PAD(g))`, `u = H(PAD(A) | PAD(B))`
+//! - `S = (B - k * g^x) ^ (a + u * x)`, `K = H(S)`
+//! - `M1 = H(H(N) xor H(g) | H("") | s | A | B | K)`, the server answers with `M2 = H(A | M1 | K)`
+
+use num_bigint::BigUint;
+use sha2::{Digest, Sha256};
+
+use crate::crypt::keys;
+
+const N_HEX: &str = concat!(
+    "FFFFFFFFFFFFFFFFC90FDAA22168C234C4C6628B80DC1CD129024E088A67CC74",
+    "020BBEA63B139B22514A08798E3404DDEF9519B3CD3A431B302B0A6DF25F1437",
+    "4FE1356D6D51C245E485B576625E7EC6F44C42E9A637ED6B0BFF5CB6F406B7ED",
+    "EE386BFB5A899FA5AE9F24117C4B1FE649286651ECE45B3DC2007CB8A163BF05",
+    "98DA48361C55D39A69163FA8FD24CF5F83655D23DCA3AD961C62F356208552BB",
+    "9ED529077096966D670C354E4ABC9804F1746C08CA18217C32905E462E36CE3B",
+    "E39E772C180E86039B2783A2EC07A28FB5C55DF06F4C52C9DE2BCBF695581718",
+    "3995497CEA956AE515D2261898FA051015728E5A8AAAC42DAD33170D04507A33",
+    "A85521ABDF1CBA64ECFB850458DBEF0A8AEA71575D060C7DB3970F85A6E1E4C7",
+    "ABF5AE8CDB0933D71E8C94E04A25619DCEE3D2261AD2EE6BF12FFA06D98A0864",
+    "D87602733EC86A64521F2B18177B200CBBE117577A615D6C770988C0BAD946E2",
+    "08E24FA074E5AB3143DB5BFCE0FD108E4B82D120A93AD2CAFFFFFFFFFFFFFFFF",
+);
+
+const G: u32 = 5;
+
+/// Lengths of `N` and everything that's padded to it
+pub const N_LEN: usize = 384;
+
+fn n() -> BigUint {
+    BigUint::parse_bytes(N_HEX.as_bytes(), 16).unwrap()
+}
+
+fn h(parts: &[&[u8]]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finalize().into()
+}
+
+fn pad(value: &BigUint) -> Vec<u8> {
+    let bytes = value.to_bytes_be();
+    let mut padded = vec![0; N_LEN.saturating_sub(bytes.len())];
+    padded.extend_from_slice(&bytes);
+    padded
+}
+
+/// Verifiers have to be usable numbers, i.e. `0 < v < N`
+pub fn is_valid_verifier(verifier: &[u8]) -> bool {
+    let v = BigUint::from_bytes_be(verifier);
+    v > BigUint::ZERO && v < n()
+}
+
+/// Server half of a login, only the proofs need to be kept around until the client answers
+pub struct ServerSession {
+    pub b_pub: Vec<u8>,
+    pub client_proof: [u8; 32],
+    pub server_proof: [u8; 32],
+}
+
+/// Answers the client's `A`. `None` if `A` is unusable (`A % N == 0`)
+pub fn start(salt: &[u8], verifier: &[u8], a_pub: &[u8]) -> Option<ServerSession> {
+    let n = n();
+    let g = BigUint::from(G);
+    let a = BigUint::from_bytes_be(a_pub);
+    if (&a % &n) == BigUint::ZERO {
+        return None;
+    }
+    let v = BigUint::from_bytes_be(verifier);
+    let k = BigUint::from_bytes_be(&h(&[&n.to_bytes_be(), &pad(&g)]));
+
+    let (b, b_pub) = loop {
+        let b = BigUint::from_bytes_be(&rand::random::<[u8; 32]>());
+        let b_pub = (&k * &v + g.modpow(&b, &n)) % &n;
+        if b_pub != BigUint::ZERO {
+            break (b, b_pub);
+        }
+    };
+    let u = BigUint::from_bytes_be(&h(&[&pad(&a), &pad(&b_pub)]));
+    if u == BigUint::ZERO {
+        return None;
+    }
+
+    let s = (&a * v.modpow(&u, &n)).modpow(&b, &n);
+    let key = h(&[&s.to_bytes_be()]);
+
+    let hn = h(&[&n.to_bytes_be()]);
+    let hg = h(&[&g.to_bytes_be()]);
+    let hn_xor_hg: Vec<u8> = hn.iter().zip(hg.iter()).map(|(x, y)| x ^ y).collect();
+    let a_bytes = a.to_bytes_be();
+    let b_bytes = b_pub.to_bytes_be();
+    let client_proof = h(&[&hn_xor_hg, &h(&[b""]), salt, &a_bytes, &b_bytes, &key]);
+    let server_proof = h(&[&a_bytes, &client_proof, &key]);
+
+    Some(ServerSession {
+        b_pub: b_bytes,
+        client_proof,
+        server_proof,
+    })
+}
+
+/// Salt handed out for emails nobody registered, so they look like any other account
+pub fn fake_salt(email: &str) -> Vec<u8> {
+    let secret = &keys::secrets().salt;
+    h(&[secret, b"srp-salt", email.as_bytes()])[..16].to_vec()
+}
@@ -1,18 +1,69 @@
+use std::{
+    collections::HashSet,
+    sync::{LazyLock, RwLock},
+};
+
+use anyhow::Context;
 use axum::{
     extract::FromRequestParts,
     http::StatusCode,
     response::{IntoResponse, Response},
 };
-use chrono::{Duration, Utc};
-use jsonwebtoken::{decode, DecodingKey, EncodingKey, Header, Validation};
+use axum_extra::extract::CookieJar;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Duration, Utc};
+use jsonwebtoken::{decode, decode_header, Header, Validation};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+
+use crate::{
+    common::error::{ErrorResponse, ErrorTypes},
+    crypt::keys,
+};
+
+pub const ACCESS_TOKEN_TTL: Duration = Duration::hours(1);
+
+/// Cookie mode: the refresh token lives in this HttpOnly cookie, and its CSRF token in a
+/// readable one the client echoes back in `X-CSRF-Token`
+pub const REFRESH_COOKIE: &str = "refresh_token";
+pub const CSRF_COOKIE: &str = "csrf_token";
+pub const CSRF_HEADER: &str = "X-CSRF-Token";
+
+/// `jti`s of revoked access tokens that haven't expired yet. The database has
+/// the full list, this copy lets every request check it without a query
+static DENYLIST: LazyLock<RwLock<HashSet<String>>> = LazyLock::new(Default::default);
+
+/// `JWT_ISSUER`, what other services expect in `iss`
+pub fn issuer() -> String {
+    std::env::var("JWT_ISSUER").unwrap_or_else(|_| "password-manager-backend".to_owned())
+}
 
-use crate::common::error::{ErrorResponse, ErrorTypes};
+/// `JWT_AUDIENCE`, what other services expect in `aud`
+pub fn audience() -> String {
+    std::env::var("JWT_AUDIENCE").unwrap_or_else(|_| "password-manager".to_owned())
+}
 
 #[derive(Serialize, Deserialize)]
 pub struct Claims {
     pub id: u32,
+    /// Login the token was issued for, the family id of its refresh token
+    pub sid: String,
+    pub jti: String,
+    pub iss: String,
+    pub aud: String,
+    pub iat: i64,
+    pub exp: i64,
+}
+
+/// Proves the password was right, but the second factor is still missing
+#[derive(Serialize, Deserialize)]
+pub struct MfaClaims {
+    pub id: u32,
+    /// Its row counts the attempts and is deleted once the token is used
+    pub jti: String,
     pub exp: i64,
+    pub device_name: Option<String>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -21,10 +72,12 @@ pub struct AuthHeader {
     pub token: String,
 }
 
+/// Opaque refresh token, only the database knows who it belongs to and whether it's valid
 #[derive(Serialize, Deserialize)]
 pub struct RefreshHeader {
-    pub claims: Claims,
     pub token: String,
+    /// Came from the session cookie rather than the `Authorization` header
+    pub from_cookie: bool,
 }
 
 impl<S: std::marker::Sync> FromRequestParts<S> for AuthHeader {
@@ -52,12 +105,7 @@ impl<S: std::marker::Sync> FromRequestParts<S> for AuthHeader {
                     .into_response()
             })?;
 
-        let claims = decode::<Claims>(
-            token,
-            &DecodingKey::from_secret(std::env::var("SECRET_WORD_JWT").unwrap().as_ref()),
-            &Validation::default(),
-        )
-        .map_err(|err| {
+        let claims = decode_claims(token).map_err(|err| {
             tracing::error!("Could not validate: {}", err);
             (
                 StatusCode::UNAUTHORIZED,
@@ -67,8 +115,7 @@ impl<S: std::marker::Sync> FromRequestParts<S> for AuthHeader {
                 )),
             )
                 .into_response()
-        })?
-        .claims;
+        })?;
 
         Ok(AuthHeader {
             claims,
@@ -84,82 +131,166 @@ impl<S: std::marker::Sync> FromRequestParts<S> for RefreshHeader {
         parts: &mut axum::http::request::Parts,
         _: &S,
     ) -> Result<Self, Self::Rejection> {
-        let token = parts
+        let header = parts
             .headers
             .get("Authorization")
             .and_then(|value| value.to_str().ok())
             .and_then(|s| s.split_whitespace().last())
-            .ok_or("Missing header")
-            .map_err(|why| {
-                tracing::error!("{}", why);
-                (
-                    StatusCode::BAD_REQUEST,
-                    axum::Json(ErrorResponse::new(
-                        ErrorTypes::NoAuthHeader,
-                        "No auth header",
-                    )),
-                )
-                    .into_response()
-            })?;
+            .map(|token| (token.to_owned(), false));
+        let cookie = || {
+            CookieJar::from_headers(&parts.headers)
+                .get(REFRESH_COOKIE)
+                .map(|cookie| (cookie.value().to_owned(), true))
+        };
+        let (token, from_cookie) =
+            header
+                .or_else(cookie)
+                .ok_or("Missing header")
+                .map_err(|why| {
+                    tracing::error!("{}", why);
+                    (
+                        StatusCode::BAD_REQUEST,
+                        axum::Json(ErrorResponse::new(
+                            ErrorTypes::NoAuthHeader,
+                            "No auth header",
+                        )),
+                    )
+                        .into_response()
+                })?;
 
-        let claims = decode::<Claims>(
-            token,
-            &DecodingKey::from_secret(std::env::var("SECRET_WORD_REFRESH").unwrap().as_ref()),
-            &Validation::default(),
-        )
-        .map_err(|err| {
-            tracing::error!("Could not validate: {}", err);
-            (
+        // Anything not shaped like `make_refresh_token` output can't be in the database
+        if URL_SAFE_NO_PAD
+            .decode(&token)
+            .map_or(true, |bytes| bytes.len() != 32)
+        {
+            return Err((
                 StatusCode::UNAUTHORIZED,
                 axum::Json(ErrorResponse::new(
-                    ErrorTypes::JwtTokenExpired,
-                    "Token expired",
+                    ErrorTypes::RefreshTokenExpired,
+                    "Malformed refresh token",
                 )),
             )
-                .into_response()
-        })?
-        .claims;
+                .into_response());
+        }
 
-        Ok(RefreshHeader {
-            claims,
-            token: token.to_owned(),
-        })
+        // The browser attaches the cookie to any request, so the client has to prove
+        // it can read the CSRF cookie, which only pages of our own origin can
+        if from_cookie {
+            let expected = csrf_token(&token);
+            let matches = parts
+                .headers
+                .get(CSRF_HEADER)
+                .is_some_and(|value| bool::from(value.as_bytes().ct_eq(expected.as_bytes())));
+            if !matches {
+                return Err((
+                    StatusCode::FORBIDDEN,
+                    axum::Json(ErrorResponse::new(
+                        ErrorTypes::CsrfTokenMismatch,
+                        "Missing or wrong CSRF token",
+                    )),
+                )
+                    .into_response());
+            }
+        }
+
+        Ok(RefreshHeader { token, from_cookie })
     }
 }
 
-pub fn make_jwt_token(user_id: u32) -> String {
+/// Tied to the refresh token, so a planted CSRF cookie is useless without the real session
+pub fn csrf_token(refresh_token: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(b"csrf:");
+    hasher.update(refresh_token.as_bytes());
+    hex::encode(hasher.finalize())
+}
+
+/// Returns the token along with its claims, whoever issues it has to remember the `jti`
+pub fn make_jwt_token(user_id: u32, session_id: &str) -> (String, Claims) {
+    let now = Utc::now();
     let claims = Claims {
         id: user_id,
-        exp: (Utc::now() + Duration::hours(1)).timestamp(),
+        sid: session_id.to_owned(),
+        jti: hex::encode(rand::random::<[u8; 16]>()),
+        iss: issuer(),
+        aud: audience(),
+        iat: now.timestamp(),
+        exp: (now + ACCESS_TOKEN_TTL).timestamp(),
+    };
+    let signing = &keys::get().signing;
+    let header = Header {
+        kid: Some(signing.kid.clone()),
+        ..Header::new(signing.algorithm)
     };
-    jsonwebtoken::encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(std::env::var("SECRET_WORD_JWT").unwrap().as_ref()),
-    )
-    .unwrap()
+    let token = jsonwebtoken::encode(&header, &claims, &signing.key).unwrap();
+    (token, claims)
 }
 
-pub fn make_refresh_token(user_id: u32) -> String {
-    let claims = Claims {
+/// The `kid` picks the key and the key fixes the algorithm, whatever `alg` the token claims
+fn decode_claims(token: &str) -> anyhow::Result<Claims> {
+    let header = decode_header(token)?;
+    let kid = header.kid.context("Token has no kid")?;
+    let Some((algorithm, key)) = keys::get().verifying(&kid) else {
+        anyhow::bail!("Token was signed with unknown key {}", kid);
+    };
+
+    let mut validation = Validation::new(*algorithm);
+    validation.set_issuer(&[issuer()]);
+    validation.set_audience(&[audience()]);
+    validation.set_required_spec_claims(&["exp", "iss", "aud"]);
+    let claims = decode::<Claims>(token, key, &validation)?.claims;
+
+    if claims.iat > Utc::now().timestamp() + validation.leeway as i64 {
+        anyhow::bail!("Token was issued in the future");
+    }
+    if DENYLIST.read().unwrap().contains(&claims.jti) {
+        anyhow::bail!("Token {} was revoked", claims.jti);
+    }
+    Ok(claims)
+}
+
+pub fn set_denylist(jtis: HashSet<String>) {
+    *DENYLIST.write().unwrap() = jtis;
+}
+
+pub const MFA_TOKEN_TTL: Duration = Duration::minutes(5);
+
+// Signed with its own secret, so it can never pass as an access token
+pub fn make_mfa_token(
+    user_id: u32,
+    jti: &str,
+    expires_at: DateTime<Utc>,
+    device_name: Option<String>,
+) -> String {
+    let claims = MfaClaims {
         id: user_id,
-        exp: (Utc::now() + Duration::days(7)).timestamp(),
+        jti: jti.to_owned(),
+        exp: expires_at.timestamp(),
+        device_name,
     };
-    jsonwebtoken::encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(std::env::var("SECRET_WORD_REFRESH").unwrap().as_ref()),
-    )
-    .unwrap()
+    jsonwebtoken::encode(&Header::default(), &claims, &keys::secrets().mfa_encoding).unwrap()
+}
+
+pub fn verify_mfa_token(token: &str) -> anyhow::Result<MfaClaims> {
+    let claims = decode::<MfaClaims>(token, &keys::secrets().mfa_decoding, &Validation::default())?;
+    Ok(claims.claims)
+}
+
+pub const REFRESH_TOKEN_TTL: Duration = Duration::days(7);
+
+/// Password reset tokens share the refresh token format and hashing
+pub const RESET_TOKEN_TTL: Duration = Duration::hours(1);
+
+/// 256 random bits, url safe
+pub fn make_refresh_token() -> String {
+    URL_SAFE_NO_PAD.encode(rand::random::<[u8; 32]>())
+}
+
+/// Refresh tokens are only ever stored as this hash
+pub fn hash_refresh_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
 }
 
 pub fn verify_jwt_token(token: &str) -> anyhow::Result<u32> {
-    let validation = Validation::default();
-
-    let claims = decode::<Claims>(
-        token,
-        &DecodingKey::from_secret(std::env::var("SECRET_WORD_JWT").unwrap().as_ref()),
-        &validation,
-    )?;
-    Ok(claims.claims.id)
+    Ok(decode_claims(token)?.id)
 }
@@ -0,0 +1,46 @@
+use totp_rs::{Algorithm, Secret, TOTP};
+
+const ISSUER: &str = "PasswordManager";
+const DIGITS: usize = 6;
+const STEP: u64 = 30;
+
+fn totp(secret: &str, account: &str) -> anyhow::Result<TOTP> {
+    let secret = Secret::Encoded(secret.to_owned())
+        .to_bytes()
+        .map_err(|why| anyhow::anyhow!("Invalid TOTP secret: {:?}", why))?;
+    // Skew is handled in `verify_code`, which needs to know the matching step
+    let totp = TOTP::new(
+        Algorithm::SHA1,
+        DIGITS,
+        0,
+        STEP,
+        secret,
+        Some(ISSUER.to_owned()),
+        account.replace(':', "_"),
+    )?;
+    Ok(totp)
+}
+
+/// 160 random bits, base32 encoded as authenticator apps expect
+pub fn generate_secret() -> String {
+    Secret::Raw(rand::random::<[u8; 20]>().to_vec())
+        .to_encoded()
+        .to_string()
+}
+
+pub fn otpauth_url(secret: &str, account: &str) -> anyhow::Result<String> {
+    Ok(totp(secret, account)?.get_url())
+}
+
+/// Returns the time step `code` belongs to, allowing one step of clock drift either way
+pub fn verify_code(secret: &str, code: &str) -> anyhow::Result<Option<u64>> {
+    let totp = totp(secret, "")?;
+    let now = chrono::Utc::now().timestamp() as u64;
+    let step = now / STEP;
+    for candidate in [step - 1, step, step + 1] {
+        if totp.check(code, candidate * STEP) {
+            return Ok(Some(candidate));
+        }
+    }
+    Ok(None)
+}
@@ -0,0 +1,488 @@
+use std::fmt;
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use ciborium::Value;
+use p256::ecdsa::{signature::Verifier, Signature, VerifyingKey};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+// COSE algorithm identifier for ECDSA P-256 with SHA-256, the one every authenticator supports
+pub const ES256: i32 = -7;
+
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_USER_VERIFIED: u8 = 0x04;
+const FLAG_ATTESTED_DATA: u8 = 0x40;
+
+/// The authenticator response did not check out, the client is at fault
+#[derive(Debug)]
+pub struct Rejected(pub String);
+
+impl fmt::Display for Rejected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for Rejected {}
+
+fn reject<T>(why: &str) -> anyhow::Result<T> {
+    Err(Rejected(why.to_owned()).into())
+}
+
+/// Relying party settings, `WEBAUTHN_RP_ID`, `WEBAUTHN_RP_NAME` and comma separated `WEBAUTHN_ORIGINS`
+pub struct RelyingParty {
+    pub id: String,
+    pub name: String,
+    pub origins: Vec<String>,
+}
+
+impl RelyingParty {
+    pub fn from_env() -> Self {
+        let id = std::env::var("WEBAUTHN_RP_ID").unwrap_or("localhost".to_string());
+        let origins = std::env::var("WEBAUTHN_ORIGINS")
+            .unwrap_or(format!("https://{}", id))
+            .split(',')
+            .map(|origin| origin.trim().to_owned())
+            .filter(|origin| !origin.is_empty())
+            .collect();
+        Self {
+            name: std::env::var("WEBAUTHN_RP_NAME").unwrap_or("PasswordManager".to_string()),
+            id,
+            origins,
+        }
+    }
+}
+
+/// Browsers send binary fields base64url encoded, some with padding
+pub fn decode_b64(value: &str) -> anyhow::Result<Vec<u8>> {
+    match URL_SAFE_NO_PAD.decode(value.trim_end_matches('=')) {
+        Ok(bytes) => Ok(bytes),
+        Err(_) => reject("Malformed base64url value"),
+    }
+}
+
+pub fn encode_b64(value: &[u8]) -> String {
+    URL_SAFE_NO_PAD.encode(value)
+}
+
+#[derive(Deserialize)]
+struct ClientData {
+    #[serde(rename = "type")]
+    kind: String,
+    challenge: String,
+    origin: String,
+}
+
+fn check_client_data(
+    rp: &RelyingParty,
+    client_data_json: &[u8],
+    kind: &str,
+    challenge: &[u8],
+) -> anyhow::Result<()> {
+    let Ok(client_data) = serde_json::from_slice::<ClientData>(client_data_json) else {
+        return reject("Malformed clientDataJSON");
+    };
+    if client_data.kind != kind {
+        return reject("Unexpected ceremony type");
+    }
+    if decode_b64(&client_data.challenge)? != challenge {
+        return reject("Challenge does not match");
+    }
+    if !rp.origins.contains(&client_data.origin) {
+        return reject("Origin is not allowed");
+    }
+    Ok(())
+}
+
+struct AuthenticatorData<'a> {
+    flags: u8,
+    sign_count: u32,
+    // Only present during registration
+    attested: &'a [u8],
+}
+
+fn parse_authenticator_data<'a>(
+    rp: &RelyingParty,
+    data: &'a [u8],
+) -> anyhow::Result<AuthenticatorData<'a>> {
+    if data.len() < 37 {
+        return reject("Authenticator data is too short");
+    }
+    if data[..32] != Sha256::digest(rp.id.as_bytes())[..] {
+        return reject("Credential is for another relying party");
+    }
+    let flags = data[32];
+    if flags & FLAG_USER_PRESENT == 0 {
+        return reject("User was not present");
+    }
+    Ok(AuthenticatorData {
+        flags,
+        sign_count: u32::from_be_bytes([data[33], data[34], data[35], data[36]]),
+        attested: &data[37..],
+    })
+}
+
+fn cose_field(map: &[(Value, Value)], label: i64) -> Option<&Value> {
+    map.iter()
+        .find(|(key, _)| key.as_integer().map(i128::from) == Some(label as i128))
+        .map(|(_, value)| value)
+}
+
+/// Turns a COSE_Key into an uncompressed SEC1 point, only EC2 P-256 keys are supported
+fn cose_to_sec1(key: &Value) -> anyhow::Result<Vec<u8>> {
+    let Some(map) = key.as_map() else {
+        return reject("Malformed credential public key");
+    };
+    let int = |label| {
+        cose_field(map, label)
+            .and_then(Value::as_integer)
+            .map(i128::from)
+    };
+    if int(1) != Some(2) || int(3) != Some(ES256 as i128) || int(-1) != Some(1) {
+        return reject("Only ES256 credentials are supported");
+    }
+    let (Some(x), Some(y)) = (
+        cose_field(map, -2).and_then(Value::as_bytes),
+        cose_field(map, -3).and_then(Value::as_bytes),
+    ) else {
+        return reject("Malformed credential public key");
+    };
+
+    let mut sec1 = Vec::with_capacity(65);
+    sec1.push(0x04);
+    sec1.extend_from_slice(x);
+    sec1.extend_from_slice(y);
+    if VerifyingKey::from_sec1_bytes(&sec1).is_err() {
+        return reject("Credential public key is not on the curve");
+    }
+    Ok(sec1)
+}
+
+pub struct RegisteredCredential {
+    pub credential_id: Vec<u8>,
+    pub public_key: Vec<u8>,
+    pub sign_count: u32,
+}
+
+/// Checks a `navigator.credentials.create()` response. Attestation statements are not
+/// verified, we ask for `none` and trust the key the same way we trust a password
+pub fn verify_registration(
+    rp: &RelyingParty,
+    challenge: &[u8],
+    client_data_json: &[u8],
+    attestation_object: &[u8],
+) -> anyhow::Result<RegisteredCredential> {
+    check_client_data(rp, client_data_json, "webauthn.create", challenge)?;
+
+    let Ok(attestation) = ciborium::from_reader::<Value, _>(attestation_object) else {
+        return reject("Malformed attestationObject");
+    };
+    let Some(auth_data) = attestation.as_map().and_then(|map| {
+        map.iter()
+            .find(|(key, _)| key.as_text() == Some("authData"))
+            .and_then(|(_, value)| value.as_bytes())
+    }) else {
+        return reject("attestationObject has no authData");
+    };
+
+    let auth_data = parse_authenticator_data(rp, auth_data)?;
+    if auth_data.flags & FLAG_ATTESTED_DATA == 0 || auth_data.attested.len() < 18 {
+        return reject("No attested credential data");
+    }
+    // AAGUID, then a 2 byte length and the credential id, then the COSE key
+    let attested = &auth_data.attested[16..];
+    let id_len = u16::from_be_bytes([attested[0], attested[1]]) as usize;
+    if attested.len() < 2 + id_len {
+        return reject("Credential id is truncated");
+    }
+    let credential_id = attested[2..2 + id_len].to_vec();
+    let mut rest = &attested[2 + id_len..];
+    let Ok(cose_key) = ciborium::from_reader::<Value, _>(&mut rest) else {
+        return reject("Malformed credential public key");
+    };
+
+    Ok(RegisteredCredential {
+        credential_id,
+        public_key: cose_to_sec1(&cose_key)?,
+        sign_count: auth_data.sign_count,
+    })
+}
+
+pub struct Assertion {
+    pub sign_count: u32,
+    pub user_verified: bool,
+}
+
+/// Checks a `navigator.credentials.get()` response against the stored public key
+pub fn verify_assertion(
+    rp: &RelyingParty,
+    challenge: &[u8],
+    public_key: &[u8],
+    client_data_json: &[u8],
+    authenticator_data: &[u8],
+    signature: &[u8],
+) -> anyhow::Result<Assertion> {
+    check_client_data(rp, client_data_json, "webauthn.get", challenge)?;
+    let auth_data = parse_authenticator_data(rp, authenticator_data)?;
+
+    let Ok(key) = VerifyingKey::from_sec1_bytes(public_key) else {
+        anyhow::bail!("Stored public key is invalid");
+    };
+    let Ok(signature) = Signature::from_der(signature) else {
+        return reject("Malformed signature");
+    };
+    let mut signed = authenticator_data.to_vec();
+    signed.extend_from_slice(&Sha256::digest(client_data_json));
+    if key.verify(&signed, &signature).is_err() {
+        return reject("Signature does not match");
+    }
+
+    Ok(Assertion {
+        sign_count: auth_data.sign_count,
+        user_verified: auth_data.flags & FLAG_USER_VERIFIED != 0,
+    })
+}
+
+/// A counter that doesn't grow hints at a cloned authenticator. Authenticators without one
+/// always report 0 and are let through
+pub fn sign_count_increased(stored: u32, received: u32) -> bool {
+    received > stored || (received == 0 && stored == 0)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use p256::ecdsa::{signature::Signer, SigningKey};
+
+    use super::*;
+
+    pub(crate) fn relying_party() -> RelyingParty {
+        RelyingParty {
+            id: "localhost".to_owned(),
+            name: "PasswordManager".to_owned(),
+            origins: vec!["https://localhost".to_owned()],
+        }
+    }
+
+    /// Answers ceremonies the way a security key with a `none` attestation would
+    pub(crate) struct Authenticator {
+        key: SigningKey,
+        pub(crate) credential_id: Vec<u8>,
+        pub(crate) rp_id: String,
+        pub(crate) origin: String,
+        pub(crate) sign_count: u32,
+    }
+
+    impl Authenticator {
+        pub(crate) fn new() -> Self {
+            Self {
+                key: SigningKey::from_slice(&rand::random::<[u8; 32]>()).unwrap(),
+                credential_id: rand::random::<[u8; 16]>().to_vec(),
+                rp_id: "localhost".to_owned(),
+                origin: "https://localhost".to_owned(),
+                sign_count: 0,
+            }
+        }
+
+        fn client_data(&self, kind: &str, challenge: &[u8]) -> Vec<u8> {
+            serde_json::json!({
+                "type": kind,
+                "challenge": encode_b64(challenge),
+                "origin": self.origin,
+            })
+            .to_string()
+            .into_bytes()
+        }
+
+        fn authenticator_data(&self, flags: u8) -> Vec<u8> {
+            let mut data = Sha256::digest(self.rp_id.as_bytes()).to_vec();
+            data.push(flags);
+            data.extend_from_slice(&self.sign_count.to_be_bytes());
+            data
+        }
+
+        /// Returns `clientDataJSON` and `attestationObject`
+        pub(crate) fn register(&self, challenge: &[u8]) -> (Vec<u8>, Vec<u8>) {
+            let mut auth_data = self.authenticator_data(FLAG_USER_PRESENT | FLAG_ATTESTED_DATA);
+            auth_data.extend_from_slice(&[0; 16]);
+            auth_data.extend_from_slice(&(self.credential_id.len() as u16).to_be_bytes());
+            auth_data.extend_from_slice(&self.credential_id);
+            let point = self.key.verifying_key().to_encoded_point(false);
+            let cose_key = Value::Map(vec![
+                (Value::from(1), Value::from(2)),
+                (Value::from(3), Value::from(ES256)),
+                (Value::from(-1), Value::from(1)),
+                (Value::from(-2), Value::Bytes(point.x().unwrap().to_vec())),
+                (Value::from(-3), Value::Bytes(point.y().unwrap().to_vec())),
+            ]);
+            ciborium::into_writer(&cose_key, &mut auth_data).unwrap();
+
+            let attestation = Value::Map(vec![
+                (Value::from("fmt"), Value::from("none")),
+                (Value::from("attStmt"), Value::Map(Vec::new())),
+                (Value::from("authData"), Value::Bytes(auth_data)),
+            ]);
+            let mut attestation_object = Vec::new();
+            ciborium::into_writer(&attestation, &mut attestation_object).unwrap();
+            (
+                self.client_data("webauthn.create", challenge),
+                attestation_object,
+            )
+        }
+
+        /// Bumps the counter, returns `clientDataJSON`, `authenticatorData` and the signature
+        pub(crate) fn assert(&mut self, challenge: &[u8]) -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+            self.sign_count += 1;
+            let client_data = self.client_data("webauthn.get", challenge);
+            let auth_data = self.authenticator_data(FLAG_USER_PRESENT | FLAG_USER_VERIFIED);
+            let mut signed = auth_data.clone();
+            signed.extend_from_slice(&Sha256::digest(&client_data));
+            let signature: Signature = self.key.sign(&signed);
+            (
+                client_data,
+                auth_data,
+                signature.to_der().as_bytes().to_vec(),
+            )
+        }
+    }
+
+    fn rejection<T>(result: anyhow::Result<T>) -> String {
+        match result {
+            Ok(_) => panic!("response was accepted"),
+            Err(why) => why.downcast::<Rejected>().unwrap().0,
+        }
+    }
+
+    fn registered(authenticator: &Authenticator) -> RegisteredCredential {
+        let (client_data, attestation) = authenticator.register(&[1; 32]);
+        verify_registration(&relying_party(), &[1; 32], &client_data, &attestation).unwrap()
+    }
+
+    fn login(
+        authenticator: &mut Authenticator,
+        credential: &RegisteredCredential,
+    ) -> anyhow::Result<Assertion> {
+        let (client_data, auth_data, signature) = authenticator.assert(&[2; 32]);
+        verify_assertion(
+            &relying_party(),
+            &[2; 32],
+            &credential.public_key,
+            &client_data,
+            &auth_data,
+            &signature,
+        )
+    }
+
+    #[test]
+    fn registers_and_logs_in() {
+        let mut authenticator = Authenticator::new();
+        let credential = registered(&authenticator);
+        assert_eq!(credential.credential_id, authenticator.credential_id);
+        assert_eq!(credential.sign_count, 0);
+
+        let assertion = login(&mut authenticator, &credential).unwrap();
+        assert_eq!(assertion.sign_count, 1);
+        assert!(assertion.user_verified);
+        assert!(sign_count_increased(
+            credential.sign_count,
+            assertion.sign_count
+        ));
+    }
+
+    #[test]
+    fn other_relying_party_is_rejected() {
+        let mut authenticator = Authenticator::new();
+        let credential = registered(&authenticator);
+        authenticator.rp_id = "evil.example".to_owned();
+
+        let (client_data, attestation) = authenticator.register(&[1; 32]);
+        assert_eq!(
+            rejection(verify_registration(
+                &relying_party(),
+                &[1; 32],
+                &client_data,
+                &attestation
+            )),
+            "Credential is for another relying party"
+        );
+        assert_eq!(
+            rejection(login(&mut authenticator, &credential)),
+            "Credential is for another relying party"
+        );
+    }
+
+    #[test]
+    fn other_origin_is_rejected() {
+        let mut authenticator = Authenticator::new();
+        let credential = registered(&authenticator);
+        authenticator.origin = "https://evil.example".to_owned();
+
+        let (client_data, attestation) = authenticator.register(&[1; 32]);
+        assert_eq!(
+            rejection(verify_registration(
+                &relying_party(),
+                &[1; 32],
+                &client_data,
+                &attestation
+            )),
+            "Origin is not allowed"
+        );
+        assert_eq!(
+            rejection(login(&mut authenticator, &credential)),
+            "Origin is not allowed"
+        );
+    }
+
+    #[test]
+    fn other_challenge_is_rejected() {
+        let mut authenticator = Authenticator::new();
+        let (client_data, attestation) = authenticator.register(&[1; 32]);
+        assert_eq!(
+            rejection(verify_registration(
+                &relying_party(),
+                &[3; 32],
+                &client_data,
+                &attestation
+            )),
+            "Challenge does not match"
+        );
+
+        let credential = registered(&authenticator);
+        let (client_data, auth_data, signature) = authenticator.assert(&[2; 32]);
+        assert_eq!(
+            rejection(verify_assertion(
+                &relying_party(),
+                &[3; 32],
+                &credential.public_key,
+                &client_data,
+                &auth_data,
+                &signature
+            )),
+            "Challenge does not match"
+        );
+    }
+
+    #[test]
+    fn other_key_is_rejected() {
+        let credential = registered(&Authenticator::new());
+        assert_eq!(
+            rejection(login(&mut Authenticator::new(), &credential)),
+            "Signature does not match"
+        );
+    }
+
+    #[test]
+    fn sign_count_has_to_grow() {
+        let mut authenticator = Authenticator::new();
+        let credential = registered(&authenticator);
+        let first = login(&mut authenticator, &credential).unwrap();
+
+        // A clone carries on from the counter the original had when it was copied
+        authenticator.sign_count = first.sign_count - 1;
+        let replayed = login(&mut authenticator, &credential).unwrap();
+        assert!(!sign_count_increased(first.sign_count, replayed.sign_count));
+        assert!(!sign_count_increased(5, 4));
+        assert!(sign_count_increased(5, 6));
+        // Authenticators without a counter stay at 0
+        assert!(sign_count_increased(0, 0));
+    }
+}
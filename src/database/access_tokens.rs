@@ -0,0 +1,75 @@
+use chrono::{DateTime, Utc};
+use sqlx::MySqlPool;
+
+pub async fn add_token(
+    pool: &MySqlPool,
+    jti: &str,
+    user_id: u32,
+    session_id: &str,
+    expires_at: DateTime<Utc>,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        "INSERT INTO access_tokens (jti, user_id, session_id, expires_at) VALUES (?, ?, ?, ?)",
+        jti,
+        user_id,
+        session_id,
+        expires_at
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn revoke_session(pool: &MySqlPool, session_id: &str) -> anyhow::Result<u64> {
+    let result = sqlx::query!(
+        "UPDATE access_tokens SET revoked = 1 WHERE session_id = ? AND expires_at > NOW()",
+        session_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+pub async fn revoke_user(pool: &MySqlPool, user_id: u32) -> anyhow::Result<u64> {
+    let result = sqlx::query!(
+        "UPDATE access_tokens SET revoked = 1 WHERE user_id = ? AND expires_at > NOW()",
+        user_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+/// Revokes everything of the user except what the `keep_session` login holds
+pub async fn revoke_other_sessions(
+    pool: &MySqlPool,
+    user_id: u32,
+    keep_session: &str,
+) -> anyhow::Result<u64> {
+    let result = sqlx::query!(
+        "UPDATE access_tokens SET revoked = 1 WHERE user_id = ? AND session_id <> ? AND expires_at > NOW()",
+        user_id,
+        keep_session
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+/// `jti`s of revoked tokens that would still pass signature and expiry checks
+pub async fn list_revoked(pool: &MySqlPool) -> anyhow::Result<Vec<String>> {
+    let rows = sqlx::query_scalar!(
+        "SELECT jti FROM access_tokens WHERE revoked = 1 AND expires_at > NOW()"
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+/// Returns amount of removed rows
+pub async fn delete_expired(pool: &MySqlPool) -> anyhow::Result<u64> {
+    let result = sqlx::query!("DELETE FROM access_tokens WHERE expires_at <= NOW()")
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected())
+}
@@ -0,0 +1,50 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::MySqlPool;
+
+#[derive(Serialize)]
+pub struct ActivityEntry {
+    pub event: String,
+    pub detail: Option<String>,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+pub async fn add_entry(
+    pool: &MySqlPool,
+    user_id: u32,
+    event: &str,
+    detail: Option<&str>,
+    user_agent: Option<&str>,
+    ip_address: Option<&str>,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        "INSERT INTO account_activity (user_id, event, detail, user_agent, ip_address) VALUES (?, ?, ?, ?, ?)",
+        user_id,
+        event,
+        detail,
+        user_agent,
+        ip_address
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Newest first
+pub async fn list_entries(
+    pool: &MySqlPool,
+    user_id: u32,
+    limit: u32,
+) -> anyhow::Result<Vec<ActivityEntry>> {
+    let rows = sqlx::query_as!(
+        ActivityEntry,
+        "SELECT event, detail, user_agent, ip_address, created_at FROM account_activity WHERE user_id = ? ORDER BY created_at DESC, id DESC LIMIT ?",
+        user_id,
+        limit
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
@@ -0,0 +1,15 @@
+use sqlx::MySqlPool;
+
+pub async fn is_done(pool: &MySqlPool, name: &str) -> anyhow::Result<bool> {
+    let row = sqlx::query!("SELECT name FROM data_migrations WHERE name = ?", name)
+        .fetch_optional(pool)
+        .await?;
+    Ok(row.is_some())
+}
+
+pub async fn mark_done(pool: &MySqlPool, name: &str) -> anyhow::Result<()> {
+    sqlx::query!("INSERT IGNORE INTO data_migrations (name) VALUES (?)", name)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
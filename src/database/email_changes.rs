@@ -0,0 +1,93 @@
+use chrono::{DateTime, Utc};
+use sqlx::MySqlPool;
+
+pub struct EmailChange {
+    pub id: i32,
+    pub user_id: i32,
+    pub new_email: String,
+    pub old_confirmed_at: Option<DateTime<Utc>>,
+    pub new_confirmed_at: Option<DateTime<Utc>>,
+}
+
+/// Users have at most one pending change, a new request replaces the old one
+pub async fn create_change(
+    pool: &MySqlPool,
+    user_id: u32,
+    new_email: &str,
+    old_token_hash: &str,
+    new_token_hash: &str,
+    expires_at: DateTime<Utc>,
+) -> anyhow::Result<()> {
+    let mut tx = pool.begin().await?;
+    sqlx::query!("DELETE FROM email_changes WHERE user_id = ?", user_id)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query!(
+        "INSERT INTO email_changes (user_id, new_email, old_token_hash, new_token_hash, expires_at) VALUES (?, ?, ?, ?, ?)",
+        user_id,
+        new_email,
+        old_token_hash,
+        new_token_hash,
+        expires_at
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Marks whichever side `token_hash` belongs to as confirmed and returns the change
+/// as it is now. `None` if no unexpired change has this token
+pub async fn confirm_side(
+    pool: &MySqlPool,
+    token_hash: &str,
+) -> anyhow::Result<Option<EmailChange>> {
+    let mut tx = pool.begin().await?;
+    sqlx::query!(
+        "UPDATE email_changes SET
+            old_confirmed_at = IF(old_token_hash = ?, COALESCE(old_confirmed_at, NOW()), old_confirmed_at),
+            new_confirmed_at = IF(new_token_hash = ?, COALESCE(new_confirmed_at, NOW()), new_confirmed_at)
+        WHERE (old_token_hash = ? OR new_token_hash = ?) AND expires_at > NOW()",
+        token_hash,
+        token_hash,
+        token_hash,
+        token_hash
+    )
+    .execute(&mut *tx)
+    .await?;
+    let change = sqlx::query_as!(
+        EmailChange,
+        "SELECT id, user_id, new_email, old_confirmed_at, new_confirmed_at FROM email_changes WHERE (old_token_hash = ? OR new_token_hash = ?) AND expires_at > NOW()",
+        token_hash,
+        token_hash
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(change)
+}
+
+/// Switches the user to the new address, which counts as verified now
+pub async fn apply_change(pool: &MySqlPool, change: &EmailChange) -> anyhow::Result<()> {
+    let mut tx = pool.begin().await?;
+    sqlx::query!(
+        "UPDATE users SET email = ?, email_verified_at = NOW() WHERE id = ?",
+        change.new_email,
+        change.user_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!("DELETE FROM email_changes WHERE id = ?", change.id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Returns amount of removed rows
+pub async fn delete_expired(pool: &MySqlPool) -> anyhow::Result<u64> {
+    let result = sqlx::query!("DELETE FROM email_changes WHERE expires_at <= NOW()")
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected())
+}
@@ -0,0 +1,78 @@
+use chrono::{DateTime, Utc};
+use sqlx::MySqlPool;
+
+pub struct Verification {
+    pub user_id: i32,
+    pub email: String,
+}
+
+/// Stores a new token for `email`, replacing any sent to the same address before
+pub async fn create_verification(
+    pool: &MySqlPool,
+    user_id: u32,
+    email: &str,
+    token_hash: &str,
+    expires_at: DateTime<Utc>,
+) -> anyhow::Result<()> {
+    let mut tx = pool.begin().await?;
+    sqlx::query!(
+        "DELETE FROM email_verifications WHERE user_id = ? AND email = ?",
+        user_id,
+        email
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!(
+        "INSERT INTO email_verifications (user_id, email, token_hash, expires_at) VALUES (?, ?, ?, ?)",
+        user_id,
+        email,
+        token_hash,
+        expires_at
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// When the user was last sent a verification email, if they still have a pending one
+pub async fn last_sent_at(pool: &MySqlPool, user_id: u32) -> anyhow::Result<Option<DateTime<Utc>>> {
+    let row = sqlx::query!(
+        r#"SELECT MAX(created_at) AS "sent_at: DateTime<Utc>" FROM email_verifications WHERE user_id = ?"#,
+        user_id
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(row.sent_at)
+}
+
+/// Consumes an unexpired token. A token can only be taken once
+pub async fn take_verification(
+    pool: &MySqlPool,
+    token_hash: &str,
+) -> anyhow::Result<Option<Verification>> {
+    let mut tx = pool.begin().await?;
+    let verification = sqlx::query_as!(
+        Verification,
+        "SELECT user_id, email FROM email_verifications WHERE token_hash = ? AND expires_at > NOW() FOR UPDATE",
+        token_hash
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM email_verifications WHERE token_hash = ?",
+        token_hash
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(verification)
+}
+
+/// Returns amount of removed rows
+pub async fn delete_expired(pool: &MySqlPool) -> anyhow::Result<u64> {
+    let result = sqlx::query!("DELETE FROM email_verifications WHERE expires_at <= NOW()")
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected())
+}
@@ -0,0 +1,86 @@
+use chrono::{DateTime, Utc};
+use sqlx::MySqlPool;
+
+/// Counts one more failure and returns the total. Failures older than `window_secs`
+/// are forgotten first
+pub async fn add_failure(
+    pool: &MySqlPool,
+    scope: &str,
+    subject: &str,
+    window_secs: i64,
+) -> anyhow::Result<u32> {
+    sqlx::query!(
+        "INSERT INTO login_failures (scope, subject, failures, last_failure_at) VALUES (?, ?, 1, NOW())
+        ON DUPLICATE KEY UPDATE
+            failures = IF(last_failure_at < NOW() - INTERVAL ? SECOND, 1, failures + 1),
+            last_failure_at = NOW()",
+        scope,
+        subject,
+        window_secs
+    )
+    .execute(pool)
+    .await?;
+    let row = sqlx::query!(
+        "SELECT failures FROM login_failures WHERE scope = ? AND subject = ?",
+        scope,
+        subject
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(row.failures)
+}
+
+pub async fn set_locked_until(
+    pool: &MySqlPool,
+    scope: &str,
+    subject: &str,
+    locked_until: DateTime<Utc>,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        "UPDATE login_failures SET locked_until = ? WHERE scope = ? AND subject = ?",
+        locked_until,
+        scope,
+        subject
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// `None` unless there is a lock that hasn't run out yet
+pub async fn get_locked_until(
+    pool: &MySqlPool,
+    scope: &str,
+    subject: &str,
+) -> anyhow::Result<Option<DateTime<Utc>>> {
+    let row = sqlx::query!(
+        r#"SELECT locked_until AS "locked_until!: DateTime<Utc>" FROM login_failures WHERE scope = ? AND subject = ? AND locked_until > NOW()"#,
+        scope,
+        subject
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(row.map(|row| row.locked_until))
+}
+
+pub async fn clear(pool: &MySqlPool, scope: &str, subject: &str) -> anyhow::Result<()> {
+    sqlx::query!(
+        "DELETE FROM login_failures WHERE scope = ? AND subject = ?",
+        scope,
+        subject
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Drops counters that are forgotten anyway and not locked. Returns amount of removed rows
+pub async fn delete_stale(pool: &MySqlPool, window_secs: i64) -> anyhow::Result<u64> {
+    let result = sqlx::query!(
+        "DELETE FROM login_failures WHERE last_failure_at < NOW() - INTERVAL ? SECOND AND (locked_until IS NULL OR locked_until <= NOW())",
+        window_secs
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
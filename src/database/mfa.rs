@@ -0,0 +1,176 @@
+use chrono::{DateTime, Utc};
+use sqlx::MySqlPool;
+
+pub struct TotpState {
+    pub totp_secret: Option<Vec<u8>>,
+    pub totp_nonce: Option<Vec<u8>>,
+    pub totp_enabled_at: Option<DateTime<Utc>>,
+}
+
+pub struct RecoveryCode {
+    pub id: i32,
+    pub code_hash: String,
+}
+
+pub async fn get_totp(pool: &MySqlPool, user_id: u32) -> anyhow::Result<TotpState> {
+    let row = sqlx::query_as!(
+        TotpState,
+        "SELECT totp_secret, totp_nonce, totp_enabled_at FROM users WHERE id = ?",
+        user_id
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(row)
+}
+
+/// Stores a pending secret, replacing any earlier unconfirmed one. Does nothing once TOTP is enabled
+pub async fn set_totp_secret(
+    pool: &MySqlPool,
+    user_id: u32,
+    secret: &[u8],
+    nonce: &[u8],
+) -> anyhow::Result<bool> {
+    let result = sqlx::query!(
+        "UPDATE users SET totp_secret = ?, totp_nonce = ?, totp_last_step = NULL WHERE id = ? AND totp_enabled_at IS NULL",
+        secret,
+        nonce,
+        user_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+pub async fn enable_totp(pool: &MySqlPool, user_id: u32) -> anyhow::Result<()> {
+    sqlx::query!(
+        "UPDATE users SET totp_enabled_at = NOW() WHERE id = ? AND totp_secret IS NOT NULL",
+        user_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Remembers the last accepted step so a code can't be replayed. Returns false if
+/// `step` is not newer than the last one
+pub async fn use_totp_step(pool: &MySqlPool, user_id: u32, step: u64) -> anyhow::Result<bool> {
+    let result = sqlx::query!(
+        "UPDATE users SET totp_last_step = ? WHERE id = ? AND (totp_last_step IS NULL OR totp_last_step < ?)",
+        step,
+        user_id,
+        step
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+pub async fn disable_totp(pool: &MySqlPool, user_id: u32) -> anyhow::Result<()> {
+    let mut tx = pool.begin().await?;
+    sqlx::query!(
+        "UPDATE users SET totp_secret = NULL, totp_nonce = NULL, totp_enabled_at = NULL, totp_last_step = NULL WHERE id = ?",
+        user_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    sqlx::query!("DELETE FROM recovery_codes WHERE user_id = ?", user_id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+pub async fn replace_recovery_codes(
+    pool: &MySqlPool,
+    user_id: u32,
+    code_hashes: &[String],
+) -> anyhow::Result<()> {
+    let mut tx = pool.begin().await?;
+    sqlx::query!("DELETE FROM recovery_codes WHERE user_id = ?", user_id)
+        .execute(&mut *tx)
+        .await?;
+    for code_hash in code_hashes {
+        sqlx::query!(
+            "INSERT INTO recovery_codes (user_id, code_hash) VALUES (?, ?)",
+            user_id,
+            code_hash
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+pub async fn unused_recovery_codes(
+    pool: &MySqlPool,
+    user_id: u32,
+) -> anyhow::Result<Vec<RecoveryCode>> {
+    let rows = sqlx::query_as!(
+        RecoveryCode,
+        "SELECT id, code_hash FROM recovery_codes WHERE user_id = ? AND used_at IS NULL",
+        user_id
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+/// Returns false if the code was used concurrently
+pub async fn use_recovery_code(pool: &MySqlPool, id: i32) -> anyhow::Result<bool> {
+    let result = sqlx::query!(
+        "UPDATE recovery_codes SET used_at = NOW() WHERE id = ? AND used_at IS NULL",
+        id
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+pub async fn create_mfa_token(
+    pool: &MySqlPool,
+    jti: &str,
+    user_id: u32,
+    expires_at: DateTime<Utc>,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        "INSERT INTO mfa_tokens (jti, user_id, expires_at) VALUES (?, ?, ?)",
+        jti,
+        user_id,
+        expires_at
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Returns false if the token is gone, expired or already took `max_attempts`
+pub async fn count_mfa_attempt(
+    pool: &MySqlPool,
+    jti: &str,
+    max_attempts: u32,
+) -> anyhow::Result<bool> {
+    let result = sqlx::query!(
+        "UPDATE mfa_tokens SET attempts = attempts + 1 WHERE jti = ? AND attempts < ? AND expires_at > NOW()",
+        jti,
+        max_attempts
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+/// Returns false if the token was used concurrently
+pub async fn use_mfa_token(pool: &MySqlPool, jti: &str) -> anyhow::Result<bool> {
+    let result = sqlx::query!("DELETE FROM mfa_tokens WHERE jti = ?", jti)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+pub async fn delete_expired_mfa_tokens(pool: &MySqlPool) -> anyhow::Result<u64> {
+    let result = sqlx::query!("DELETE FROM mfa_tokens WHERE expires_at <= NOW()")
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected())
+}
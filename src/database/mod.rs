@@ -1,2 +1,15 @@
+pub mod access_tokens;
+pub mod activity;
+pub mod data_migrations;
+pub mod email_changes;
+pub mod email_verifications;
+pub mod login_failures;
+pub mod mfa;
+pub mod password_resets;
+pub mod srp;
 pub mod tokens;
+pub mod uploads;
 pub mod users;
+pub mod vaults;
+pub mod versions;
+pub mod webauthn;
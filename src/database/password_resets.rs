@@ -0,0 +1,52 @@
+use chrono::{DateTime, Utc};
+use sqlx::MySqlPool;
+
+/// Stores a new reset token, replacing any the user requested before
+pub async fn create_reset(
+    pool: &MySqlPool,
+    user_id: u32,
+    token_hash: &str,
+    expires_at: DateTime<Utc>,
+) -> anyhow::Result<()> {
+    let mut tx = pool.begin().await?;
+    sqlx::query!("DELETE FROM password_resets WHERE user_id = ?", user_id)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query!(
+        "INSERT INTO password_resets (user_id, token_hash, expires_at) VALUES (?, ?, ?)",
+        user_id,
+        token_hash,
+        expires_at
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Consumes an unexpired token and returns whose it was. A token can only be taken once
+pub async fn take_reset(pool: &MySqlPool, token_hash: &str) -> anyhow::Result<Option<u32>> {
+    let mut tx = pool.begin().await?;
+    let row = sqlx::query!(
+        "SELECT user_id FROM password_resets WHERE token_hash = ? AND expires_at > NOW() FOR UPDATE",
+        token_hash
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+    sqlx::query!(
+        "DELETE FROM password_resets WHERE token_hash = ?",
+        token_hash
+    )
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(row.map(|row| row.user_id as u32))
+}
+
+/// Returns amount of removed rows
+pub async fn delete_expired(pool: &MySqlPool) -> anyhow::Result<u64> {
+    let result = sqlx::query!("DELETE FROM password_resets WHERE expires_at <= NOW()")
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected())
+}
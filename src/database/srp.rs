@@ -0,0 +1,92 @@
+use chrono::{DateTime, Utc};
+use sqlx::MySqlPool;
+
+pub struct SrpVerifier {
+    pub salt: Vec<u8>,
+    pub verifier: Vec<u8>,
+}
+
+pub struct SrpSession {
+    pub user_id: Option<i32>,
+    pub client_proof: Vec<u8>,
+    pub server_proof: Vec<u8>,
+}
+
+pub async fn get_verifier(pool: &MySqlPool, user_id: u32) -> anyhow::Result<Option<SrpVerifier>> {
+    let row = sqlx::query_as!(
+        SrpVerifier,
+        "SELECT salt, verifier FROM srp_verifiers WHERE user_id = ?",
+        user_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(row)
+}
+
+pub async fn set_verifier(
+    pool: &MySqlPool,
+    user_id: u32,
+    salt: &[u8],
+    verifier: &[u8],
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        "INSERT INTO srp_verifiers (user_id, salt, verifier) VALUES (?, ?, ?)
+        ON DUPLICATE KEY UPDATE salt = VALUES(salt), verifier = VALUES(verifier), created_at = NOW()",
+        user_id,
+        salt,
+        verifier
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn delete_verifier(pool: &MySqlPool, user_id: u32) -> anyhow::Result<()> {
+    sqlx::query!("DELETE FROM srp_verifiers WHERE user_id = ?", user_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// `user_id` is `None` for made up sessions of unknown emails, they can never be finished
+pub async fn create_session(
+    pool: &MySqlPool,
+    id: &str,
+    user_id: Option<u32>,
+    client_proof: &[u8],
+    server_proof: &[u8],
+    expires_at: DateTime<Utc>,
+) -> anyhow::Result<()> {
+    // Abandoned logins are cleaned up whenever a new one starts
+    sqlx::query!("DELETE FROM srp_sessions WHERE expires_at <= NOW()")
+        .execute(pool)
+        .await?;
+    sqlx::query!(
+        "INSERT INTO srp_sessions (id, user_id, client_proof, server_proof, expires_at) VALUES (?, ?, ?, ?, ?)",
+        id,
+        user_id,
+        client_proof,
+        server_proof,
+        expires_at
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// A session can only be taken once, so every proof gets exactly one try
+pub async fn take_session(pool: &MySqlPool, id: &str) -> anyhow::Result<Option<SrpSession>> {
+    let mut tx = pool.begin().await?;
+    let session = sqlx::query_as!(
+        SrpSession,
+        "SELECT user_id, client_proof, server_proof FROM srp_sessions WHERE id = ? AND expires_at > NOW() FOR UPDATE",
+        id
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+    sqlx::query!("DELETE FROM srp_sessions WHERE id = ?", id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(session)
+}
@@ -1,31 +1,173 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
 use sqlx::MySqlPool;
 
+pub struct RefreshToken {
+    pub id: i32,
+    pub user_id: i32,
+    pub family_id: String,
+    pub retired: bool,
+    pub device_name: Option<String>,
+}
+
+/// Where a token was issued, recorded on every row of the family
+pub struct SessionInfo<'a> {
+    pub device_name: Option<&'a str>,
+    pub user_agent: Option<&'a str>,
+    pub ip_address: Option<&'a str>,
+}
+
+/// A login, i.e. a token family, described by its current token
+#[derive(Serialize)]
+pub struct Session {
+    pub id: String,
+    pub device_name: Option<String>,
+    pub user_agent: Option<String>,
+    pub ip_address: Option<String>,
+    pub signed_in_at: DateTime<Utc>,
+    pub last_active_at: DateTime<Utc>,
+}
+
 pub async fn create_token(
     pool: &MySqlPool,
     user_id: u32,
-    refresh_token: &str,
+    family_id: &str,
+    token_hash: &str,
+    expires_at: DateTime<Utc>,
+    info: &SessionInfo<'_>,
 ) -> anyhow::Result<u32> {
     let row = sqlx::query!(
-        "INSERT INTO refresh_tokens (user_id, token) VALUES (?, ?)",
+        "INSERT INTO refresh_tokens (user_id, family_id, token_hash, expires_at, device_name, user_agent, ip_address) VALUES (?, ?, ?, ?, ?, ?, ?)",
         user_id,
-        refresh_token
+        family_id,
+        token_hash,
+        expires_at,
+        info.device_name,
+        info.user_agent,
+        info.ip_address
     )
     .execute(pool)
     .await?;
     Ok(row.last_insert_id() as u32)
 }
 
-pub async fn token_exists(pool: &MySqlPool, token: &str) -> anyhow::Result<()> {
-    sqlx::query("SELECT * FROM refresh_tokens WHERE token = ?")
-        .bind(token)
-        .fetch_one(pool)
+/// Looks up an unexpired token, retired ones included so reuse can be detected
+pub async fn get_token(pool: &MySqlPool, token_hash: &str) -> anyhow::Result<Option<RefreshToken>> {
+    let row = sqlx::query_as!(
+        RefreshToken,
+        "SELECT id, user_id, family_id, retired, device_name FROM refresh_tokens WHERE token_hash = ? AND expires_at > NOW()",
+        token_hash
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(row)
+}
+
+/// Retires `old` and adds its successor to the same family. Returns false if `old`
+/// was already retired, meaning somebody else used it first
+pub async fn rotate_token(
+    pool: &MySqlPool,
+    old: &RefreshToken,
+    token_hash: &str,
+    expires_at: DateTime<Utc>,
+    info: &SessionInfo<'_>,
+) -> anyhow::Result<bool> {
+    let mut tx = pool.begin().await?;
+
+    let retired = sqlx::query!(
+        "UPDATE refresh_tokens SET retired = 1, last_used_at = NOW() WHERE id = ? AND retired = 0",
+        old.id
+    )
+    .execute(&mut *tx)
+    .await?;
+    if retired.rows_affected() != 1 {
+        return Ok(false);
+    }
+
+    sqlx::query!(
+        "INSERT INTO refresh_tokens (user_id, family_id, token_hash, expires_at, device_name, user_agent, ip_address) VALUES (?, ?, ?, ?, ?, ?, ?)",
+        old.user_id,
+        old.family_id,
+        token_hash,
+        expires_at,
+        info.device_name,
+        info.user_agent,
+        info.ip_address
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(true)
+}
+
+/// Active sessions of the user, most recently used first
+pub async fn list_sessions(pool: &MySqlPool, user_id: u32) -> anyhow::Result<Vec<Session>> {
+    let rows = sqlx::query_as!(
+        Session,
+        r#"SELECT t.family_id AS id, t.device_name, t.user_agent, t.ip_address,
+            (SELECT MIN(f.created_at) FROM refresh_tokens f WHERE f.family_id = t.family_id) AS "signed_in_at!: DateTime<Utc>",
+            COALESCE(t.last_used_at, t.created_at) AS "last_active_at!: DateTime<Utc>"
+        FROM refresh_tokens t
+        WHERE t.user_id = ? AND t.retired = 0 AND t.expires_at > NOW()
+        ORDER BY last_active_at DESC"#,
+        user_id
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+pub async fn delete_family(pool: &MySqlPool, family_id: &str) -> anyhow::Result<()> {
+    sqlx::query!("DELETE FROM refresh_tokens WHERE family_id = ?", family_id)
+        .execute(pool)
         .await?;
     Ok(())
 }
 
-pub async fn delete_token(pool: &MySqlPool, token: &str) -> anyhow::Result<()> {
-    sqlx::query!("DELETE FROM refresh_tokens WHERE token = ?", token)
+/// Same as `delete_family`, but only if the family belongs to `user_id`
+pub async fn delete_session(
+    pool: &MySqlPool,
+    user_id: u32,
+    family_id: &str,
+) -> anyhow::Result<bool> {
+    let result = sqlx::query!(
+        "DELETE FROM refresh_tokens WHERE user_id = ? AND family_id = ?",
+        user_id,
+        family_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn delete_user_tokens(pool: &MySqlPool, user_id: u32) -> anyhow::Result<()> {
+    sqlx::query!("DELETE FROM refresh_tokens WHERE user_id = ?", user_id)
         .execute(pool)
         .await?;
     Ok(())
 }
+
+/// Signs the user out everywhere except the `keep_family` session
+pub async fn delete_other_sessions(
+    pool: &MySqlPool,
+    user_id: u32,
+    keep_family: &str,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        "DELETE FROM refresh_tokens WHERE user_id = ? AND family_id <> ?",
+        user_id,
+        keep_family
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Returns amount of removed rows
+pub async fn delete_expired(pool: &MySqlPool) -> anyhow::Result<u64> {
+    let result = sqlx::query!("DELETE FROM refresh_tokens WHERE expires_at <= NOW()")
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected())
+}
@@ -0,0 +1,187 @@
+use chrono::{DateTime, Utc};
+use sqlx::MySqlPool;
+
+pub struct UploadSession {
+    pub id: String,
+    pub vault_id: u32,
+    pub user_id: i32,
+    pub object_key: String,
+    pub store_upload_id: String,
+    pub base_revision: u32,
+    pub length: u64,
+    pub upload_offset: u64,
+    pub expected_checksum: Option<String>,
+    /// Every part is sealed as it arrives, all of them continue the same encryption stream
+    pub stream_nonce: Vec<u8>,
+    pub expires_at: DateTime<Utc>,
+}
+
+pub struct UploadPart {
+    pub part_number: u16,
+    pub etag: String,
+    pub size: u64,
+}
+
+pub async fn create_session(pool: &MySqlPool, session: &UploadSession) -> anyhow::Result<()> {
+    sqlx::query!(
+        "INSERT INTO upload_sessions (id, vault_id, user_id, object_key, store_upload_id, base_revision, length, expected_checksum, stream_nonce, expires_at) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        session.id,
+        session.vault_id,
+        session.user_id,
+        session.object_key,
+        session.store_upload_id,
+        session.base_revision,
+        session.length,
+        session.expected_checksum,
+        session.stream_nonce,
+        session.expires_at
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn get_session(
+    pool: &MySqlPool,
+    user_id: u32,
+    vault_id: u32,
+    id: &str,
+) -> anyhow::Result<Option<UploadSession>> {
+    let row = sqlx::query_as!(
+        UploadSession,
+        "SELECT id, vault_id, user_id, object_key, store_upload_id, base_revision, length, upload_offset, expected_checksum, stream_nonce, expires_at FROM upload_sessions WHERE id = ? AND user_id = ? AND vault_id = ? AND expires_at > NOW()",
+        id,
+        user_id,
+        vault_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(row)
+}
+
+pub async fn expired_sessions(pool: &MySqlPool) -> anyhow::Result<Vec<UploadSession>> {
+    let rows = sqlx::query_as!(
+        UploadSession,
+        "SELECT id, vault_id, user_id, object_key, store_upload_id, base_revision, length, upload_offset, expected_checksum, stream_nonce, expires_at FROM upload_sessions WHERE expires_at <= NOW()"
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+/// Every session of the user, expired or not
+pub async fn user_sessions(pool: &MySqlPool, user_id: u32) -> anyhow::Result<Vec<UploadSession>> {
+    let rows = sqlx::query_as!(
+        UploadSession,
+        "SELECT id, vault_id, user_id, object_key, store_upload_id, base_revision, length, upload_offset, expected_checksum, stream_nonce, expires_at FROM upload_sessions WHERE user_id = ?",
+        user_id
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+/// Records an appended part that moves the offset by `appended` bytes, fails if somebody
+/// else moved the offset in the meantime
+/// Reserves the stream positions of `from_offset..to_offset` for a chunk with `digest`. Once
+/// sealed, the range can only be sealed again with the same bytes, which gives the same
+/// ciphertext, so a nonce never protects two different plaintexts
+pub async fn claim_seal(
+    pool: &MySqlPool,
+    id: &str,
+    from_offset: u64,
+    to_offset: u64,
+    digest: &[u8],
+) -> anyhow::Result<bool> {
+    let claimed = sqlx::query!(
+        "UPDATE upload_sessions SET sealed_offset = ?, sealed_digest = ?
+        WHERE id = ? AND upload_offset = ?
+            AND (sealed_offset = ? OR (sealed_offset = ? AND sealed_digest = ?))",
+        to_offset,
+        digest,
+        id,
+        from_offset,
+        from_offset,
+        to_offset,
+        digest
+    )
+    .execute(pool)
+    .await?;
+    Ok(claimed.rows_affected() == 1)
+}
+
+pub async fn add_part(
+    pool: &MySqlPool,
+    id: &str,
+    from_offset: u64,
+    appended: u64,
+    part: &UploadPart,
+) -> anyhow::Result<bool> {
+    let mut tx = pool.begin().await?;
+
+    let moved = sqlx::query!(
+        "UPDATE upload_sessions SET upload_offset = upload_offset + ? WHERE id = ? AND upload_offset = ?",
+        appended,
+        id,
+        from_offset
+    )
+    .execute(&mut *tx)
+    .await?;
+    if moved.rows_affected() != 1 {
+        return Ok(false);
+    }
+
+    sqlx::query!(
+        "REPLACE INTO upload_parts (session_id, part_number, etag, size) VALUES (?, ?, ?, ?)",
+        id,
+        part.part_number,
+        part.etag,
+        part.size
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(true)
+}
+
+pub async fn list_parts(pool: &MySqlPool, id: &str) -> anyhow::Result<Vec<UploadPart>> {
+    let rows = sqlx::query_as!(
+        UploadPart,
+        "SELECT part_number, etag, size FROM upload_parts WHERE session_id = ? ORDER BY part_number",
+        id
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+pub async fn delete_session(pool: &MySqlPool, id: &str) -> anyhow::Result<()> {
+    sqlx::query!("DELETE FROM upload_sessions WHERE id = ?", id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Points a finished upload at a newer revision, so it can still commit after a conflict
+pub async fn rebase_session(pool: &MySqlPool, id: &str, base_revision: u32) -> anyhow::Result<()> {
+    sqlx::query!(
+        "UPDATE upload_sessions SET base_revision = ? WHERE id = ?",
+        base_revision,
+        id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Bytes announced by the user's unfinished uploads
+pub async fn pending_bytes(pool: &MySqlPool, user_id: u32) -> anyhow::Result<u64> {
+    let row = sqlx::query!(
+        r#"SELECT CAST(COALESCE(SUM(length), 0) AS UNSIGNED) AS "pending!: u64" FROM upload_sessions WHERE user_id = ? AND expires_at > NOW()"#,
+        user_id
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(row.pending)
+}
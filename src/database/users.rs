@@ -1,4 +1,14 @@
+use chrono::{DateTime, Utc};
 use sqlx::MySqlPool;
+
+/// How clients derive the vault key from the master password
+pub struct KdfSettings {
+    pub kdf_algorithm: String,
+    pub kdf_iterations: u32,
+    pub kdf_memory: Option<u32>,
+    pub kdf_parallelism: Option<u32>,
+    pub kdf_salt: Option<Vec<u8>>,
+}
 pub async fn create_user(
     pool: &MySqlPool,
     username: &str,
@@ -16,11 +26,11 @@ pub async fn create_user(
     Ok(row.last_insert_id() as u32)
 }
 
-pub async fn id_by_email(pool: &MySqlPool, email: &str) -> anyhow::Result<u32> {
+pub async fn find_by_email(pool: &MySqlPool, email: &str) -> anyhow::Result<Option<u32>> {
     let row = sqlx::query!("SELECT id FROM users WHERE email = ?", email)
-        .fetch_one(pool)
+        .fetch_optional(pool)
         .await?;
-    Ok(row.id as u32)
+    Ok(row.map(|row| row.id as u32))
 }
 
 pub async fn get_password_hash(pool: &MySqlPool, id: u32) -> anyhow::Result<String> {
@@ -29,3 +39,172 @@ pub async fn get_password_hash(pool: &MySqlPool, id: u32) -> anyhow::Result<Stri
         .await?;
     Ok(row.password_hash)
 }
+
+pub async fn set_password_hash(
+    pool: &MySqlPool,
+    id: u32,
+    password_hash: &str,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        "UPDATE users SET password_hash = ? WHERE id = ?",
+        password_hash,
+        id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn set_username(pool: &MySqlPool, id: u32, username: &str) -> anyhow::Result<()> {
+    sqlx::query!("UPDATE users SET username = ? WHERE id = ?", username, id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn find_by_username(pool: &MySqlPool, username: &str) -> anyhow::Result<Option<u32>> {
+    let row = sqlx::query!("SELECT id FROM users WHERE username = ?", username)
+        .fetch_optional(pool)
+        .await?;
+    Ok(row.map(|row| row.id as u32))
+}
+
+pub async fn get_email(pool: &MySqlPool, id: u32) -> anyhow::Result<String> {
+    let row = sqlx::query!("SELECT email FROM users WHERE id = ?", id)
+        .fetch_one(pool)
+        .await?;
+    Ok(row.email)
+}
+
+pub async fn is_email_verified(pool: &MySqlPool, id: u32) -> anyhow::Result<bool> {
+    let row = sqlx::query!("SELECT email_verified_at FROM users WHERE id = ?", id)
+        .fetch_one(pool)
+        .await?;
+    Ok(row.email_verified_at.is_some())
+}
+
+/// Only marks the address verified if it's still the user's current one
+pub async fn set_email_verified(pool: &MySqlPool, id: u32, email: &str) -> anyhow::Result<bool> {
+    let result = sqlx::query!(
+        "UPDATE users SET email_verified_at = NOW() WHERE id = ? AND email = ?",
+        id,
+        email
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn get_storage_quota(pool: &MySqlPool, id: u32) -> anyhow::Result<Option<u64>> {
+    let row = sqlx::query!("SELECT storage_quota FROM users WHERE id = ?", id)
+        .fetch_one(pool)
+        .await?;
+    Ok(row.storage_quota)
+}
+
+pub async fn get_data_key(pool: &MySqlPool, id: u32) -> anyhow::Result<Option<Vec<u8>>> {
+    let row = sqlx::query!("SELECT data_key FROM users WHERE id = ?", id)
+        .fetch_one(pool)
+        .await?;
+    Ok(row.data_key)
+}
+
+/// Only sets the key if the user has none yet, so concurrent requests agree on one key
+pub async fn init_data_key(pool: &MySqlPool, id: u32, data_key: &[u8]) -> anyhow::Result<()> {
+    sqlx::query!(
+        "UPDATE users SET data_key = ? WHERE id = ? AND data_key IS NULL",
+        data_key,
+        id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// When the account is going to be purged, `None` unless its deletion was requested
+pub async fn get_purge_at(pool: &MySqlPool, id: u32) -> anyhow::Result<Option<DateTime<Utc>>> {
+    let row = sqlx::query!("SELECT purge_at FROM users WHERE id = ?", id)
+        .fetch_one(pool)
+        .await?;
+    Ok(row.purge_at)
+}
+
+pub async fn set_purge_at(
+    pool: &MySqlPool,
+    id: u32,
+    purge_at: Option<DateTime<Utc>>,
+) -> anyhow::Result<()> {
+    sqlx::query!("UPDATE users SET purge_at = ? WHERE id = ?", purge_at, id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Accounts whose grace period is over
+pub async fn due_for_purge(pool: &MySqlPool) -> anyhow::Result<Vec<u32>> {
+    let rows = sqlx::query!("SELECT id FROM users WHERE purge_at <= NOW()")
+        .fetch_all(pool)
+        .await?;
+    Ok(rows.into_iter().map(|row| row.id as u32).collect())
+}
+
+/// Everything else the user owns goes with the row through `ON DELETE CASCADE`
+pub async fn delete_user(pool: &MySqlPool, id: u32) -> anyhow::Result<()> {
+    sqlx::query!("DELETE FROM users WHERE id = ?", id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// One query whether or not the account exists, prelogin shouldn't take longer for either
+pub async fn get_kdf_by_email(
+    pool: &MySqlPool,
+    email: &str,
+) -> anyhow::Result<Option<KdfSettings>> {
+    let row = sqlx::query_as!(
+        KdfSettings,
+        "SELECT kdf_algorithm, kdf_iterations, kdf_memory, kdf_parallelism, kdf_salt FROM users WHERE email = ?",
+        email
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(row)
+}
+
+pub async fn set_kdf(pool: &MySqlPool, id: u32, kdf: &KdfSettings) -> anyhow::Result<()> {
+    sqlx::query!(
+        "UPDATE users SET kdf_algorithm = ?, kdf_iterations = ?, kdf_memory = ?, kdf_parallelism = ?, kdf_salt = ? WHERE id = ?",
+        kdf.kdf_algorithm,
+        kdf.kdf_iterations,
+        kdf.kdf_memory,
+        kdf.kdf_parallelism,
+        kdf.kdf_salt,
+        id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Ids and emails of accounts from before KDF settings, which have no salt stored yet
+pub async fn without_kdf_salt(pool: &MySqlPool) -> anyhow::Result<Vec<(u32, String)>> {
+    let rows = sqlx::query!("SELECT id, email FROM users WHERE kdf_salt IS NULL")
+        .fetch_all(pool)
+        .await?;
+    Ok(rows
+        .into_iter()
+        .map(|row| (row.id as u32, row.email))
+        .collect())
+}
+
+/// Only sets the salt if the user has none yet, like `init_data_key`
+pub async fn init_kdf_salt(pool: &MySqlPool, id: u32, salt: &[u8]) -> anyhow::Result<()> {
+    sqlx::query!(
+        "UPDATE users SET kdf_salt = ? WHERE id = ? AND kdf_salt IS NULL",
+        salt,
+        id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
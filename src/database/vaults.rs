@@ -0,0 +1,92 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::MySqlPool;
+
+#[derive(Serialize, Clone)]
+pub struct Vault {
+    pub id: u32,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+}
+
+pub async fn create_vault(pool: &MySqlPool, user_id: u32, name: &str) -> anyhow::Result<u32> {
+    let row = sqlx::query!(
+        "INSERT INTO vaults (user_id, name) VALUES (?, ?)",
+        user_id,
+        name
+    )
+    .execute(pool)
+    .await?;
+    Ok(row.last_insert_id() as u32)
+}
+
+/// Users that never got a vault, including everybody from before vaults existed
+/// Ids of users without any vault and when they registered
+pub async fn users_without_vaults(
+    pool: &MySqlPool,
+) -> anyhow::Result<Vec<(u32, Option<DateTime<Utc>>)>> {
+    let rows = sqlx::query!(
+        "SELECT u.id, u.created_at FROM users u WHERE NOT EXISTS (SELECT 1 FROM vaults v WHERE v.user_id = u.id)"
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(|row| (row.id as u32, row.created_at))
+        .collect())
+}
+
+pub async fn get_vault(
+    pool: &MySqlPool,
+    user_id: u32,
+    vault_id: u32,
+) -> anyhow::Result<Option<Vault>> {
+    let row = sqlx::query_as!(
+        Vault,
+        "SELECT id, name, created_at FROM vaults WHERE id = ? AND user_id = ?",
+        vault_id,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(row)
+}
+
+pub async fn list_vaults(pool: &MySqlPool, user_id: u32) -> anyhow::Result<Vec<Vault>> {
+    let rows = sqlx::query_as!(
+        Vault,
+        "SELECT id, name, created_at FROM vaults WHERE user_id = ? ORDER BY id",
+        user_id
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+pub async fn rename_vault(
+    pool: &MySqlPool,
+    user_id: u32,
+    vault_id: u32,
+    name: &str,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        "UPDATE vaults SET name = ? WHERE id = ? AND user_id = ?",
+        name,
+        vault_id,
+        user_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn delete_vault(pool: &MySqlPool, user_id: u32, vault_id: u32) -> anyhow::Result<()> {
+    sqlx::query!(
+        "DELETE FROM vaults WHERE id = ? AND user_id = ?",
+        vault_id,
+        user_id
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
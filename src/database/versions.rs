@@ -0,0 +1,118 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::MySqlPool;
+
+#[derive(Serialize, Clone)]
+pub struct VaultVersion {
+    pub version: u32,
+    pub size: u64,
+    pub uploaded_by: i32,
+    pub checksum: String,
+    pub created_at: DateTime<Utc>,
+    #[serde(skip)]
+    pub object_key: String,
+    #[serde(skip)]
+    pub encrypted: bool,
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn create_version(
+    pool: &MySqlPool,
+    vault_id: u32,
+    version: u32,
+    size: u64,
+    uploaded_by: u32,
+    checksum: &str,
+    object_key: &str,
+    encrypted: bool,
+) -> anyhow::Result<bool> {
+    let result = sqlx::query!(
+        "INSERT INTO vault_versions (vault_id, version, size, uploaded_by, checksum, object_key, encrypted) VALUES (?, ?, ?, ?, ?, ?, ?)",
+        vault_id,
+        version,
+        size,
+        uploaded_by,
+        checksum,
+        object_key,
+        encrypted
+    )
+    .execute(pool)
+    .await;
+
+    // (vault_id, version) is unique, so a concurrent writer that got there first makes this fail
+    match result {
+        Ok(_) => Ok(true),
+        Err(sqlx::Error::Database(err)) if err.is_unique_violation() => Ok(false),
+        Err(err) => Err(err.into()),
+    }
+}
+
+pub async fn latest_version(pool: &MySqlPool, vault_id: u32) -> anyhow::Result<Option<u32>> {
+    let row = sqlx::query!(
+        r#"SELECT MAX(version) AS "version: u32" FROM vault_versions WHERE vault_id = ?"#,
+        vault_id
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(row.version)
+}
+
+pub async fn get_version(
+    pool: &MySqlPool,
+    vault_id: u32,
+    version: u32,
+) -> anyhow::Result<Option<VaultVersion>> {
+    let row = sqlx::query_as!(
+        VaultVersion,
+        "SELECT version, size, uploaded_by, checksum, created_at, object_key, encrypted FROM vault_versions WHERE vault_id = ? AND version = ?",
+        vault_id,
+        version
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(row)
+}
+
+pub async fn list_versions(pool: &MySqlPool, vault_id: u32) -> anyhow::Result<Vec<VaultVersion>> {
+    let rows = sqlx::query_as!(
+        VaultVersion,
+        "SELECT version, size, uploaded_by, checksum, created_at, object_key, encrypted FROM vault_versions WHERE vault_id = ? ORDER BY version DESC",
+        vault_id
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+pub async fn delete_version(pool: &MySqlPool, vault_id: u32, version: u32) -> anyhow::Result<()> {
+    sqlx::query!(
+        "DELETE FROM vault_versions WHERE vault_id = ? AND version = ?",
+        vault_id,
+        version
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Whether some version is stored in `object_key`
+pub async fn object_committed(pool: &MySqlPool, object_key: &str) -> anyhow::Result<bool> {
+    let row = sqlx::query!(
+        r#"SELECT EXISTS(SELECT 1 FROM vault_versions WHERE object_key = ?) AS "committed: bool""#,
+        object_key
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(row.committed)
+}
+
+/// Total size of every stored version across the user's vaults
+pub async fn user_usage(pool: &MySqlPool, user_id: u32) -> anyhow::Result<u64> {
+    let row = sqlx::query!(
+        r#"SELECT CAST(COALESCE(SUM(vault_versions.size), 0) AS UNSIGNED) AS "used!: u64" FROM vault_versions JOIN vaults ON vaults.id = vault_versions.vault_id WHERE vaults.user_id = ?"#,
+        user_id
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(row.used)
+}
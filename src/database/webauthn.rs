@@ -0,0 +1,148 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::MySqlPool;
+
+pub struct Challenge {
+    pub user_id: Option<i32>,
+    pub challenge: Vec<u8>,
+}
+
+#[derive(Serialize)]
+pub struct WebauthnCredential {
+    pub id: i32,
+    #[serde(skip)]
+    pub user_id: i32,
+    #[serde(skip)]
+    pub credential_id: Vec<u8>,
+    #[serde(skip)]
+    pub public_key: Vec<u8>,
+    #[serde(skip)]
+    pub sign_count: u32,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+pub async fn create_challenge(
+    pool: &MySqlPool,
+    id: &str,
+    user_id: Option<u32>,
+    purpose: &str,
+    challenge: &[u8],
+    expires_at: DateTime<Utc>,
+) -> anyhow::Result<()> {
+    // Abandoned ceremonies are cleaned up whenever a new one starts
+    sqlx::query!("DELETE FROM webauthn_challenges WHERE expires_at <= NOW()")
+        .execute(pool)
+        .await?;
+    sqlx::query!(
+        "INSERT INTO webauthn_challenges (id, user_id, purpose, challenge, expires_at) VALUES (?, ?, ?, ?, ?)",
+        id,
+        user_id,
+        purpose,
+        challenge,
+        expires_at
+    )
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Challenges are single use, whoever deletes the row gets it
+pub async fn take_challenge(
+    pool: &MySqlPool,
+    id: &str,
+    purpose: &str,
+) -> anyhow::Result<Option<Challenge>> {
+    let mut tx = pool.begin().await?;
+    let challenge = sqlx::query_as!(
+        Challenge,
+        "SELECT user_id, challenge FROM webauthn_challenges WHERE id = ? AND purpose = ? AND expires_at > NOW() FOR UPDATE",
+        id,
+        purpose
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+    sqlx::query!("DELETE FROM webauthn_challenges WHERE id = ?", id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(challenge)
+}
+
+pub async fn create_credential(
+    pool: &MySqlPool,
+    user_id: u32,
+    credential_id: &[u8],
+    public_key: &[u8],
+    algorithm: i32,
+    sign_count: u32,
+    name: &str,
+) -> anyhow::Result<u32> {
+    let row = sqlx::query!(
+        "INSERT INTO webauthn_credentials (user_id, credential_id, public_key, algorithm, sign_count, name) VALUES (?, ?, ?, ?, ?, ?)",
+        user_id,
+        credential_id,
+        public_key,
+        algorithm,
+        sign_count,
+        name
+    )
+    .execute(pool)
+    .await?;
+    Ok(row.last_insert_id() as u32)
+}
+
+pub async fn get_credential(
+    pool: &MySqlPool,
+    credential_id: &[u8],
+) -> anyhow::Result<Option<WebauthnCredential>> {
+    let row = sqlx::query_as!(
+        WebauthnCredential,
+        "SELECT id, user_id, credential_id, public_key, sign_count, name, created_at, last_used_at FROM webauthn_credentials WHERE credential_id = ?",
+        credential_id
+    )
+    .fetch_optional(pool)
+    .await?;
+    Ok(row)
+}
+
+pub async fn list_credentials(
+    pool: &MySqlPool,
+    user_id: u32,
+) -> anyhow::Result<Vec<WebauthnCredential>> {
+    let rows = sqlx::query_as!(
+        WebauthnCredential,
+        "SELECT id, user_id, credential_id, public_key, sign_count, name, created_at, last_used_at FROM webauthn_credentials WHERE user_id = ? ORDER BY created_at",
+        user_id
+    )
+    .fetch_all(pool)
+    .await?;
+    Ok(rows)
+}
+
+/// Moves the signature counter forward. Fails if it did not grow, which hints at a cloned
+/// authenticator. Authenticators without a counter always report 0 and are let through
+pub async fn use_credential(pool: &MySqlPool, id: i32, sign_count: u32) -> anyhow::Result<bool> {
+    let result = sqlx::query!(
+        "UPDATE webauthn_credentials SET sign_count = ?, last_used_at = NOW() WHERE id = ? AND (sign_count < ? OR (sign_count = 0 AND ? = 0))",
+        sign_count,
+        id,
+        sign_count,
+        sign_count
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+pub async fn delete_credential(pool: &MySqlPool, user_id: u32, id: i32) -> anyhow::Result<bool> {
+    let result = sqlx::query!(
+        "DELETE FROM webauthn_credentials WHERE user_id = ? AND id = ?",
+        user_id,
+        id
+    )
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
@@ -0,0 +1,331 @@
+use std::{sync::Arc, time::Duration};
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::MySqlPool;
+
+use crate::{
+    common::{
+        client::ClientInfo,
+        error::{AppError, ErrorTypes},
+    },
+    controllers::{self, activity::Activity, email_changes::Confirmation},
+    crypt::token::AuthHeader,
+    error_response,
+    handlers::{
+        auth::{confirm_password, is_valid_email, login_failed, login_lock, login_succeeded},
+        mfa::invalid_credentials,
+        srp::SrpRecord,
+        storage, uploads,
+    },
+    mail::{self, Mailer},
+    store::VaultStore,
+};
+
+pub fn bad_data() -> Response {
+    error_response!(
+        StatusCode::BAD_REQUEST,
+        ErrorTypes::BadData,
+        "Provided data is bad"
+    )
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ChangePassword {
+    current_password: String,
+    new_password: String,
+    // Verifier for the new password, the old one stops working either way
+    #[serde(default)]
+    srp: Option<SrpRecord>,
+}
+
+pub async fn change_password(
+    State(pool): State<MySqlPool>,
+    auth_header: AuthHeader,
+    client: ClientInfo,
+    Json(data): Json<ChangePassword>,
+) -> Result<Response, AppError> {
+    let user_id = auth_header.claims.id;
+    if data.new_password.len() < 4 || data.new_password.len() > 64 {
+        return Ok(bad_data());
+    }
+    if let Some(denied) = confirm_password(&pool, user_id, &data.current_password, &client).await? {
+        return Ok(denied);
+    }
+
+    controllers::users::change_password(&pool, user_id, &data.new_password).await?;
+    controllers::srp::delete_verifier(&pool, user_id).await?;
+    if let Some(srp) = &data.srp {
+        srp.migrate(&pool, user_id).await?;
+    }
+    // The session this request comes from stays signed in, every other one is signed out
+    controllers::tokens::delete_other_sessions(&pool, user_id, &auth_header.claims.sid).await?;
+    controllers::activity::record(&pool, user_id, Activity::PasswordChanged, None, &client).await;
+    Ok((StatusCode::NO_CONTENT).into_response())
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ChangeEmail {
+    password: String,
+    new_email: String,
+}
+
+/// Nothing changes yet, both addresses get a confirmation email first
+pub async fn change_email(
+    State(pool): State<MySqlPool>,
+    State(mailer): State<Arc<dyn Mailer>>,
+    auth_header: AuthHeader,
+    client: ClientInfo,
+    Json(data): Json<ChangeEmail>,
+) -> Result<Response, AppError> {
+    let user_id = auth_header.claims.id;
+    if !is_valid_email(&data.new_email) {
+        return Ok(bad_data());
+    }
+    if let Some(denied) = confirm_password(&pool, user_id, &data.password, &client).await? {
+        return Ok(denied);
+    }
+
+    let Some(emails) =
+        controllers::email_changes::start_change(&pool, user_id, &data.new_email).await?
+    else {
+        return Ok(error_response!(
+            StatusCode::CONFLICT,
+            ErrorTypes::UserAlreadyExists,
+            "Email is already taken"
+        ));
+    };
+    for email in emails {
+        mail::send_in_background(mailer.clone(), email);
+    }
+    controllers::activity::record(
+        &pool,
+        user_id,
+        Activity::EmailChangeRequested,
+        Some(&data.new_email),
+        &client,
+    )
+    .await;
+    Ok((StatusCode::ACCEPTED).into_response())
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ConfirmEmailChange {
+    token: String,
+}
+
+/// 202 while the other address still has to confirm, 204 once the email is changed
+pub async fn confirm_email_change(
+    State(pool): State<MySqlPool>,
+    client: ClientInfo,
+    Json(data): Json<ConfirmEmailChange>,
+) -> Result<Response, AppError> {
+    match controllers::email_changes::confirm(&pool, &data.token).await? {
+        Confirmation::Invalid => Ok(error_response!(
+            StatusCode::BAD_REQUEST,
+            ErrorTypes::InvalidVerificationToken,
+            "Confirmation token is invalid or expired"
+        )),
+        Confirmation::Pending => Ok((StatusCode::ACCEPTED).into_response()),
+        Confirmation::Taken => Ok(error_response!(
+            StatusCode::CONFLICT,
+            ErrorTypes::UserAlreadyExists,
+            "Email is already taken"
+        )),
+        Confirmation::Changed { user_id, new_email } => {
+            controllers::activity::record(
+                &pool,
+                user_id,
+                Activity::EmailChanged,
+                Some(&new_email),
+                &client,
+            )
+            .await;
+            Ok((StatusCode::NO_CONTENT).into_response())
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ChangeUsername {
+    password: String,
+    username: String,
+}
+
+pub async fn change_username(
+    State(pool): State<MySqlPool>,
+    auth_header: AuthHeader,
+    client: ClientInfo,
+    Json(data): Json<ChangeUsername>,
+) -> Result<Response, AppError> {
+    let user_id = auth_header.claims.id;
+    if data.username.is_empty() || data.username.len() > 32 {
+        return Ok(bad_data());
+    }
+    if let Some(denied) = confirm_password(&pool, user_id, &data.password, &client).await? {
+        return Ok(denied);
+    }
+
+    if !controllers::users::change_username(&pool, user_id, &data.username).await? {
+        return Ok(error_response!(
+            StatusCode::CONFLICT,
+            ErrorTypes::UserAlreadyExists,
+            "Username is already taken"
+        ));
+    }
+    controllers::activity::record(
+        &pool,
+        user_id,
+        Activity::UsernameChanged,
+        Some(&data.username),
+        &client,
+    )
+    .await;
+    Ok((StatusCode::NO_CONTENT).into_response())
+}
+
+pub async fn list_activity(
+    State(pool): State<MySqlPool>,
+    auth_header: AuthHeader,
+) -> Result<Response, AppError> {
+    let activity = controllers::activity::list(&pool, auth_header.claims.id).await?;
+    Ok((StatusCode::OK, Json(activity)).into_response())
+}
+
+pub fn pending_deletion(purge_at: DateTime<Utc>) -> Response {
+    error_response!(
+        StatusCode::FORBIDDEN,
+        ErrorTypes::AccountPendingDeletion,
+        "Account is going to be deleted at {}, restore it to log in",
+        purge_at
+    )
+}
+
+/// Removes every stored object of the user, then the user with all of their rows.
+/// Objects first: if this fails the rows are still there to retry with
+pub async fn purge_account(
+    pool: &MySqlPool,
+    store: &dyn VaultStore,
+    user_id: u32,
+) -> anyhow::Result<()> {
+    for session in controllers::uploads::user_sessions(pool, user_id).await? {
+        if let Err(why) = uploads::release_upload(pool, store, &session).await {
+            tracing::error!("Could not abort upload {}: {}", session.id, why);
+        }
+    }
+    for vault in controllers::vaults::list_vaults(pool, user_id).await? {
+        storage::purge_vault_objects(pool, store, vault.id).await?;
+    }
+    // Whatever no version points at anymore, e.g. assembled uploads
+    for object in store.list(&format!("{}/", user_id)).await? {
+        store.delete(&object.key).await?;
+    }
+
+    controllers::users::delete_user(pool, user_id).await?;
+    tracing::info!("Purged account {}", user_id);
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct DeleteAccount {
+    password: String,
+}
+
+#[derive(Serialize)]
+struct DeletionScheduledResponse {
+    purge_at: DateTime<Utc>,
+}
+
+/// Signs the user out everywhere and deletes the account, right away or after the grace period
+pub async fn delete_account(
+    State(pool): State<MySqlPool>,
+    State(store): State<Arc<dyn VaultStore>>,
+    auth_header: AuthHeader,
+    client: ClientInfo,
+    Json(data): Json<DeleteAccount>,
+) -> Result<Response, AppError> {
+    let user_id = auth_header.claims.id;
+    if let Some(denied) = confirm_password(&pool, user_id, &data.password, &client).await? {
+        return Ok(denied);
+    }
+
+    controllers::tokens::delete_user_tokens(&pool, user_id).await?;
+    if controllers::users::deletion_grace().is_zero() {
+        purge_account(&pool, store.as_ref(), user_id).await?;
+        return Ok((StatusCode::NO_CONTENT).into_response());
+    }
+
+    let purge_at = controllers::users::schedule_deletion(&pool, user_id).await?;
+    controllers::activity::record(&pool, user_id, Activity::DeletionScheduled, None, &client).await;
+    let resp = DeletionScheduledResponse { purge_at };
+    Ok((StatusCode::ACCEPTED, Json(resp)).into_response())
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RestoreAccount {
+    email: String,
+    password: String,
+}
+
+/// Takes credentials instead of a token, deleting the account signed it out everywhere
+pub async fn restore_account(
+    State(pool): State<MySqlPool>,
+    client: ClientInfo,
+    Json(data): Json<RestoreAccount>,
+) -> Result<Response, AppError> {
+    if let Some(locked) = login_lock(&pool, None, &client).await? {
+        return Ok(locked);
+    }
+    let Some(user_id) = controllers::users::find_by_email(&pool, &data.email).await? else {
+        login_failed(&pool, None, &client).await?;
+        return Ok(invalid_credentials());
+    };
+    if let Some(locked) = login_lock(&pool, Some(user_id), &client).await? {
+        return Ok(locked);
+    }
+    if !controllers::users::verify_password(&pool, user_id, &data.password).await? {
+        login_failed(&pool, Some(user_id), &client).await?;
+        return Ok(invalid_credentials());
+    }
+    login_succeeded(&pool, user_id).await?;
+
+    if controllers::users::purge_at(&pool, user_id)
+        .await?
+        .is_none()
+    {
+        return Ok(error_response!(
+            StatusCode::BAD_REQUEST,
+            ErrorTypes::BadData,
+            "Account is not scheduled for deletion"
+        ));
+    }
+    controllers::users::cancel_deletion(&pool, user_id).await?;
+    controllers::activity::record(&pool, user_id, Activity::DeletionCancelled, None, &client).await;
+    Ok((StatusCode::NO_CONTENT).into_response())
+}
+
+/// Runs forever, purging accounts whose grace period is over
+pub async fn purge_deleted_accounts(pool: MySqlPool, store: Arc<dyn VaultStore>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(60 * 60));
+    loop {
+        interval.tick().await;
+        let due = match controllers::users::due_for_purge(&pool).await {
+            Ok(due) => due,
+            Err(why) => {
+                tracing::error!("Could not list accounts to purge: {}", why);
+                continue;
+            }
+        };
+        for user_id in due {
+            if let Err(why) = purge_account(&pool, store.as_ref(), user_id).await {
+                tracing::error!("Could not purge account {}: {}", user_id, why);
+            }
+        }
+    }
+}
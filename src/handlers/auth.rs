@@ -1,20 +1,35 @@
+use std::{sync::Arc, time::Duration};
+
 use axum::{
     extract::{Query, State},
-    http::StatusCode,
+    http::{header, StatusCode},
     response::{IntoResponse, Response},
     Json,
 };
+use axum_extra::extract::{
+    cookie::{Cookie, SameSite},
+    CookieJar,
+};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::MySqlPool;
 
 use crate::{
-    common::error::{AppError, ErrorTypes},
-    controllers,
+    common::{
+        client::ClientInfo,
+        error::{AppError, ErrorTypes},
+    },
+    controllers::{
+        self, email_verifications::Enforcement, kdf::KdfParams, login_failures::Scope,
+        tokens::Rotation,
+    },
     crypt::{
         self,
         token::{self, RefreshHeader},
     },
     error_response,
+    handlers::{self, srp::SrpRecord},
+    mail::{self, Mailer},
 };
 
 #[derive(Deserialize, Serialize)]
@@ -22,29 +37,148 @@ pub struct UserRegister {
     username: String,
     email: String,
     password: String,
+    #[serde(default)]
+    device_name: Option<String>,
+    #[serde(default)]
+    srp: Option<SrpRecord>,
+    // What the client derived its keys with, defaults to what `/prelogin` offered
+    #[serde(default)]
+    kdf: Option<KdfParams>,
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct UserLogin {
     email: String,
     password: String,
+    #[serde(default)]
+    device_name: Option<String>,
+    // Lets existing users switch to SRP logins
+    #[serde(default)]
+    srp: Option<SrpRecord>,
+}
+
+fn is_valid_device_name(device_name: Option<&str>) -> bool {
+    device_name.is_none_or(|name| !name.is_empty() && name.chars().count() <= 64)
 }
 
 #[derive(Serialize)]
 struct TokensResponse {
+    jwt_token: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    refresh_token: Option<String>,
+}
+
+/// Where the browser sends the refresh cookie, `REFRESH_COOKIE_PATH`.
+/// Only needs changing when the server sits behind a path prefix
+fn refresh_cookie_path() -> String {
+    std::env::var("REFRESH_COOKIE_PATH").unwrap_or_else(|_| "/token".to_owned())
+}
+
+fn session_cookies(refresh_token: &str) -> CookieJar {
+    let max_age = time::Duration::seconds(token::REFRESH_TOKEN_TTL.num_seconds());
+    let refresh = Cookie::build((token::REFRESH_COOKIE, refresh_token.to_owned()))
+        .path(refresh_cookie_path())
+        .http_only(true)
+        .secure(true)
+        .same_site(SameSite::Strict)
+        .max_age(max_age);
+    // Readable by our own pages, they echo it back in `X-CSRF-Token`
+    let csrf = Cookie::build((token::CSRF_COOKIE, token::csrf_token(refresh_token)))
+        .path("/")
+        .secure(true)
+        .same_site(SameSite::Strict)
+        .max_age(max_age);
+    CookieJar::new().add(refresh).add(csrf)
+}
+
+/// In cookie mode the refresh token never shows up in the body, so scripts can't read it
+fn token_pair(
+    status: StatusCode,
+    cookies: bool,
     jwt_token: String,
     refresh_token: String,
+) -> Response {
+    if cookies {
+        let jar = session_cookies(&refresh_token);
+        let resp = TokensResponse {
+            jwt_token,
+            refresh_token: None,
+        };
+        return (status, jar, Json(resp)).into_response();
+    }
+    let resp = TokensResponse {
+        jwt_token,
+        refresh_token: Some(refresh_token),
+    };
+    (status, Json(resp)).into_response()
+}
+
+#[derive(Serialize)]
+struct VerificationPendingResponse {
+    // Tokens are only handed out by login once the email is verified
+    email_verification_required: bool,
+}
+
+pub fn is_valid_email(email: &str) -> bool {
+    let Some((local, domain)) = email.split_once('@') else {
+        return false;
+    };
+    email.len() <= 255
+        && !local.is_empty()
+        && !domain.starts_with('.')
+        && !domain.ends_with('.')
+        && domain.contains('.')
+        && !domain.contains('@')
+        && !email.chars().any(char::is_whitespace)
+}
+
+#[derive(Serialize)]
+struct MfaPendingResponse {
+    mfa_token: String,
+    // Second factors the user can finish the login with
+    methods: Vec<&'static str>,
+}
+
+/// Starts a new session and hands out its tokens
+pub async fn tokens_response(
+    pool: &MySqlPool,
+    user_id: u32,
+    device_name: Option<&str>,
+    client: &ClientInfo,
+) -> anyhow::Result<Response> {
+    if controllers::email_verifications::enforcement() == Enforcement::Login
+        && !controllers::email_verifications::is_verified(pool, user_id).await?
+    {
+        return Ok(handlers::email::email_not_verified());
+    }
+    if let Some(purge_at) = controllers::users::purge_at(pool, user_id).await? {
+        return Ok(handlers::account::pending_deletion(purge_at));
+    }
+
+    let session = controllers::tokens::create_token(pool, user_id, device_name, client).await?;
+    let jwt_token =
+        controllers::tokens::issue_access_token(pool, user_id, &session.session_id).await?;
+
+    Ok(token_pair(
+        StatusCode::OK,
+        client.session_cookies,
+        jwt_token,
+        session.refresh_token,
+    ))
 }
 
 pub async fn register(
     State(pool): State<MySqlPool>,
+    State(mailer): State<Arc<dyn Mailer>>,
+    client: ClientInfo,
     Json(user_data): Json<UserRegister>,
 ) -> Result<Response, AppError> {
     if (user_data.username.len() > 32 || user_data.username.is_empty())
         || (user_data.password.is_empty()
             || user_data.password.len() < 4
             || user_data.password.len() > 64)
-        || (user_data.email.is_empty())
+        || !is_valid_email(&user_data.email)
+        || !is_valid_device_name(user_data.device_name.as_deref())
     {
         return Ok(error_response!(
             StatusCode::BAD_REQUEST,
@@ -53,6 +187,17 @@ pub async fn register(
         ));
     }
 
+    let kdf = user_data
+        .kdf
+        .unwrap_or_else(|| KdfParams::default_for(&user_data.email));
+    let Some(kdf) = kdf.to_settings() else {
+        return Ok(error_response!(
+            StatusCode::BAD_REQUEST,
+            ErrorTypes::BadData,
+            "Unsupported or too weak KDF parameters"
+        ));
+    };
+
     let hashed_password = crypt::password::hash_password(&user_data.password);
 
     match controllers::users::create_user(
@@ -64,15 +209,38 @@ pub async fn register(
     .await
     {
         Ok(id) => {
-            let jwt_token = crypt::token::make_jwt_token(id);
-            let refresh_token = crypt::token::make_refresh_token(id);
-            controllers::tokens::create_token(&pool, id, &refresh_token).await?;
+            controllers::kdf::set(&pool, id, &kdf).await?;
+            if let Some(srp) = &user_data.srp {
+                srp.migrate(&pool, id).await?;
+            }
+            let email =
+                controllers::email_verifications::start_verification(&pool, id, &user_data.email)
+                    .await?;
+            mail::send_in_background(mailer, email);
+
+            if controllers::email_verifications::enforcement() == Enforcement::Login {
+                let resp = VerificationPendingResponse {
+                    email_verification_required: true,
+                };
+                return Ok((StatusCode::CREATED, Json(resp)).into_response());
+            }
+
+            let session = controllers::tokens::create_token(
+                &pool,
+                id,
+                user_data.device_name.as_deref(),
+                &client,
+            )
+            .await?;
+            let jwt_token =
+                controllers::tokens::issue_access_token(&pool, id, &session.session_id).await?;
 
-            let resp = TokensResponse {
+            return Ok(token_pair(
+                StatusCode::CREATED,
+                client.session_cookies,
                 jwt_token,
-                refresh_token,
-            };
-            return Ok((StatusCode::CREATED, Json(resp)).into_response());
+                session.refresh_token,
+            ));
         }
         Err(why) => {
             tracing::error!("Could not create user: {}", why);
@@ -87,14 +255,89 @@ pub async fn register(
     }
 }
 
+pub fn account_locked(locked_until: DateTime<Utc>) -> Response {
+    let retry_after = (locked_until - Utc::now()).num_seconds().max(1);
+    let mut resp = error_response!(
+        StatusCode::TOO_MANY_REQUESTS,
+        ErrorTypes::AccountLocked,
+        "Too many failed attempts, try again in {} seconds",
+        retry_after
+    );
+    resp.headers_mut()
+        .insert(header::RETRY_AFTER, retry_after.into());
+    resp
+}
+
+/// Checked before any credentials are looked at. Without `user_id` only the client
+/// address is checked
+pub async fn login_lock(
+    pool: &MySqlPool,
+    user_id: Option<u32>,
+    client: &ClientInfo,
+) -> anyhow::Result<Option<Response>> {
+    let locked_until = match (user_id, &client.ip_address) {
+        (Some(user_id), _) => {
+            controllers::login_failures::locked_until(pool, Scope::Account, &user_id.to_string())
+                .await?
+        }
+        (None, Some(ip_address)) => {
+            controllers::login_failures::locked_until(pool, Scope::Ip, ip_address).await?
+        }
+        (None, None) => None,
+    };
+    Ok(locked_until.map(account_locked))
+}
+
+/// Counts a wrong password or code against the account (if known) and the client address
+pub async fn login_failed(
+    pool: &MySqlPool,
+    user_id: Option<u32>,
+    client: &ClientInfo,
+) -> anyhow::Result<()> {
+    if let Some(user_id) = user_id {
+        controllers::login_failures::record_failure(pool, Scope::Account, &user_id.to_string())
+            .await?;
+    }
+    if let Some(ip_address) = &client.ip_address {
+        controllers::login_failures::record_failure(pool, Scope::Ip, ip_address).await?;
+    }
+    Ok(())
+}
+
+/// Re-checks the password of a signed in user before a sensitive change. Wrong guesses count
+/// like failed logins, so a stolen access token can't be used to find the password
+pub async fn confirm_password(
+    pool: &MySqlPool,
+    user_id: u32,
+    password: &str,
+    client: &ClientInfo,
+) -> anyhow::Result<Option<Response>> {
+    if let Some(locked) = login_lock(pool, Some(user_id), client).await? {
+        return Ok(Some(locked));
+    }
+    if !controllers::users::verify_password(pool, user_id, password).await? {
+        login_failed(pool, Some(user_id), client).await?;
+        return Ok(Some(handlers::mfa::invalid_credentials()));
+    }
+    Ok(None)
+}
+
+/// Only the account is forgiven, an address that guessed one password right
+/// shouldn't get a fresh start against every other account
+pub async fn login_succeeded(pool: &MySqlPool, user_id: u32) -> anyhow::Result<()> {
+    controllers::login_failures::clear(pool, Scope::Account, &user_id.to_string()).await
+}
+
 pub async fn login(
     State(pool): State<MySqlPool>,
+    client: ClientInfo,
     Json(user_data): Json<UserLogin>,
 ) -> Result<Response, AppError> {
     if (user_data.password.is_empty()
         || user_data.password.len() < 4
         || user_data.password.len() > 64)
         || (user_data.email.is_empty())
+        || !is_valid_device_name(user_data.device_name.as_deref())
     {
         return Ok(error_response!(
             StatusCode::BAD_REQUEST,
@@ -103,12 +346,22 @@ pub async fn login(
         ));
     }
 
-    let user_id = controllers::users::id_by_email(&pool, &user_data.email).await?;
+    if let Some(locked) = login_lock(&pool, None, &client).await? {
+        return Ok(locked);
+    }
+    let Some(user_id) = controllers::users::find_by_email(&pool, &user_data.email).await? else {
+        login_failed(&pool, None, &client).await?;
+        return Ok(handlers::mfa::invalid_credentials());
+    };
+    if let Some(locked) = login_lock(&pool, Some(user_id), &client).await? {
+        return Ok(locked);
+    }
 
     let user_password_hash = controllers::users::get_password_hash(&pool, user_id).await?;
 
     if let Err(why) = crypt::password::verify_password(&user_data.password, &user_password_hash) {
         tracing::error!("Could not verify user: {}", why);
+        login_failed(&pool, Some(user_id), &client).await?;
 
         return Ok(error_response!(
             StatusCode::UNAUTHORIZED,
@@ -118,32 +371,130 @@ pub async fn login(
         ));
     }
 
-    let jwt_token = crypt::token::make_jwt_token(user_id);
-    let refresh_token = crypt::token::make_refresh_token(user_id);
-    controllers::tokens::create_token(&pool, user_id, &refresh_token).await?;
+    if let Some(srp) = &user_data.srp {
+        srp.migrate(&pool, user_id).await?;
+    }
 
-    let resp = TokensResponse {
-        jwt_token,
-        refresh_token,
+    Ok(first_factor_passed(&pool, user_id, user_data.device_name, &client).await?)
+}
+
+/// Password (or its SRP proof) was fine, but the real tokens are only handed out
+/// once the second factor is in. Failures are forgiven only then, otherwise logging in
+/// again would reset the count of wrong codes
+pub async fn first_factor_passed(
+    pool: &MySqlPool,
+    user_id: u32,
+    device_name: Option<String>,
+    client: &ClientInfo,
+) -> anyhow::Result<Response> {
+    let mut methods = Vec::new();
+    if controllers::mfa::is_enabled(pool, user_id).await? {
+        methods.push("totp");
+    }
+    if controllers::webauthn::has_credentials(pool, user_id).await? {
+        methods.push("webauthn");
+    }
+    if !methods.is_empty() {
+        let resp = MfaPendingResponse {
+            mfa_token: controllers::mfa::issue_token(pool, user_id, device_name).await?,
+            methods,
+        };
+        return Ok((StatusCode::ACCEPTED, Json(resp)).into_response());
+    }
+
+    let resp = tokens_response(pool, user_id, device_name.as_deref(), client).await?;
+    login_succeeded(pool, user_id).await?;
+    Ok(resp)
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SecondFactorLogin {
+    mfa_token: String,
+    // TOTP code or one of the recovery codes
+    code: String,
+}
+
+pub fn mfa_token_used() -> Response {
+    error_response!(
+        StatusCode::UNAUTHORIZED,
+        ErrorTypes::MfaTokenExpired,
+        "MFA token was already used or took too many wrong codes, log in again"
+    )
+}
+
+pub fn mfa_token_expired(why: anyhow::Error) -> Response {
+    error_response!(
+        StatusCode::UNAUTHORIZED,
+        ErrorTypes::MfaTokenExpired,
+        "MFA token is invalid or expired, log in again: {}",
+        why
+    )
+}
+
+pub async fn login_2fa(
+    State(pool): State<MySqlPool>,
+    client: ClientInfo,
+    Json(data): Json<SecondFactorLogin>,
+) -> Result<Response, AppError> {
+    let claims = match crypt::token::verify_mfa_token(&data.mfa_token) {
+        Ok(claims) => claims,
+        Err(why) => return Ok(mfa_token_expired(why)),
     };
-    return Ok((StatusCode::OK, Json(resp)).into_response());
+
+    if let Some(locked) = login_lock(&pool, Some(claims.id), &client).await? {
+        return Ok(locked);
+    }
+    if !controllers::mfa::count_attempt(&pool, &claims.jti).await? {
+        return Ok(mfa_token_used());
+    }
+    if !controllers::mfa::verify(&pool, claims.id, &data.code).await? {
+        login_failed(&pool, Some(claims.id), &client).await?;
+        return Ok(handlers::mfa::invalid_code());
+    }
+    if !controllers::mfa::redeem_token(&pool, &claims.jti).await? {
+        return Ok(mfa_token_used());
+    }
+
+    let resp = tokens_response(&pool, claims.id, claims.device_name.as_deref(), &client).await?;
+    login_succeeded(&pool, claims.id).await?;
+    Ok(resp)
 }
 
 pub async fn update_jwt_token(
     State(pool): State<MySqlPool>,
+    client: ClientInfo,
     refresh_header: RefreshHeader,
 ) -> Result<Response, AppError> {
-    if let Err(why) = controllers::tokens::token_exists(&pool, &refresh_header.token).await {
-        return Ok(error_response!(
-            StatusCode::FORBIDDEN,
-            ErrorTypes::RefreshTokenExpired,
-            "Refresh token expired: {}",
-            why
-        ));
-    }
+    let (user_id, session_id, refresh_token) =
+        match controllers::tokens::rotate_token(&pool, &refresh_header.token, &client).await? {
+            Rotation::Rotated {
+                user_id,
+                session_id,
+                refresh_token,
+            } => (user_id, session_id, refresh_token),
+            Rotation::Unknown => {
+                return Ok(error_response!(
+                    StatusCode::FORBIDDEN,
+                    ErrorTypes::RefreshTokenExpired,
+                    "Refresh token expired"
+                ));
+            }
+            Rotation::Reused => {
+                return Ok(error_response!(
+                    StatusCode::FORBIDDEN,
+                    ErrorTypes::RefreshTokenReused,
+                    "Refresh token was already used, log in again"
+                ));
+            }
+        };
 
-    let jwt_token = crypt::token::make_jwt_token(refresh_header.claims.id);
-    return Ok((StatusCode::OK, jwt_token.to_string()).into_response());
+    let jwt_token = controllers::tokens::issue_access_token(&pool, user_id, &session_id).await?;
+    Ok(token_pair(
+        StatusCode::OK,
+        client.session_cookies || refresh_header.from_cookie,
+        jwt_token,
+        refresh_token,
+    ))
 }
 
 #[derive(Serialize, Deserialize)]
@@ -162,6 +513,15 @@ pub async fn validate(Query(data): Query<QueryValidate>) -> Result<Response, Res
     Ok((StatusCode::OK).into_response())
 }
 
+/// Public keys other services can check access tokens with, matched by the token's `kid`
+pub async fn jwks() -> Response {
+    (
+        [(header::CACHE_CONTROL, "public, max-age=300")],
+        Json(&crypt::keys::get().jwks),
+    )
+        .into_response()
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct LogoutBody {
     refresh_token: String,
@@ -176,3 +536,74 @@ pub async fn logout(
     }
     Ok((StatusCode::OK).into_response())
 }
+
+/// Logout for cookie mode, the refresh cookie only ever reaches the refresh path
+pub async fn logout_cookie(
+    State(pool): State<MySqlPool>,
+    jar: CookieJar,
+    refresh_header: RefreshHeader,
+) -> Result<Response, Response> {
+    if let Err(why) = controllers::tokens::delete_token(&pool, &refresh_header.token).await {
+        tracing::error!("Err deleting rtoken: {}", why);
+    }
+    let jar = jar
+        .remove(Cookie::build(token::REFRESH_COOKIE).path(refresh_cookie_path()))
+        .remove(Cookie::build(token::CSRF_COOKIE).path("/"));
+    Ok((StatusCode::OK, jar).into_response())
+}
+
+fn log_purge(what: &str, result: anyhow::Result<u64>) {
+    match result {
+        Ok(0) => {}
+        Ok(removed) => tracing::info!("Purged {} expired {}", removed, what),
+        Err(why) => tracing::error!("Could not purge expired {}: {}", what, why),
+    }
+}
+
+/// Runs forever, dropping tokens and pending changes nobody can use anymore
+pub async fn expire_tokens(pool: MySqlPool) {
+    let mut interval = tokio::time::interval(Duration::from_secs(60 * 60));
+    loop {
+        interval.tick().await;
+        log_purge(
+            "refresh tokens",
+            controllers::tokens::delete_expired(&pool).await,
+        );
+        log_purge(
+            "access tokens",
+            controllers::tokens::delete_expired_access_tokens(&pool).await,
+        );
+        log_purge(
+            "password reset tokens",
+            controllers::password_resets::delete_expired(&pool).await,
+        );
+        log_purge(
+            "email verification tokens",
+            controllers::email_verifications::delete_expired(&pool).await,
+        );
+        log_purge(
+            "email changes",
+            controllers::email_changes::delete_expired(&pool).await,
+        );
+        log_purge(
+            "MFA tokens",
+            controllers::mfa::delete_expired_tokens(&pool).await,
+        );
+        log_purge(
+            "login failure counters",
+            controllers::login_failures::delete_stale(&pool).await,
+        );
+    }
+}
+
+/// Runs forever, keeping the access token denylist in step with the database.
+/// Revocations made here apply at once, this picks up the ones made by other instances
+pub async fn sync_denylist(pool: MySqlPool) {
+    let mut interval = tokio::time::interval(Duration::from_secs(30));
+    loop {
+        interval.tick().await;
+        if let Err(why) = controllers::tokens::sync_denylist(&pool).await {
+            tracing::error!("Could not load revoked access tokens: {}", why);
+        }
+    }
+}
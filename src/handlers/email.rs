@@ -0,0 +1,97 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use sqlx::MySqlPool;
+
+use crate::{
+    common::error::{AppError, ErrorTypes},
+    controllers::{self, email_verifications::Enforcement},
+    crypt::token::AuthHeader,
+    error_response,
+    mail::{self, Mailer},
+};
+
+pub fn email_not_verified() -> Response {
+    error_response!(
+        StatusCode::FORBIDDEN,
+        ErrorTypes::EmailNotVerified,
+        "Confirm your email first"
+    )
+}
+
+/// Keeps unverified users away from the routes it wraps when `EMAIL_VERIFICATION=storage`
+pub async fn require_verified_email(
+    State(pool): State<MySqlPool>,
+    auth_header: AuthHeader,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    if controllers::email_verifications::enforcement() == Enforcement::Storage
+        && !controllers::email_verifications::is_verified(&pool, auth_header.claims.id).await?
+    {
+        return Ok(email_not_verified());
+    }
+    Ok(next.run(request).await)
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct VerifyEmail {
+    token: String,
+}
+
+pub async fn verify_email(
+    State(pool): State<MySqlPool>,
+    Json(data): Json<VerifyEmail>,
+) -> Result<Response, AppError> {
+    if !controllers::email_verifications::verify(&pool, &data.token).await? {
+        return Ok(error_response!(
+            StatusCode::BAD_REQUEST,
+            ErrorTypes::InvalidVerificationToken,
+            "Verification token is invalid or expired"
+        ));
+    }
+    Ok((StatusCode::NO_CONTENT).into_response())
+}
+
+pub async fn resend_verification(
+    State(pool): State<MySqlPool>,
+    State(mailer): State<Arc<dyn Mailer>>,
+    auth_header: AuthHeader,
+) -> Result<Response, AppError> {
+    let user_id = auth_header.claims.id;
+    if controllers::email_verifications::is_verified(&pool, user_id).await? {
+        return Ok(error_response!(
+            StatusCode::CONFLICT,
+            ErrorTypes::EmailAlreadyVerified,
+            "Email is already verified"
+        ));
+    }
+    if let Some(allowed_at) =
+        controllers::email_verifications::resend_allowed_at(&pool, user_id).await?
+    {
+        let retry_after = (allowed_at - Utc::now()).num_seconds().max(1);
+        let mut resp = error_response!(
+            StatusCode::TOO_MANY_REQUESTS,
+            ErrorTypes::TooManyRequests,
+            "Verification email was sent recently, try again in {} seconds",
+            retry_after
+        );
+        resp.headers_mut()
+            .insert(header::RETRY_AFTER, retry_after.into());
+        return Ok(resp);
+    }
+
+    let email = controllers::users::get_email(&pool, user_id).await?;
+    let email =
+        controllers::email_verifications::start_verification(&pool, user_id, &email).await?;
+    mail::send_in_background(mailer, email);
+    Ok((StatusCode::ACCEPTED).into_response())
+}
@@ -0,0 +1,209 @@
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::MySqlPool;
+
+use crate::{
+    common::{
+        client::ClientInfo,
+        error::{AppError, ErrorTypes},
+    },
+    controllers::{self, activity::Activity, kdf::KdfParams},
+    crypt::token::AuthHeader,
+    error_response,
+    handlers::{account::bad_data, auth::confirm_password, srp::SrpRecord},
+};
+
+#[derive(Serialize, Deserialize)]
+pub struct Prelogin {
+    email: String,
+}
+
+/// Tells the client how to derive keys from the master password before it logs in.
+/// Answers for any email, made up parameters don't reveal whether an account exists
+pub async fn prelogin(
+    State(pool): State<MySqlPool>,
+    Json(data): Json<Prelogin>,
+) -> Result<Response, AppError> {
+    let params = controllers::kdf::for_email(&pool, &data.email).await?;
+    Ok((StatusCode::OK, Json(params)).into_response())
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ChangeKdf {
+    password: String,
+    kdf: KdfParams,
+    // What the client derives from the master password changes with the KDF, so it comes
+    // along with the settings, like a password change
+    new_password: String,
+    #[serde(default)]
+    srp: Option<SrpRecord>,
+}
+
+pub async fn change_kdf(
+    State(pool): State<MySqlPool>,
+    auth_header: AuthHeader,
+    client: ClientInfo,
+    Json(data): Json<ChangeKdf>,
+) -> Result<Response, AppError> {
+    let user_id = auth_header.claims.id;
+    let Some(settings) = data.kdf.to_settings() else {
+        return Ok(error_response!(
+            StatusCode::BAD_REQUEST,
+            ErrorTypes::BadData,
+            "Unsupported or too weak KDF parameters"
+        ));
+    };
+    if data.new_password.len() < 4 || data.new_password.len() > 64 {
+        return Ok(bad_data());
+    }
+    if let Some(denied) = confirm_password(&pool, user_id, &data.password, &client).await? {
+        return Ok(denied);
+    }
+
+    controllers::kdf::set(&pool, user_id, &settings).await?;
+    controllers::users::change_password(&pool, user_id, &data.new_password).await?;
+    controllers::srp::delete_verifier(&pool, user_id).await?;
+    if let Some(srp) = &data.srp {
+        srp.migrate(&pool, user_id).await?;
+    }
+    controllers::tokens::delete_other_sessions(&pool, user_id, &auth_header.claims.sid).await?;
+    controllers::activity::record(
+        &pool,
+        user_id,
+        Activity::KdfChanged,
+        Some(&settings.kdf_algorithm),
+        &client,
+    )
+    .await;
+    Ok((StatusCode::NO_CONTENT).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::{engine::general_purpose::STANDARD, Engine};
+
+    use super::*;
+    use crate::{
+        bootstrap,
+        common::testing::{auth_header, client, json, setup},
+        controllers::tokens::Rotation,
+    };
+
+    const EMAIL: &str = "kdf@example.com";
+    const OLD_PASSWORD: &str = "old password";
+    const NEW_PASSWORD: &str = "new password";
+
+    async fn change(pool: &MySqlPool, user_id: u32, sid: &str, password: &str) -> Response {
+        change_kdf(
+            State(pool.clone()),
+            auth_header(user_id, sid),
+            client(),
+            Json(ChangeKdf {
+                password: password.to_owned(),
+                kdf: KdfParams {
+                    algorithm: "pbkdf2-sha256".to_owned(),
+                    iterations: 600_000,
+                    memory: None,
+                    parallelism: None,
+                    salt: STANDARD.encode([7; 16]),
+                },
+                new_password: NEW_PASSWORD.to_owned(),
+                srp: None,
+            }),
+        )
+        .await
+        .unwrap()
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn change_replaces_password_and_keeps_current_session(pool: MySqlPool) {
+        let user_id = setup(&pool, EMAIL, OLD_PASSWORD).await;
+        let current = controllers::tokens::create_token(&pool, user_id, None, &client())
+            .await
+            .unwrap();
+        let other = controllers::tokens::create_token(&pool, user_id, None, &client())
+            .await
+            .unwrap();
+
+        let response = change(&pool, user_id, &current.session_id, OLD_PASSWORD).await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        assert!(
+            controllers::users::verify_password(&pool, user_id, NEW_PASSWORD)
+                .await
+                .unwrap()
+        );
+        assert!(matches!(
+            controllers::tokens::rotate_token(&pool, &current.refresh_token, &client())
+                .await
+                .unwrap(),
+            Rotation::Rotated { .. }
+        ));
+        assert!(matches!(
+            controllers::tokens::rotate_token(&pool, &other.refresh_token, &client())
+                .await
+                .unwrap(),
+            Rotation::Unknown
+        ));
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn wrong_passwords_lock_the_account(pool: MySqlPool) {
+        let user_id = setup(&pool, EMAIL, OLD_PASSWORD).await;
+        for _ in 0..6 {
+            let response = change(&pool, user_id, "session", "wrong password").await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        }
+
+        let response = change(&pool, user_id, "session", OLD_PASSWORD).await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(
+            controllers::users::verify_password(&pool, user_id, OLD_PASSWORD)
+                .await
+                .unwrap()
+        );
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn legacy_salt_is_only_stored_at_startup(pool: MySqlPool) {
+        let user_id = setup(&pool, EMAIL, OLD_PASSWORD).await;
+        sqlx::query("UPDATE users SET kdf_salt = NULL WHERE id = ?")
+            .bind(user_id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let ask = || async {
+            let response = prelogin(
+                State(pool.clone()),
+                Json(Prelogin {
+                    email: EMAIL.to_owned(),
+                }),
+            )
+            .await
+            .unwrap();
+            json(response).await["salt"].clone()
+        };
+
+        let shown = ask().await;
+        let stored: Option<Vec<u8>> = sqlx::query_scalar("SELECT kdf_salt FROM users WHERE id = ?")
+            .bind(user_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert!(stored.is_none());
+
+        bootstrap::kdf_salts(&pool).await.unwrap();
+        let stored: Option<Vec<u8>> = sqlx::query_scalar("SELECT kdf_salt FROM users WHERE id = ?")
+            .bind(user_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(STANDARD.encode(stored.unwrap()), shown.as_str().unwrap());
+        assert_eq!(ask().await, shown);
+    }
+}
@@ -0,0 +1,129 @@
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::MySqlPool;
+
+use crate::{
+    common::{
+        client::ClientInfo,
+        error::{AppError, ErrorTypes},
+    },
+    controllers,
+    crypt::token::AuthHeader,
+    error_response,
+    handlers::auth::{confirm_password, login_failed},
+};
+
+pub fn invalid_credentials() -> Response {
+    error_response!(
+        StatusCode::UNAUTHORIZED,
+        ErrorTypes::InvalidCreds,
+        "Invalid credentials"
+    )
+}
+
+pub fn invalid_code() -> Response {
+    error_response!(
+        StatusCode::UNAUTHORIZED,
+        ErrorTypes::InvalidTwoFactorCode,
+        "Invalid two-factor code"
+    )
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct EnrollBody {
+    password: String,
+}
+
+pub async fn enroll_totp(
+    State(pool): State<MySqlPool>,
+    auth_header: AuthHeader,
+    client: ClientInfo,
+    Json(data): Json<EnrollBody>,
+) -> Result<Response, AppError> {
+    let user_id = auth_header.claims.id;
+    if let Some(denied) = confirm_password(&pool, user_id, &data.password, &client).await? {
+        return Ok(denied);
+    }
+
+    let Some(enrollment) = controllers::mfa::start_enrollment(&pool, user_id).await? else {
+        return Ok(error_response!(
+            StatusCode::CONFLICT,
+            ErrorTypes::TwoFactorAlreadyEnabled,
+            "Two-factor authentication is already enabled"
+        ));
+    };
+    Ok((StatusCode::OK, Json(enrollment)).into_response())
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ConfirmBody {
+    code: String,
+}
+
+#[derive(Serialize)]
+struct RecoveryCodesResponse {
+    recovery_codes: Vec<String>,
+}
+
+pub async fn confirm_totp(
+    State(pool): State<MySqlPool>,
+    auth_header: AuthHeader,
+    Json(data): Json<ConfirmBody>,
+) -> Result<Response, AppError> {
+    let user_id = auth_header.claims.id;
+    if controllers::mfa::is_enabled(&pool, user_id).await? {
+        return Ok(error_response!(
+            StatusCode::CONFLICT,
+            ErrorTypes::TwoFactorAlreadyEnabled,
+            "Two-factor authentication is already enabled"
+        ));
+    }
+
+    let Some(recovery_codes) =
+        controllers::mfa::confirm_enrollment(&pool, user_id, data.code.trim()).await?
+    else {
+        return Ok(invalid_code());
+    };
+    Ok((
+        StatusCode::OK,
+        Json(RecoveryCodesResponse { recovery_codes }),
+    )
+        .into_response())
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct DisableBody {
+    password: String,
+    code: String,
+}
+
+pub async fn disable_totp(
+    State(pool): State<MySqlPool>,
+    auth_header: AuthHeader,
+    client: ClientInfo,
+    Json(data): Json<DisableBody>,
+) -> Result<Response, AppError> {
+    let user_id = auth_header.claims.id;
+    if !controllers::mfa::is_enabled(&pool, user_id).await? {
+        return Ok(error_response!(
+            StatusCode::CONFLICT,
+            ErrorTypes::TwoFactorNotEnabled,
+            "Two-factor authentication is not enabled"
+        ));
+    }
+    if let Some(denied) = confirm_password(&pool, user_id, &data.password, &client).await? {
+        return Ok(denied);
+    }
+    if !controllers::mfa::verify(&pool, user_id, &data.code).await? {
+        login_failed(&pool, Some(user_id), &client).await?;
+        return Ok(invalid_code());
+    }
+
+    controllers::mfa::disable(&pool, user_id).await?;
+    Ok((StatusCode::NO_CONTENT).into_response())
+}
@@ -1,2 +1,12 @@
+pub mod account;
 pub mod auth;
+pub mod email;
+pub mod kdf;
+pub mod mfa;
+pub mod password;
+pub mod sessions;
+pub mod srp;
 pub mod storage;
+pub mod uploads;
+pub mod vaults;
+pub mod webauthn;
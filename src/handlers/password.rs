@@ -0,0 +1,225 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::MySqlPool;
+
+use crate::{
+    common::{
+        client::ClientInfo,
+        error::{AppError, ErrorTypes},
+    },
+    controllers::{self, activity::Activity},
+    error_response,
+    mail::{self, Mailer},
+};
+
+#[derive(Serialize, Deserialize)]
+pub struct ForgotPassword {
+    email: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ResetPassword {
+    token: String,
+    password: String,
+}
+
+/// Always accepted, so it can't be used to find out which emails are registered. Even the
+/// lookup happens after answering, the time known accounts take to set up would give it away
+pub async fn forgot_password(
+    State(pool): State<MySqlPool>,
+    State(mailer): State<Arc<dyn Mailer>>,
+    Json(data): Json<ForgotPassword>,
+) -> Result<Response, AppError> {
+    tokio::spawn(async move {
+        match controllers::password_resets::request_reset(&pool, &data.email).await {
+            Ok(Some(email)) => mail::send_in_background(mailer, email),
+            Ok(None) => {}
+            Err(why) => tracing::error!("Could not start a password reset: {}", why),
+        }
+    });
+    Ok((StatusCode::ACCEPTED).into_response())
+}
+
+pub async fn reset_password(
+    State(pool): State<MySqlPool>,
+    client: ClientInfo,
+    Json(data): Json<ResetPassword>,
+) -> Result<Response, AppError> {
+    if data.password.len() < 4 || data.password.len() > 64 {
+        return Ok(error_response!(
+            StatusCode::BAD_REQUEST,
+            ErrorTypes::BadData,
+            "Provided data is bad"
+        ));
+    }
+
+    let Some(user_id) =
+        controllers::password_resets::reset_password(&pool, &data.token, &data.password).await?
+    else {
+        return Ok(error_response!(
+            StatusCode::BAD_REQUEST,
+            ErrorTypes::InvalidResetToken,
+            "Reset token is invalid or expired"
+        ));
+    };
+    controllers::activity::record(&pool, user_id, Activity::PasswordReset, None, &client).await;
+    Ok((StatusCode::NO_CONTENT).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{
+        common::testing::{client, json, setup},
+        controllers::tokens::Rotation,
+        database,
+        mail::memory::MemoryMailer,
+    };
+
+    const EMAIL: &str = "reset@example.com";
+    const OLD_PASSWORD: &str = "old password";
+    const NEW_PASSWORD: &str = "new password";
+
+    /// Asks for a reset and fishes the token out of the email
+    async fn request_token(pool: &MySqlPool) -> String {
+        let mailer = Arc::new(MemoryMailer::default());
+        let response = forgot_password(
+            State(pool.clone()),
+            State(mailer.clone() as Arc<dyn Mailer>),
+            Json(ForgotPassword {
+                email: EMAIL.to_owned(),
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+
+        // The email goes out in the background
+        for _ in 0..100 {
+            if let Some(email) = mailer.sent().pop() {
+                assert_eq!(email.to, EMAIL);
+                let link = email.body.split("\n\n").nth(1).unwrap();
+                return link.rsplit("token=").next().unwrap().to_owned();
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("no reset email was sent");
+    }
+
+    async fn reset(pool: &MySqlPool, token: &str) -> Response {
+        reset_password(
+            State(pool.clone()),
+            client(),
+            Json(ResetPassword {
+                token: token.to_owned(),
+                password: NEW_PASSWORD.to_owned(),
+            }),
+        )
+        .await
+        .unwrap()
+    }
+
+    async fn assert_invalid_token(response: Response) {
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            json(response).await["error_type"],
+            ErrorTypes::InvalidResetToken.as_str()
+        );
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn token_is_single_use(pool: MySqlPool) {
+        let user_id = setup(&pool, EMAIL, OLD_PASSWORD).await;
+        let token = request_token(&pool).await;
+
+        assert_eq!(reset(&pool, &token).await.status(), StatusCode::NO_CONTENT);
+        assert!(
+            controllers::users::verify_password(&pool, user_id, NEW_PASSWORD)
+                .await
+                .unwrap()
+        );
+        assert_invalid_token(reset(&pool, &token).await).await;
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn token_expires(pool: MySqlPool) {
+        let user_id = setup(&pool, EMAIL, OLD_PASSWORD).await;
+        let token = request_token(&pool).await;
+        sqlx::query(
+            "UPDATE password_resets SET expires_at = NOW() - INTERVAL 1 MINUTE WHERE user_id = ?",
+        )
+        .bind(user_id)
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        assert_invalid_token(reset(&pool, &token).await).await;
+        assert!(
+            controllers::users::verify_password(&pool, user_id, OLD_PASSWORD)
+                .await
+                .unwrap()
+        );
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn unknown_email_sends_nothing(pool: MySqlPool) {
+        setup(&pool, EMAIL, OLD_PASSWORD).await;
+        let mailer = Arc::new(MemoryMailer::default());
+        let response = forgot_password(
+            State(pool.clone()),
+            State(mailer.clone() as Arc<dyn Mailer>),
+            Json(ForgotPassword {
+                email: "nobody@example.com".to_owned(),
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(mailer.sent().is_empty());
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn reset_signs_out_and_drops_verifier(pool: MySqlPool) {
+        let user_id = setup(&pool, EMAIL, OLD_PASSWORD).await;
+        let session = controllers::tokens::create_token(&pool, user_id, None, &client())
+            .await
+            .unwrap();
+        controllers::tokens::issue_access_token(&pool, user_id, &session.session_id)
+            .await
+            .unwrap();
+        database::srp::set_verifier(&pool, user_id, &[1; 16], &[2; 256])
+            .await
+            .unwrap();
+
+        let token = request_token(&pool).await;
+        assert_eq!(reset(&pool, &token).await.status(), StatusCode::NO_CONTENT);
+
+        assert!(matches!(
+            controllers::tokens::rotate_token(&pool, &session.refresh_token, &client())
+                .await
+                .unwrap(),
+            Rotation::Unknown
+        ));
+        assert_eq!(
+            database::access_tokens::list_revoked(&pool)
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+        assert!(database::srp::get_verifier(&pool, user_id)
+            .await
+            .unwrap()
+            .is_none());
+    }
+}
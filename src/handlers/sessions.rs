@@ -0,0 +1,46 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use sqlx::MySqlPool;
+
+use crate::{
+    common::error::{AppError, ErrorTypes},
+    controllers,
+    crypt::token::AuthHeader,
+    error_response,
+};
+
+pub async fn list_sessions(
+    State(pool): State<MySqlPool>,
+    auth_header: AuthHeader,
+) -> Result<Response, AppError> {
+    let sessions = controllers::tokens::list_sessions(&pool, auth_header.claims.id).await?;
+    Ok((StatusCode::OK, Json(sessions)).into_response())
+}
+
+pub async fn delete_session(
+    State(pool): State<MySqlPool>,
+    auth_header: AuthHeader,
+    Path(session_id): Path<String>,
+) -> Result<Response, AppError> {
+    if !controllers::tokens::delete_session(&pool, auth_header.claims.id, &session_id).await? {
+        return Ok(error_response!(
+            StatusCode::NOT_FOUND,
+            ErrorTypes::SessionNotExists,
+            "Session {} does not exist",
+            session_id
+        ));
+    }
+    Ok((StatusCode::NO_CONTENT).into_response())
+}
+
+pub async fn logout_all(
+    State(pool): State<MySqlPool>,
+    auth_header: AuthHeader,
+) -> Result<Response, AppError> {
+    controllers::tokens::delete_user_tokens(&pool, auth_header.claims.id).await?;
+    Ok((StatusCode::OK).into_response())
+}
@@ -0,0 +1,141 @@
+use axum::{
+    extract::State,
+    http::{HeaderName, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::MySqlPool;
+
+use crate::{
+    common::{
+        client::ClientInfo,
+        error::{AppError, ErrorTypes},
+    },
+    controllers::{self, srp::Proof},
+    crypt::token::AuthHeader,
+    error_response,
+    handlers::{
+        auth::{confirm_password, first_factor_passed, login_failed, login_lock},
+        mfa::invalid_credentials,
+    },
+};
+
+/// Lets the client check that the server knew the verifier, i.e. it's not talking to an impostor
+pub const SERVER_PROOF: HeaderName = HeaderName::from_static("srp-server-proof");
+
+fn bad_data() -> Response {
+    error_response!(
+        StatusCode::BAD_REQUEST,
+        ErrorTypes::BadData,
+        "Provided data is bad"
+    )
+}
+
+/// Salt and verifier computed by the client, hex. See `crypt::srp` for the math
+#[derive(Serialize, Deserialize)]
+pub struct SrpRecord {
+    salt: String,
+    verifier: String,
+}
+
+impl SrpRecord {
+    /// Stored next to a password login, so the user can log in with SRP from now on.
+    /// A broken record only costs the migration, not the login
+    pub async fn migrate(&self, pool: &MySqlPool, user_id: u32) -> anyhow::Result<()> {
+        if !controllers::srp::set_verifier(pool, user_id, &self.salt, &self.verifier).await? {
+            tracing::warn!("Ignoring unusable SRP verifier of user {}", user_id);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SetVerifier {
+    password: String,
+    #[serde(flatten)]
+    srp: SrpRecord,
+}
+
+pub async fn set_verifier(
+    State(pool): State<MySqlPool>,
+    auth_header: AuthHeader,
+    client: ClientInfo,
+    Json(data): Json<SetVerifier>,
+) -> Result<Response, AppError> {
+    let user_id = auth_header.claims.id;
+    if let Some(denied) = confirm_password(&pool, user_id, &data.password, &client).await? {
+        return Ok(denied);
+    }
+    if !controllers::srp::set_verifier(&pool, user_id, &data.srp.salt, &data.srp.verifier).await? {
+        return Ok(bad_data());
+    }
+    Ok((StatusCode::NO_CONTENT).into_response())
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LoginStart {
+    email: String,
+    // Client's public value `A`, hex
+    a: String,
+}
+
+pub async fn login_start(
+    State(pool): State<MySqlPool>,
+    client: ClientInfo,
+    Json(data): Json<LoginStart>,
+) -> Result<Response, AppError> {
+    if let Some(locked) = login_lock(&pool, None, &client).await? {
+        return Ok(locked);
+    }
+    let user_id = controllers::users::find_by_email(&pool, &data.email).await?;
+    if let Some(locked) = login_lock(&pool, user_id, &client).await? {
+        return Ok(locked);
+    }
+
+    match controllers::srp::start_login(&pool, user_id, &data.email, &data.a).await? {
+        Some(challenge) => Ok((StatusCode::OK, Json(challenge)).into_response()),
+        None => Ok(bad_data()),
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LoginFinish {
+    session_id: String,
+    // Client's proof `M1`, hex
+    m1: String,
+    #[serde(default)]
+    device_name: Option<String>,
+}
+
+/// Ends like a password login: tokens, or an MFA token if a second factor is needed
+pub async fn login_finish(
+    State(pool): State<MySqlPool>,
+    client: ClientInfo,
+    Json(data): Json<LoginFinish>,
+) -> Result<Response, AppError> {
+    if data
+        .device_name
+        .as_deref()
+        .is_some_and(|name| name.is_empty() || name.chars().count() > 64)
+    {
+        return Ok(bad_data());
+    }
+
+    let (user_id, server_proof) =
+        match controllers::srp::finish_login(&pool, &data.session_id, &data.m1).await? {
+            Proof::Verified {
+                user_id,
+                server_proof,
+            } => (user_id, server_proof),
+            Proof::Rejected { user_id } => {
+                login_failed(&pool, user_id, &client).await?;
+                return Ok(invalid_credentials());
+            }
+        };
+
+    let mut resp = first_factor_passed(&pool, user_id, data.device_name, &client).await?;
+    resp.headers_mut()
+        .insert(SERVER_PROOF, HeaderValue::from_str(&server_proof)?);
+    Ok(resp)
+}
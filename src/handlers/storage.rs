@@ -1,90 +1,321 @@
-use std::{fs::OpenOptions, io::Write};
+use std::sync::Arc;
 
 use axum::{
-    extract::{Multipart, State},
+    extract::{Multipart, Path, Query, State},
     http::{
-        header::{CONTENT_DISPOSITION, CONTENT_TYPE}, HeaderMap, HeaderValue, StatusCode
+        header::{CONTENT_DISPOSITION, CONTENT_TYPE, ETAG, IF_MATCH, IF_NONE_MATCH},
+        HeaderMap, HeaderValue, StatusCode,
     },
     response::{IntoResponse, Response},
+    Json,
 };
-use minio::s3::{
-    types::{PartInfo, S3Api},
-};
+use futures::{StreamExt, TryStreamExt};
 use serde::{Deserialize, Serialize};
-
-const STORAGE_FILENAME: &str = "pmanager.pm";
+use sha2::{Digest, Sha256};
+use sqlx::MySqlPool;
 
 use crate::{
-    common::error::{error_response, AppError, ErrorTypes},
-    crypt::token::AuthHeader,
+    common::{
+        digest::{expected_sha256, sha256_headers},
+        error::{AppError, ErrorTypes},
+    },
+    controllers::{
+        self,
+        quota::{QuotaExceeded, StorageUsage},
+        versions::RetentionPolicy,
+    },
+    crypt::{
+        encryption::{decrypt_stream, encrypt_stream},
+        token::AuthHeader,
+    },
+    handlers::vaults::vault_not_exists,
+    store::VaultStore,
 };
 
+// Every write gets its own object, so a losing concurrent upload can't clobber the winner
+pub fn new_version_key(user_id: u32, vault_id: u32) -> String {
+    format!(
+        "{}/{}/{}.pm",
+        user_id,
+        vault_id,
+        hex::encode(rand::random::<[u8; 16]>())
+    )
+}
+
+pub fn revision_etag(revision: u32) -> String {
+    format!("\"{}\"", revision)
+}
+
+pub fn etag_matches(header: &HeaderValue, revision: u32, exists: bool) -> bool {
+    let etag = revision_etag(revision);
+    header
+        .to_str()
+        .unwrap_or_default()
+        .split(',')
+        .map(|tag| tag.trim().trim_start_matches("W/"))
+        .any(|tag| (tag == "*" && exists) || tag == etag)
+}
+
+pub fn quota_exceeded(usage: &StorageUsage) -> Response {
+    crate::error_response!(
+        StatusCode::PAYLOAD_TOO_LARGE,
+        ErrorTypes::QuotaExceeded,
+        "Storage quota exceeded, {} of {} bytes used and {} reserved by unfinished uploads",
+        usage.used,
+        usage.quota,
+        usage.pending
+    )
+}
+
+pub fn vault_conflict(revision: u32) -> Response {
+    let mut response = crate::error_response!(
+        StatusCode::PRECONDITION_FAILED,
+        ErrorTypes::VaultConflict,
+        "Vault was modified, current revision is {}",
+        revision
+    );
+    response.headers_mut().insert(
+        ETAG,
+        HeaderValue::from_str(&revision_etag(revision)).unwrap(),
+    );
+    response
+}
+
+pub fn invalid_digest() -> Response {
+    crate::error_response!(
+        StatusCode::BAD_REQUEST,
+        ErrorTypes::BadData,
+        "Content-Digest or Digest header has a malformed sha-256 value"
+    )
+}
+
+pub fn checksum_mismatch(expected: &str, checksum: &str) -> Response {
+    crate::error_response!(
+        StatusCode::BAD_REQUEST,
+        ErrorTypes::ChecksumMismatch,
+        "Expected sha-256 {} but received {}",
+        expected,
+        checksum
+    )
+}
+
+async fn prune_versions(
+    pool: &MySqlPool,
+    store: &dyn VaultStore,
+    vault_id: u32,
+) -> anyhow::Result<()> {
+    let versions = controllers::versions::list_versions(pool, vault_id).await?;
+    for version in RetentionPolicy::from_env().versions_to_prune(&versions) {
+        store.delete(&version.object_key).await?;
+        controllers::versions::delete_version(pool, vault_id, version.version).await?;
+    }
+    Ok(())
+}
+
+/// Records `object_key` as revision `base_revision + 1`. If another writer got there first
+/// `None` is returned and the object is left to the caller
+#[allow(clippy::too_many_arguments)]
+pub async fn commit_version(
+    pool: &MySqlPool,
+    store: &dyn VaultStore,
+    vault_id: u32,
+    base_revision: u32,
+    size: u64,
+    uploaded_by: u32,
+    checksum: &str,
+    object_key: &str,
+    encrypted: bool,
+) -> anyhow::Result<Option<u32>> {
+    let revision = base_revision + 1;
+    let created = controllers::versions::create_version(
+        pool,
+        vault_id,
+        revision,
+        size,
+        uploaded_by,
+        checksum,
+        object_key,
+        encrypted,
+    )
+    .await?;
+    if !created {
+        return Ok(None);
+    }
+
+    // The version is committed at this point, leftovers get pruned on the next upload
+    if let Err(why) = prune_versions(pool, store, vault_id).await {
+        tracing::error!("Could not prune versions of vault {}: {}", vault_id, why);
+    }
+    Ok(Some(revision))
+}
+
+/// Removes every stored version of the vault from object storage
+pub async fn purge_vault_objects(
+    pool: &MySqlPool,
+    store: &dyn VaultStore,
+    vault_id: u32,
+) -> anyhow::Result<()> {
+    for version in controllers::versions::list_versions(pool, vault_id).await? {
+        store.delete(&version.object_key).await?;
+    }
+    Ok(())
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct UploadReq {
     password: String,
 }
 
 pub async fn upload(
-    State(s3_client): State<minio::s3::Client>,
+    State(pool): State<MySqlPool>,
+    State(store): State<Arc<dyn VaultStore>>,
     auth_header: AuthHeader,
+    Path(vault_id): Path<u32>,
+    headers: HeaderMap,
     mut multipart: Multipart,
 ) -> Result<Response, AppError> {
     let user_id = auth_header.claims.id;
-    let filename = format!("{}/{}", user_id, STORAGE_FILENAME);
-
-    while let Some(mut field) = multipart.next_field().await? {
-        let multipart_upload = s3_client
-            .create_multipart_upload("user-storages", &filename)
-            .send()
-            .await?;
-        let mut parts: Vec<minio::s3::types::PartInfo> = Vec::new();
-
-        while let Some(chunk) = field.chunk().await? {
-            let response: minio::s3::response::UploadPartResponse = s3_client
-                .upload_part(
-                    &multipart_upload.bucket,
-                    &multipart_upload.object,
-                    &multipart_upload.upload_id,
-                    (parts.len() + 1) as u16, // Indexing of parts starts at 1
-                    chunk.clone().into(),
-                )
-                .send()
-                .await?;
-
-            let partinfo = PartInfo {
-                number: (parts.len() + 1) as u16,
-                etag: response.etag,
-                size: chunk.len() as u64,
-            };
-            parts.push(partinfo);
+    if controllers::vaults::get_vault(&pool, user_id, vault_id)
+        .await?
+        .is_none()
+    {
+        return Ok(vault_not_exists(vault_id));
+    }
+
+    let Some(if_match) = headers.get(IF_MATCH) else {
+        return Ok(crate::error_response!(
+            StatusCode::PRECONDITION_REQUIRED,
+            ErrorTypes::BadData,
+            "If-Match header is required"
+        ));
+    };
+    let revision = controllers::versions::current_revision(&pool, vault_id).await?;
+    if !etag_matches(if_match, revision, revision > 0) {
+        return Ok(vault_conflict(revision));
+    }
+    let Ok(expected) = expected_sha256(&headers) else {
+        return Ok(invalid_digest());
+    };
+
+    let Some(field) = multipart.next_field().await? else {
+        return Ok(crate::error_response!(
+            StatusCode::BAD_REQUEST,
+            ErrorTypes::BadData,
+            "No file provided"
+        ));
+    };
+    let filename = new_version_key(user_id, vault_id);
+    let usage = controllers::quota::usage(&pool, user_id).await?;
+    let data_key = controllers::users::data_key(&pool, user_id).await?;
+
+    // Hash, size and quota all refer to the plaintext, the stored object is slightly larger
+    let mut hasher = Sha256::new();
+    let mut size: u64 = 0;
+    let body = field
+        .map_err(anyhow::Error::from)
+        .inspect_ok(|chunk| {
+            hasher.update(chunk);
+            size += chunk.len() as u64;
+        })
+        .boxed();
+    let body = encrypt_stream(
+        &data_key,
+        controllers::quota::limit(body, usage.remaining()),
+    );
+    match store.put(&filename, body).await {
+        Ok(_) => {}
+        Err(why) if why.is::<QuotaExceeded>() => return Ok(quota_exceeded(&usage)),
+        Err(why) => return Err(why.into()),
+    }
+
+    let checksum = hex::encode(hasher.finalize());
+    if let Some(expected) = expected {
+        if expected != checksum {
+            store.delete(&filename).await?;
+            return Ok(checksum_mismatch(&expected, &checksum));
         }
-        let _: minio::s3::response::CompleteMultipartUploadResponse = s3_client
-            .complete_multipart_upload(
-                "user-storages",
-                &filename,
-                multipart_upload.upload_id,
-                parts,
-            )
-            .send()
-            .await?;
     }
 
-    Ok((StatusCode::OK).into_response())
+    let Some(revision) = commit_version(
+        &pool,
+        store.as_ref(),
+        vault_id,
+        revision,
+        size,
+        user_id,
+        &checksum,
+        &filename,
+        true,
+    )
+    .await?
+    else {
+        // Somebody else committed a new revision while we were uploading
+        store.delete(&filename).await?;
+        let revision = controllers::versions::current_revision(&pool, vault_id).await?;
+        return Ok(vault_conflict(revision));
+    };
+
+    let mut headers = HeaderMap::with_capacity(3);
+    headers.insert(
+        ETAG,
+        HeaderValue::from_str(&revision_etag(revision)).unwrap(),
+    );
+    headers.extend(sha256_headers(&checksum));
+    Ok((StatusCode::OK, headers).into_response())
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct DownloadQuery {
+    version: Option<u32>,
 }
 
 pub async fn download(
-    State(s3_client): State<minio::s3::Client>,
+    State(pool): State<MySqlPool>,
+    State(store): State<Arc<dyn VaultStore>>,
     auth_header: AuthHeader,
+    Path(vault_id): Path<u32>,
+    Query(query): Query<DownloadQuery>,
+    request_headers: HeaderMap,
 ) -> Result<Response, AppError> {
-    let user_id = auth_header.claims.id;
-    let filename = format!("{}/{}", user_id, STORAGE_FILENAME);
+    let Some(vault) =
+        controllers::vaults::get_vault(&pool, auth_header.claims.id, vault_id).await?
+    else {
+        return Ok(vault_not_exists(vault_id));
+    };
 
-    let response = match s3_client
-        .get_object("user-storages", &filename)
-        .send()
-        .await
-    {
-        Ok(response) => response,
+    let version = match query.version {
+        Some(version) => {
+            match controllers::versions::get_version(&pool, vault_id, version).await? {
+                Some(version) => Some(version),
+                None => {
+                    return Ok(crate::error_response!(
+                        StatusCode::NOT_FOUND,
+                        ErrorTypes::VersionNotExists,
+                        "Version {} does not exist",
+                        version
+                    ));
+                }
+            }
+        }
+        None => controllers::versions::latest_version(&pool, vault_id).await?,
+    };
+    let Some(version) = version else {
+        return Ok(crate::error_response!(
+            StatusCode::NOT_FOUND,
+            ErrorTypes::FileNotExists,
+            "Nothing was uploaded to this vault yet"
+        ));
+    };
+    let etag = HeaderValue::from_str(&revision_etag(version.version)).unwrap();
+
+    if let Some(if_none_match) = request_headers.get(IF_NONE_MATCH) {
+        if etag_matches(if_none_match, version.version, true) {
+            return Ok((StatusCode::NOT_MODIFIED, [(ETAG, etag)]).into_response());
+        }
+    }
+
+    let stream = match store.get(&version.object_key).await {
+        Ok(stream) => stream,
         Err(why) => {
             tracing::error!("Such file does not exist");
             return Ok(crate::error_response!(
@@ -96,12 +327,160 @@ pub async fn download(
         }
     };
 
-    let (stream, _size) = response.content.to_stream().await?;
+    let stream = if version.encrypted {
+        let data_key = controllers::users::data_key(&pool, auth_header.claims.id).await?;
+        decrypt_stream(&data_key, stream)
+    } else {
+        stream
+    };
     let body = axum::body::Body::from_stream(stream);
 
-    let mut headers = HeaderMap::with_capacity(2);
-    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/vnd.sqlite3"));
-    headers.insert(CONTENT_DISPOSITION, HeaderValue::from_str(format!("form-data; name=\"user\"; filename=\"pmanager.pm\"").as_str()).unwrap());
+    let mut headers = HeaderMap::with_capacity(6);
+    headers.insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static("application/vnd.sqlite3"),
+    );
+    headers.insert(
+        CONTENT_DISPOSITION,
+        HeaderValue::from_str(
+            format!("form-data; name=\"user\"; filename=\"{}.pm\"", vault.name).as_str(),
+        )
+        .unwrap(),
+    );
+    headers.insert(ETAG, etag);
+    headers.insert("X-Vault-Version", HeaderValue::from(version.version));
+    headers.extend(sha256_headers(&version.checksum));
 
     Ok((headers, body).into_response())
 }
+
+pub async fn list_versions(
+    State(pool): State<MySqlPool>,
+    auth_header: AuthHeader,
+    Path(vault_id): Path<u32>,
+) -> Result<Response, AppError> {
+    if controllers::vaults::get_vault(&pool, auth_header.claims.id, vault_id)
+        .await?
+        .is_none()
+    {
+        return Ok(vault_not_exists(vault_id));
+    }
+    let versions = controllers::versions::list_versions(&pool, vault_id).await?;
+    Ok((StatusCode::OK, Json(versions)).into_response())
+}
+
+pub async fn restore_version(
+    State(pool): State<MySqlPool>,
+    State(store): State<Arc<dyn VaultStore>>,
+    auth_header: AuthHeader,
+    Path((vault_id, version)): Path<(u32, u32)>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let user_id = auth_header.claims.id;
+    if controllers::vaults::get_vault(&pool, user_id, vault_id)
+        .await?
+        .is_none()
+    {
+        return Ok(vault_not_exists(vault_id));
+    }
+
+    // Restoring replaces the current contents like an upload, so it needs the same precondition
+    let Some(if_match) = headers.get(IF_MATCH) else {
+        return Ok(crate::error_response!(
+            StatusCode::PRECONDITION_REQUIRED,
+            ErrorTypes::BadData,
+            "If-Match header is required"
+        ));
+    };
+    let Some(source) = controllers::versions::get_version(&pool, vault_id, version).await? else {
+        return Ok(crate::error_response!(
+            StatusCode::NOT_FOUND,
+            ErrorTypes::VersionNotExists,
+            "Version {} does not exist",
+            version
+        ));
+    };
+
+    let revision = controllers::versions::current_revision(&pool, vault_id).await?;
+    if !etag_matches(if_match, revision, true) {
+        return Ok(vault_conflict(revision));
+    }
+
+    let usage = controllers::quota::usage(&pool, user_id).await?;
+    if source.size > usage.remaining() {
+        return Ok(quota_exceeded(&usage));
+    }
+
+    // Restoring never rewrites history, the old contents become the newest version.
+    // The copy stays sealed with the same user key, so it's stored as is
+    let filename = new_version_key(user_id, vault_id);
+    store.copy(&source.object_key, &filename).await?;
+    let Some(revision) = commit_version(
+        &pool,
+        store.as_ref(),
+        vault_id,
+        revision,
+        source.size,
+        user_id,
+        &source.checksum,
+        &filename,
+        source.encrypted,
+    )
+    .await?
+    else {
+        store.delete(&filename).await?;
+        let revision = controllers::versions::current_revision(&pool, vault_id).await?;
+        return Ok(vault_conflict(revision));
+    };
+
+    let restored = controllers::versions::get_version(&pool, vault_id, revision).await?;
+    Ok((
+        StatusCode::CREATED,
+        [(ETAG, revision_etag(revision))],
+        Json(restored),
+    )
+        .into_response())
+}
+
+pub async fn usage(
+    State(pool): State<MySqlPool>,
+    auth_header: AuthHeader,
+) -> Result<Response, AppError> {
+    let usage = controllers::quota::usage(&pool, auth_header.claims.id).await?;
+    Ok((StatusCode::OK, Json(usage)).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        common::testing::{auth_header, setup},
+        store::memory::MemoryStore,
+    };
+
+    #[sqlx::test(migrations = false)]
+    async fn restore_requires_if_match(pool: MySqlPool) {
+        let user_id = setup(&pool, "storage@example.com", "password").await;
+        let vault_id = controllers::vaults::create_vault(&pool, user_id, "vault")
+            .await
+            .unwrap();
+        let store: Arc<dyn VaultStore> = Arc::new(MemoryStore::default());
+        let restore = |headers: HeaderMap| {
+            restore_version(
+                State(pool.clone()),
+                State(store.clone()),
+                auth_header(user_id, "session"),
+                Path((vault_id, 1)),
+                headers,
+            )
+        };
+
+        let response = restore(HeaderMap::new()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PRECONDITION_REQUIRED);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(IF_MATCH, HeaderValue::from_static("*"));
+        let response = restore(headers).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
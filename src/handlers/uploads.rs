@@ -0,0 +1,499 @@
+use std::{sync::Arc, time::Duration};
+
+use axum::{
+    body::Body,
+    extract::{Path, State},
+    http::{
+        header::{CACHE_CONTROL, ETAG, IF_MATCH, LOCATION},
+        HeaderMap, HeaderName, HeaderValue, StatusCode,
+    },
+    response::{IntoResponse, Response},
+};
+use bytes::Bytes;
+use futures::TryStreamExt;
+use sha2::{Digest, Sha256};
+use sqlx::MySqlPool;
+
+use crate::{
+    common::{
+        digest::{expected_sha256, sha256_headers, ChecksumMismatch},
+        error::{AppError, ErrorTypes},
+    },
+    controllers,
+    crypt::{
+        encryption::{decrypt_stream, encrypt_part, PART_ALIGNMENT},
+        token::AuthHeader,
+    },
+    database::uploads::{UploadPart, UploadSession},
+    error_response,
+    handlers::{
+        storage::{
+            checksum_mismatch, commit_version, etag_matches, invalid_digest, new_version_key,
+            quota_exceeded, revision_etag, vault_conflict,
+        },
+        vaults::vault_not_exists,
+    },
+    store::{UploadedPart, VaultStore, MAX_PARTS, MIN_PART_SIZE},
+};
+
+// Header names follow the tus protocol
+const UPLOAD_LENGTH: HeaderName = HeaderName::from_static("upload-length");
+const UPLOAD_OFFSET: HeaderName = HeaderName::from_static("upload-offset");
+const UPLOAD_EXPIRES: HeaderName = HeaderName::from_static("upload-expires");
+
+const MAX_PART_SIZE: usize = 64 * 1024 * 1024;
+
+fn header_u64(headers: &HeaderMap, name: &HeaderName) -> Option<u64> {
+    headers.get(name)?.to_str().ok()?.parse().ok()
+}
+
+fn session_headers(session: &UploadSession) -> HeaderMap {
+    let mut headers = HeaderMap::with_capacity(4);
+    headers.insert(UPLOAD_OFFSET, HeaderValue::from(session.upload_offset));
+    headers.insert(UPLOAD_LENGTH, HeaderValue::from(session.length));
+    headers.insert(
+        UPLOAD_EXPIRES,
+        HeaderValue::from_str(
+            &session
+                .expires_at
+                .format("%a, %d %b %Y %H:%M:%S GMT")
+                .to_string(),
+        )
+        .unwrap(),
+    );
+    headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    headers
+}
+
+fn session_not_exists(id: &str) -> Response {
+    error_response!(
+        StatusCode::NOT_FOUND,
+        ErrorTypes::UploadSessionNotExists,
+        "Upload session {} does not exist or has expired",
+        id
+    )
+}
+
+pub async fn create_upload(
+    State(pool): State<MySqlPool>,
+    State(store): State<Arc<dyn VaultStore>>,
+    auth_header: AuthHeader,
+    Path(vault_id): Path<u32>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let user_id = auth_header.claims.id;
+    if controllers::vaults::get_vault(&pool, user_id, vault_id)
+        .await?
+        .is_none()
+    {
+        return Ok(vault_not_exists(vault_id));
+    }
+
+    let Some(length) = header_u64(&headers, &UPLOAD_LENGTH) else {
+        return Ok(error_response!(
+            StatusCode::BAD_REQUEST,
+            ErrorTypes::BadData,
+            "Upload-Length header is required"
+        ));
+    };
+    let Some(if_match) = headers.get(IF_MATCH) else {
+        return Ok(error_response!(
+            StatusCode::PRECONDITION_REQUIRED,
+            ErrorTypes::BadData,
+            "If-Match header is required"
+        ));
+    };
+    let revision = controllers::versions::current_revision(&pool, vault_id).await?;
+    if !etag_matches(if_match, revision, revision > 0) {
+        return Ok(vault_conflict(revision));
+    }
+    let usage = controllers::quota::usage(&pool, user_id).await?;
+    if length > usage.remaining() {
+        return Ok(quota_exceeded(&usage));
+    }
+    // The digest covers the whole vault, it can only be checked once the last chunk arrives
+    let Ok(expected_checksum) = expected_sha256(&headers) else {
+        return Ok(invalid_digest());
+    };
+
+    let object_key = new_version_key(user_id, vault_id);
+    let store_upload_id = store.create_upload(&object_key).await?;
+    let session = controllers::uploads::create_session(
+        &pool,
+        user_id,
+        vault_id,
+        &object_key,
+        &store_upload_id,
+        revision,
+        length,
+        expected_checksum,
+    )
+    .await?;
+
+    let mut headers = session_headers(&session);
+    headers.insert(
+        LOCATION,
+        HeaderValue::from_str(&format!("/vaults/{}/uploads/{}", vault_id, session.id)).unwrap(),
+    );
+    Ok((StatusCode::CREATED, headers).into_response())
+}
+
+pub async fn upload_offset(
+    State(pool): State<MySqlPool>,
+    auth_header: AuthHeader,
+    Path((vault_id, id)): Path<(u32, String)>,
+) -> Result<Response, AppError> {
+    let Some(session) =
+        controllers::uploads::get_session(&pool, auth_header.claims.id, vault_id, &id).await?
+    else {
+        return Ok(session_not_exists(&id));
+    };
+    Ok((StatusCode::OK, session_headers(&session)).into_response())
+}
+
+pub async fn append_upload(
+    State(pool): State<MySqlPool>,
+    State(store): State<Arc<dyn VaultStore>>,
+    auth_header: AuthHeader,
+    Path((vault_id, id)): Path<(u32, String)>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<Response, AppError> {
+    let user_id = auth_header.claims.id;
+    let Some(mut session) =
+        controllers::uploads::get_session(&pool, user_id, vault_id, &id).await?
+    else {
+        return Ok(session_not_exists(&id));
+    };
+
+    if header_u64(&headers, &UPLOAD_OFFSET) != Some(session.upload_offset) {
+        let mut response = error_response!(
+            StatusCode::CONFLICT,
+            ErrorTypes::BadData,
+            "Upload-Offset does not match, current offset is {}",
+            session.upload_offset
+        );
+        response
+            .headers_mut()
+            .insert(UPLOAD_OFFSET, HeaderValue::from(session.upload_offset));
+        return Ok(response);
+    }
+
+    // A dropped connection fails here and leaves the offset untouched, so the client can resume
+    let data = match axum::body::to_bytes(body, MAX_PART_SIZE).await {
+        Ok(data) => data,
+        Err(why) => {
+            return Ok(error_response!(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                ErrorTypes::BadData,
+                "Could not read chunk: {}",
+                why
+            ));
+        }
+    };
+    let offset = session.upload_offset + data.len() as u64;
+    if data.is_empty() && offset == session.length {
+        // Everything arrived but finishing failed, an empty chunk retries it. After a conflict
+        // the client can send If-Match with the newer revision to commit on top of it anyway
+        if let Some(if_match) = headers.get(IF_MATCH) {
+            let revision = controllers::versions::current_revision(&pool, vault_id).await?;
+            if !etag_matches(if_match, revision, revision > 0) {
+                return Ok(vault_conflict(revision));
+            }
+            controllers::uploads::rebase_session(&pool, &session.id, revision).await?;
+            session.base_revision = revision;
+        }
+        return finish_response(&pool, store.as_ref(), &session, user_id).await;
+    }
+    if data.is_empty()
+        || offset > session.length
+        || (offset < session.length && data.len() < MIN_PART_SIZE)
+    {
+        return Ok(error_response!(
+            StatusCode::BAD_REQUEST,
+            ErrorTypes::BadData,
+            "Chunk must be between {} and {} bytes unless it is the last one",
+            MIN_PART_SIZE,
+            MAX_PART_SIZE
+        ));
+    }
+    let last = offset == session.length;
+    if !last && !data.len().is_multiple_of(PART_ALIGNMENT) {
+        return Ok(error_response!(
+            StatusCode::BAD_REQUEST,
+            ErrorTypes::BadData,
+            "Chunk must be a multiple of {} bytes unless it is the last one",
+            PART_ALIGNMENT
+        ));
+    }
+
+    let parts = controllers::uploads::list_parts(&pool, &session.id).await?;
+    if parts.len() >= MAX_PARTS as usize {
+        return Ok(error_response!(
+            StatusCode::BAD_REQUEST,
+            ErrorTypes::BadData,
+            "Upload does not fit into {} parts",
+            MAX_PARTS
+        ));
+    }
+    // Each part continues the session's encryption stream, so the store only ever sees
+    // ciphertext. Stream positions that were sealed once, even if the part never got
+    // recorded, only take the same bytes again
+    if !controllers::uploads::claim_seal(
+        &pool,
+        &session.id,
+        session.upload_offset,
+        offset,
+        &Sha256::digest(&data),
+    )
+    .await?
+    {
+        return Ok(error_response!(
+            StatusCode::CONFLICT,
+            ErrorTypes::BadData,
+            "Different data was already sent at offset {}, resend the same chunk or start a new upload",
+            session.upload_offset
+        ));
+    }
+    let data_key = controllers::users::data_key(&pool, user_id).await?;
+    let sealed = encrypt_part(
+        &data_key,
+        &session.stream_nonce,
+        session.upload_offset,
+        &data,
+        last,
+    )?;
+    let uploaded = store
+        .upload_part(
+            &session.object_key,
+            &session.store_upload_id,
+            (parts.len() + 1) as u16,
+            Bytes::from(sealed),
+        )
+        .await?;
+    let part = UploadPart {
+        part_number: uploaded.number,
+        etag: uploaded.etag,
+        size: uploaded.size,
+    };
+    if !controllers::uploads::add_part(
+        &pool,
+        &session.id,
+        session.upload_offset,
+        data.len() as u64,
+        &part,
+    )
+    .await?
+    {
+        return Ok(error_response!(
+            StatusCode::CONFLICT,
+            ErrorTypes::BadData,
+            "Upload was appended concurrently"
+        ));
+    }
+
+    if !last {
+        let mut headers = HeaderMap::with_capacity(1);
+        headers.insert(UPLOAD_OFFSET, HeaderValue::from(offset));
+        return Ok((StatusCode::NO_CONTENT, headers).into_response());
+    }
+    finish_response(&pool, store.as_ref(), &session, user_id).await
+}
+
+async fn finish_response(
+    pool: &MySqlPool,
+    store: &dyn VaultStore,
+    session: &UploadSession,
+    user_id: u32,
+) -> Result<Response, AppError> {
+    let (revision, checksum) = match finish_upload(pool, store, session, user_id).await {
+        Ok(Some(committed)) => committed,
+        Ok(None) => {
+            let revision = controllers::versions::current_revision(pool, session.vault_id).await?;
+            return Ok(vault_conflict(revision));
+        }
+        Err(why) => match why.downcast::<ChecksumMismatch>() {
+            Ok(mismatch) => return Ok(checksum_mismatch(&mismatch.expected, &mismatch.checksum)),
+            Err(why) => return Err(why.into()),
+        },
+    };
+    let mut headers = HeaderMap::with_capacity(4);
+    headers.insert(UPLOAD_OFFSET, HeaderValue::from(session.length));
+    headers.insert(
+        ETAG,
+        HeaderValue::from_str(&revision_etag(revision)).unwrap(),
+    );
+    headers.extend(sha256_headers(&checksum));
+    Ok((StatusCode::NO_CONTENT, headers).into_response())
+}
+
+/// The session stays around until the version is committed, so whatever fails on the way
+/// can be retried (or the upload cancelled) with the same session
+async fn finish_upload(
+    pool: &MySqlPool,
+    store: &dyn VaultStore,
+    session: &UploadSession,
+    user_id: u32,
+) -> anyhow::Result<Option<(u32, String)>> {
+    // A retry finds the parts already assembled
+    if store.head(&session.object_key).await?.is_none() {
+        let parts = controllers::uploads::list_parts(pool, &session.id)
+            .await?
+            .into_iter()
+            .map(|part| UploadedPart {
+                number: part.part_number,
+                etag: part.etag,
+                size: part.size,
+            })
+            .collect();
+        store
+            .complete_upload(&session.object_key, &session.store_upload_id, parts)
+            .await?;
+    }
+
+    // Read the assembled vault back to check what the client sent
+    let data_key = controllers::users::data_key(pool, user_id).await?;
+    let mut hasher = Sha256::new();
+    let mut size: u64 = 0;
+    let mut opened = decrypt_stream(&data_key, store.get(&session.object_key).await?);
+    while let Some(chunk) = opened.try_next().await? {
+        hasher.update(&chunk);
+        size += chunk.len() as u64;
+    }
+    let checksum = hex::encode(hasher.finalize());
+    if let Some(expected) = &session.expected_checksum {
+        if *expected != checksum {
+            return Err(ChecksumMismatch {
+                expected: expected.clone(),
+                checksum,
+            }
+            .into());
+        }
+    }
+
+    let Some(revision) = commit_version(
+        pool,
+        store,
+        session.vault_id,
+        session.base_revision,
+        size,
+        user_id,
+        &checksum,
+        &session.object_key,
+        true,
+    )
+    .await?
+    else {
+        return Ok(None);
+    };
+
+    // The version is committed, a leftover session only wastes a row until it expires
+    if let Err(why) = controllers::uploads::delete_session(pool, &session.id).await {
+        tracing::error!("Could not delete upload session {}: {}", session.id, why);
+    }
+    Ok(Some((revision, checksum)))
+}
+
+/// Frees what the session holds in the store, the multipart upload or, once the parts were
+/// assembled, the object unless it made it into a version
+pub async fn release_upload(
+    pool: &MySqlPool,
+    store: &dyn VaultStore,
+    session: &UploadSession,
+) -> anyhow::Result<()> {
+    if store.head(&session.object_key).await?.is_some() {
+        if controllers::versions::object_committed(pool, &session.object_key).await? {
+            return Ok(());
+        }
+        store.delete(&session.object_key).await
+    } else {
+        store
+            .abort_upload(&session.object_key, &session.store_upload_id)
+            .await
+    }
+}
+
+pub async fn cancel_upload(
+    State(pool): State<MySqlPool>,
+    State(store): State<Arc<dyn VaultStore>>,
+    auth_header: AuthHeader,
+    Path((vault_id, id)): Path<(u32, String)>,
+) -> Result<Response, AppError> {
+    let Some(session) =
+        controllers::uploads::get_session(&pool, auth_header.claims.id, vault_id, &id).await?
+    else {
+        return Ok(session_not_exists(&id));
+    };
+
+    release_upload(&pool, store.as_ref(), &session).await?;
+    controllers::uploads::delete_session(&pool, &session.id).await?;
+    Ok((StatusCode::NO_CONTENT).into_response())
+}
+
+async fn purge_expired_sessions(pool: &MySqlPool, store: &dyn VaultStore) -> anyhow::Result<()> {
+    for session in controllers::uploads::expired_sessions(pool).await? {
+        if let Err(why) = release_upload(pool, store, &session).await {
+            tracing::error!("Could not abort upload {}: {}", session.id, why);
+        }
+        controllers::uploads::delete_session(pool, &session.id).await?;
+    }
+    Ok(())
+}
+
+/// Runs forever, aborting uploads nobody resumed in time
+pub async fn expire_sessions(pool: MySqlPool, store: Arc<dyn VaultStore>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(10 * 60));
+    loop {
+        interval.tick().await;
+        if let Err(why) = purge_expired_sessions(&pool, store.as_ref()).await {
+            tracing::error!("Could not purge expired upload sessions: {}", why);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::testing::setup;
+
+    #[sqlx::test(migrations = false)]
+    async fn sealed_range_only_takes_the_same_bytes(pool: MySqlPool) {
+        let user_id = setup(&pool, "uploads@example.com", "password").await;
+        let vault_id = controllers::vaults::create_vault(&pool, user_id, "vault")
+            .await
+            .unwrap();
+        let session = controllers::uploads::create_session(
+            &pool, user_id, vault_id, "object", "upload", 0, 100, None,
+        )
+        .await
+        .unwrap();
+        let claim = |from: u64, to: u64, data: &'static [u8]| {
+            let pool = pool.clone();
+            let id = session.id.clone();
+            async move {
+                controllers::uploads::claim_seal(&pool, &id, from, to, &Sha256::digest(data))
+                    .await
+                    .unwrap()
+            }
+        };
+
+        assert!(claim(0, 60, b"first").await);
+        // Sealed but never recorded, like after a failed add_part
+        assert!(claim(0, 60, b"first").await);
+        assert!(!claim(0, 60, b"other").await);
+        assert!(!claim(0, 50, b"first").await);
+
+        let part = UploadPart {
+            part_number: 1,
+            etag: "etag".to_owned(),
+            size: 60,
+        };
+        assert!(
+            controllers::uploads::add_part(&pool, &session.id, 0, 60, &part)
+                .await
+                .unwrap()
+        );
+        assert!(!claim(0, 60, b"first").await);
+        assert!(claim(60, 100, b"second").await);
+    }
+}
@@ -0,0 +1,131 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::MySqlPool;
+
+use crate::{
+    common::error::{AppError, ErrorTypes},
+    controllers,
+    crypt::token::AuthHeader,
+    error_response,
+    handlers::storage,
+    store::VaultStore,
+};
+
+pub fn vault_not_exists(vault_id: u32) -> Response {
+    error_response!(
+        StatusCode::NOT_FOUND,
+        ErrorTypes::VaultNotExists,
+        "Vault {} does not exist",
+        vault_id
+    )
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct VaultBody {
+    name: String,
+}
+
+pub async fn create_vault(
+    State(pool): State<MySqlPool>,
+    auth_header: AuthHeader,
+    Json(data): Json<VaultBody>,
+) -> Result<Response, AppError> {
+    let user_id = auth_header.claims.id;
+    if !controllers::vaults::is_valid_name(&data.name) {
+        return Ok(error_response!(
+            StatusCode::BAD_REQUEST,
+            ErrorTypes::BadData,
+            "Provided data is bad"
+        ));
+    }
+
+    match controllers::vaults::create_vault(&pool, user_id, &data.name).await {
+        Ok(id) => {
+            let vault = controllers::vaults::get_vault(&pool, user_id, id).await?;
+            Ok((StatusCode::CREATED, Json(vault)).into_response())
+        }
+        Err(why) => {
+            tracing::error!("Could not create vault: {}", why);
+
+            Ok(error_response!(
+                StatusCode::CONFLICT,
+                ErrorTypes::VaultAlreadyExists,
+                "Vault is already created: {}",
+                why
+            ))
+        }
+    }
+}
+
+pub async fn list_vaults(
+    State(pool): State<MySqlPool>,
+    auth_header: AuthHeader,
+) -> Result<Response, AppError> {
+    let vaults = controllers::vaults::list_vaults(&pool, auth_header.claims.id).await?;
+    Ok((StatusCode::OK, Json(vaults)).into_response())
+}
+
+pub async fn rename_vault(
+    State(pool): State<MySqlPool>,
+    auth_header: AuthHeader,
+    Path(vault_id): Path<u32>,
+    Json(data): Json<VaultBody>,
+) -> Result<Response, AppError> {
+    let user_id = auth_header.claims.id;
+    if !controllers::vaults::is_valid_name(&data.name) {
+        return Ok(error_response!(
+            StatusCode::BAD_REQUEST,
+            ErrorTypes::BadData,
+            "Provided data is bad"
+        ));
+    }
+    if controllers::vaults::get_vault(&pool, user_id, vault_id)
+        .await?
+        .is_none()
+    {
+        return Ok(vault_not_exists(vault_id));
+    }
+
+    if let Err(why) = controllers::vaults::rename_vault(&pool, user_id, vault_id, &data.name).await
+    {
+        tracing::error!("Could not rename vault: {}", why);
+
+        return Ok(error_response!(
+            StatusCode::CONFLICT,
+            ErrorTypes::VaultAlreadyExists,
+            "Vault is already created: {}",
+            why
+        ));
+    }
+
+    let vault = controllers::vaults::get_vault(&pool, user_id, vault_id).await?;
+    Ok((StatusCode::OK, Json(vault)).into_response())
+}
+
+pub async fn delete_vault(
+    State(pool): State<MySqlPool>,
+    State(store): State<Arc<dyn VaultStore>>,
+    auth_header: AuthHeader,
+    Path(vault_id): Path<u32>,
+) -> Result<Response, AppError> {
+    let user_id = auth_header.claims.id;
+    if controllers::vaults::get_vault(&pool, user_id, vault_id)
+        .await?
+        .is_none()
+    {
+        return Ok(vault_not_exists(vault_id));
+    }
+
+    // Objects first: if this fails the rows are still there to retry with
+    storage::purge_vault_objects(&pool, store.as_ref(), vault_id).await?;
+    controllers::vaults::delete_vault(&pool, user_id, vault_id).await?;
+
+    Ok((StatusCode::NO_CONTENT).into_response())
+}
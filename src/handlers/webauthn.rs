@@ -0,0 +1,470 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::MySqlPool;
+
+use crate::{
+    common::{
+        client::ClientInfo,
+        error::{AppError, ErrorTypes},
+    },
+    controllers,
+    crypt::{
+        self,
+        token::AuthHeader,
+        webauthn::{decode_b64, Rejected},
+    },
+    error_response,
+    handlers::{
+        auth::{
+            confirm_password, login_failed, login_lock, login_succeeded, mfa_token_expired,
+            mfa_token_used, tokens_response,
+        },
+        mfa::invalid_credentials,
+    },
+};
+
+fn rejected(why: anyhow::Error) -> Result<Response, AppError> {
+    match why.downcast::<Rejected>() {
+        Ok(why) => Ok(error_response!(
+            StatusCode::BAD_REQUEST,
+            ErrorTypes::WebauthnRejected,
+            "WebAuthn verification failed: {}",
+            why
+        )),
+        Err(why) => Err(why.into()),
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RegisterStartBody {
+    password: String,
+}
+
+pub async fn register_start(
+    State(pool): State<MySqlPool>,
+    auth_header: AuthHeader,
+    client: ClientInfo,
+    Json(data): Json<RegisterStartBody>,
+) -> Result<Response, AppError> {
+    let user_id = auth_header.claims.id;
+    if let Some(denied) = confirm_password(&pool, user_id, &data.password, &client).await? {
+        return Ok(denied);
+    }
+
+    let ceremony = controllers::webauthn::start_registration(&pool, user_id).await?;
+    Ok((StatusCode::OK, Json(ceremony)).into_response())
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AttestationResponse {
+    #[serde(rename = "clientDataJSON")]
+    client_data_json: String,
+    #[serde(rename = "attestationObject")]
+    attestation_object: String,
+}
+
+/// `PublicKeyCredential` from `navigator.credentials.create()`, as serialized by `toJSON()`
+#[derive(Serialize, Deserialize)]
+pub struct RegistrationCredential {
+    #[serde(rename = "rawId")]
+    raw_id: String,
+    response: AttestationResponse,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RegisterFinishBody {
+    challenge_id: String,
+    name: String,
+    credential: RegistrationCredential,
+}
+
+pub async fn register_finish(
+    State(pool): State<MySqlPool>,
+    auth_header: AuthHeader,
+    Json(data): Json<RegisterFinishBody>,
+) -> Result<Response, AppError> {
+    let user_id = auth_header.claims.id;
+    if data.name.is_empty() || data.name.chars().count() > 64 {
+        return Ok(error_response!(
+            StatusCode::BAD_REQUEST,
+            ErrorTypes::BadData,
+            "Provided data is bad"
+        ));
+    }
+
+    let result = async {
+        let response = &data.credential.response;
+        controllers::webauthn::finish_registration(
+            &pool,
+            user_id,
+            &data.challenge_id,
+            &data.name,
+            &decode_b64(&response.client_data_json)?,
+            &decode_b64(&response.attestation_object)?,
+        )
+        .await
+    }
+    .await;
+    let id = match result {
+        Ok(id) => id as i32,
+        Err(why) => return rejected(why),
+    };
+
+    let credentials = controllers::webauthn::list_credentials(&pool, user_id).await?;
+    let created = credentials
+        .into_iter()
+        .find(|credential| credential.id == id);
+    Ok((StatusCode::CREATED, Json(created)).into_response())
+}
+
+pub async fn list_credentials(
+    State(pool): State<MySqlPool>,
+    auth_header: AuthHeader,
+) -> Result<Response, AppError> {
+    let credentials = controllers::webauthn::list_credentials(&pool, auth_header.claims.id).await?;
+    Ok((StatusCode::OK, Json(credentials)).into_response())
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct DeleteCredentialBody {
+    password: String,
+}
+
+pub async fn delete_credential(
+    State(pool): State<MySqlPool>,
+    auth_header: AuthHeader,
+    client: ClientInfo,
+    Path(id): Path<i32>,
+    Json(data): Json<DeleteCredentialBody>,
+) -> Result<Response, AppError> {
+    let user_id = auth_header.claims.id;
+    if let Some(denied) = confirm_password(&pool, user_id, &data.password, &client).await? {
+        return Ok(denied);
+    }
+    if !controllers::webauthn::delete_credential(&pool, user_id, id).await? {
+        return Ok(error_response!(
+            StatusCode::NOT_FOUND,
+            ErrorTypes::CredentialNotExists,
+            "Credential {} does not exist",
+            id
+        ));
+    }
+    Ok((StatusCode::NO_CONTENT).into_response())
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LoginStartBody {
+    // From the first login step. Without it this is a passwordless login
+    #[serde(default)]
+    mfa_token: Option<String>,
+}
+
+fn passwordless_disabled() -> Response {
+    error_response!(
+        StatusCode::FORBIDDEN,
+        ErrorTypes::NotEnoughPermissions,
+        "Passwordless login is disabled"
+    )
+}
+
+pub async fn login_start(
+    State(pool): State<MySqlPool>,
+    Json(data): Json<LoginStartBody>,
+) -> Result<Response, AppError> {
+    let user_id = match data.mfa_token {
+        Some(mfa_token) => match crypt::token::verify_mfa_token(&mfa_token) {
+            Ok(claims) => Some(claims.id),
+            Err(why) => return Ok(mfa_token_expired(why)),
+        },
+        None if controllers::webauthn::passwordless_enabled() => None,
+        None => return Ok(passwordless_disabled()),
+    };
+
+    let ceremony = controllers::webauthn::start_login(&pool, user_id).await?;
+    Ok((StatusCode::OK, Json(ceremony)).into_response())
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AssertionResponse {
+    #[serde(rename = "clientDataJSON")]
+    client_data_json: String,
+    #[serde(rename = "authenticatorData")]
+    authenticator_data: String,
+    signature: String,
+}
+
+/// `PublicKeyCredential` from `navigator.credentials.get()`, as serialized by `toJSON()`
+#[derive(Serialize, Deserialize)]
+pub struct AuthenticationCredential {
+    #[serde(rename = "rawId")]
+    raw_id: String,
+    response: AssertionResponse,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LoginFinishBody {
+    challenge_id: String,
+    #[serde(default)]
+    mfa_token: Option<String>,
+    #[serde(default)]
+    device_name: Option<String>,
+    credential: AuthenticationCredential,
+}
+
+pub async fn login_finish(
+    State(pool): State<MySqlPool>,
+    client: ClientInfo,
+    Json(data): Json<LoginFinishBody>,
+) -> Result<Response, AppError> {
+    let (expected_user, mfa_jti, device_name) = match &data.mfa_token {
+        Some(mfa_token) => match crypt::token::verify_mfa_token(mfa_token) {
+            Ok(claims) => (Some(claims.id), Some(claims.jti), claims.device_name),
+            Err(why) => return Ok(mfa_token_expired(why)),
+        },
+        None if controllers::webauthn::passwordless_enabled() => {
+            (None, None, data.device_name.clone())
+        }
+        None => return Ok(passwordless_disabled()),
+    };
+    if device_name
+        .as_deref()
+        .is_some_and(|name| name.is_empty() || name.chars().count() > 64)
+    {
+        return Ok(error_response!(
+            StatusCode::BAD_REQUEST,
+            ErrorTypes::BadData,
+            "Provided data is bad"
+        ));
+    }
+
+    if let Some(locked) = login_lock(&pool, expected_user, &client).await? {
+        return Ok(locked);
+    }
+    if let Some(jti) = &mfa_jti {
+        if !controllers::mfa::count_attempt(&pool, jti).await? {
+            return Ok(mfa_token_used());
+        }
+    }
+
+    let result = async {
+        let response = &data.credential.response;
+        controllers::webauthn::finish_login(
+            &pool,
+            &data.challenge_id,
+            &decode_b64(&data.credential.raw_id)?,
+            &decode_b64(&response.client_data_json)?,
+            &decode_b64(&response.authenticator_data)?,
+            &decode_b64(&response.signature)?,
+        )
+        .await
+    }
+    .await;
+    let user_id = match result {
+        Ok(user_id) => user_id,
+        Err(why) => {
+            if why.is::<Rejected>() {
+                login_failed(&pool, expected_user, &client).await?;
+            }
+            return rejected(why);
+        }
+    };
+    // The challenge was bound to the mfa token's user, but check anyway
+    if expected_user.is_some_and(|expected| expected != user_id) {
+        return Ok(invalid_credentials());
+    }
+    if let Some(jti) = &mfa_jti {
+        if !controllers::mfa::redeem_token(&pool, jti).await? {
+            return Ok(mfa_token_used());
+        }
+    }
+
+    let resp = tokens_response(&pool, user_id, device_name.as_deref(), &client).await?;
+    login_succeeded(&pool, user_id).await?;
+    Ok(resp)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        common::testing::{auth_header, client, json, setup},
+        crypt::webauthn::{encode_b64, tests::Authenticator},
+    };
+
+    const PASSWORD: &str = "correct horse";
+
+    async fn start_registration(pool: &MySqlPool, user_id: u32) -> (String, Vec<u8>) {
+        let response = register_start(
+            State(pool.clone()),
+            auth_header(user_id, "session"),
+            client(),
+            Json(RegisterStartBody {
+                password: PASSWORD.to_owned(),
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        ceremony(response).await
+    }
+
+    async fn ceremony(response: Response) -> (String, Vec<u8>) {
+        let ceremony = json(response).await;
+        (
+            ceremony["challenge_id"].as_str().unwrap().to_owned(),
+            decode_b64(ceremony["public_key"]["challenge"].as_str().unwrap()).unwrap(),
+        )
+    }
+
+    fn registration(
+        authenticator: &Authenticator,
+        challenge_id: &str,
+        challenge: &[u8],
+    ) -> RegisterFinishBody {
+        let (client_data, attestation) = authenticator.register(challenge);
+        RegisterFinishBody {
+            challenge_id: challenge_id.to_owned(),
+            name: "Security key".to_owned(),
+            credential: RegistrationCredential {
+                raw_id: encode_b64(&authenticator.credential_id),
+                response: AttestationResponse {
+                    client_data_json: encode_b64(&client_data),
+                    attestation_object: encode_b64(&attestation),
+                },
+            },
+        }
+    }
+
+    fn authentication(
+        authenticator: &mut Authenticator,
+        challenge_id: &str,
+        challenge: &[u8],
+        mfa_token: String,
+    ) -> LoginFinishBody {
+        let (client_data, auth_data, signature) = authenticator.assert(challenge);
+        LoginFinishBody {
+            challenge_id: challenge_id.to_owned(),
+            mfa_token: Some(mfa_token),
+            device_name: None,
+            credential: AuthenticationCredential {
+                raw_id: encode_b64(&authenticator.credential_id),
+                response: AssertionResponse {
+                    client_data_json: encode_b64(&client_data),
+                    authenticator_data: encode_b64(&auth_data),
+                    signature: encode_b64(&signature),
+                },
+            },
+        }
+    }
+
+    async fn error_type(response: Response) -> String {
+        json(response).await["error_type"]
+            .as_str()
+            .unwrap()
+            .to_owned()
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn registration_challenge_is_single_use(pool: MySqlPool) {
+        let user_id = setup(&pool, "webauthn@example.com", PASSWORD).await;
+        let (challenge_id, challenge) = start_registration(&pool, user_id).await;
+        let authenticator = Authenticator::new();
+
+        let response = register_finish(
+            State(pool.clone()),
+            auth_header(user_id, "session"),
+            Json(registration(&authenticator, &challenge_id, &challenge)),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        let replayed = register_finish(
+            State(pool.clone()),
+            auth_header(user_id, "session"),
+            Json(registration(
+                &Authenticator::new(),
+                &challenge_id,
+                &challenge,
+            )),
+        )
+        .await
+        .unwrap();
+        assert_eq!(replayed.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            error_type(replayed).await,
+            ErrorTypes::WebauthnRejected.as_str()
+        );
+        let credentials = controllers::webauthn::list_credentials(&pool, user_id)
+            .await
+            .unwrap();
+        assert_eq!(credentials.len(), 1);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn login_challenge_is_single_use(pool: MySqlPool) {
+        let user_id = setup(&pool, "webauthn@example.com", PASSWORD).await;
+        let mut authenticator = Authenticator::new();
+        let (challenge_id, challenge) = start_registration(&pool, user_id).await;
+        register_finish(
+            State(pool.clone()),
+            auth_header(user_id, "session"),
+            Json(registration(&authenticator, &challenge_id, &challenge)),
+        )
+        .await
+        .unwrap();
+
+        let mfa_token = controllers::mfa::issue_token(&pool, user_id, None)
+            .await
+            .unwrap();
+        let response = login_start(
+            State(pool.clone()),
+            Json(LoginStartBody {
+                mfa_token: Some(mfa_token.clone()),
+            }),
+        )
+        .await
+        .unwrap();
+        let (challenge_id, challenge) = ceremony(response).await;
+        let response = login_finish(
+            State(pool.clone()),
+            client(),
+            Json(authentication(
+                &mut authenticator,
+                &challenge_id,
+                &challenge,
+                mfa_token,
+            )),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // Even with a fresh first step the old challenge is gone
+        let mfa_token = controllers::mfa::issue_token(&pool, user_id, None)
+            .await
+            .unwrap();
+        let replayed = login_finish(
+            State(pool.clone()),
+            client(),
+            Json(authentication(
+                &mut authenticator,
+                &challenge_id,
+                &challenge,
+                mfa_token,
+            )),
+        )
+        .await
+        .unwrap();
+        assert_eq!(replayed.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            error_type(replayed).await,
+            ErrorTypes::WebauthnRejected.as_str()
+        );
+    }
+}
@@ -0,0 +1,34 @@
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+use tokio::io::AsyncWriteExt;
+
+use super::{Email, Mailer};
+
+/// Appends emails to a file. For development setups where nothing can actually be delivered
+pub struct FileMailer {
+    path: PathBuf,
+}
+
+impl FileMailer {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+}
+
+#[async_trait]
+impl Mailer for FileMailer {
+    async fn send(&self, email: &Email) -> anyhow::Result<()> {
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        let entry = format!(
+            "To: {}\nSubject: {}\n\n{}\n\n",
+            email.to, email.subject, email.body
+        );
+        file.write_all(entry.as_bytes()).await?;
+        Ok(())
+    }
+}
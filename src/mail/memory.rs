@@ -0,0 +1,27 @@
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+
+use super::{Email, Mailer};
+
+/// Keeps sent emails in process memory so tests can look at them
+#[derive(Default)]
+pub struct MemoryMailer {
+    sent: Mutex<Vec<Email>>,
+}
+
+impl MemoryMailer {
+    // Nothing in the server reads these back, only tests do
+    #[allow(dead_code)]
+    pub fn sent(&self) -> Vec<Email> {
+        self.sent.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl Mailer for MemoryMailer {
+    async fn send(&self, email: &Email) -> anyhow::Result<()> {
+        self.sent.lock().unwrap().push(email.clone());
+        Ok(())
+    }
+}
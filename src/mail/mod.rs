@@ -0,0 +1,50 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+pub mod file;
+#[cfg(test)]
+pub mod memory;
+pub mod smtp;
+
+#[derive(Debug, Clone)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// Sends plain text emails to users
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, email: &Email) -> anyhow::Result<()>;
+}
+
+/// Sends `email` without making the caller wait. Failures are only logged, nobody is
+/// around to see them anyway
+pub fn send_in_background(mailer: Arc<dyn Mailer>, email: Email) {
+    tokio::spawn(async move {
+        if let Err(why) = mailer.send(&email).await {
+            tracing::error!("Could not send \"{}\" email: {}", email.subject, why);
+        }
+    });
+}
+
+/// Picks the backend from `MAIL_BACKEND`: `smtp`, or `file` (`MAIL_PATH`) for development.
+/// There is no default, emails carry reset and verification links that must neither get
+/// lost nor end up in logs
+pub fn from_env() -> Arc<dyn Mailer> {
+    let backend = std::env::var("MAIL_BACKEND").expect("MAIL_BACKEND NOT SET");
+    match backend.as_str() {
+        "smtp" => Arc::new(smtp::SmtpMailer::from_env()),
+        "file" => {
+            tracing::warn!("MAIL_BACKEND is file, emails are only written to MAIL_PATH");
+            Arc::new(file::FileMailer::new(
+                std::env::var_os("MAIL_PATH")
+                    .expect("MAIL_PATH NOT SET")
+                    .into(),
+            ))
+        }
+        other => panic!("Unknown MAIL_BACKEND: {}", other),
+    }
+}
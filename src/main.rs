@@ -1,31 +1,24 @@
-use std::time::Duration;
+use std::{net::SocketAddr, sync::Arc};
 
 use axum::extract::FromRef;
-use minio::s3::{
-    builders::ListBuckets, creds::StaticProvider, response::ListBucketsResponse, types::S3Api,
-    ClientBuilder,
-};
-use sqlx::{mysql::MySqlPoolOptions, MySql, MySqlPool, Pool};
+use mail::Mailer;
+use sqlx::{MySql, MySqlPool, Pool};
+use store::VaultStore;
 
+mod bootstrap;
 mod common;
 mod controllers;
 mod crypt;
 mod database;
 mod handlers;
-
-async fn get_pool(connection_str: &str) -> MySqlPool {
-    MySqlPoolOptions::new()
-        .max_connections(10)
-        .acquire_timeout(Duration::from_secs(10))
-        .connect(connection_str)
-        .await
-        .expect("Cant connect")
-}
+mod mail;
+mod store;
 
 #[derive(Clone)]
 struct AppState {
     pool: MySqlPool,
-    s3_client: minio::s3::Client,
+    store: Arc<dyn VaultStore>,
+    mailer: Arc<dyn Mailer>,
 }
 
 impl FromRef<AppState> for Pool<MySql> {
@@ -34,9 +27,15 @@ impl FromRef<AppState> for Pool<MySql> {
     }
 }
 
-impl FromRef<AppState> for minio::s3::Client {
+impl FromRef<AppState> for Arc<dyn VaultStore> {
     fn from_ref(state: &AppState) -> Self {
-        state.s3_client.clone()
+        state.store.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<dyn Mailer> {
+    fn from_ref(state: &AppState) -> Self {
+        state.mailer.clone()
     }
 }
 
@@ -45,23 +44,60 @@ async fn main() {
     tracing_subscriber::fmt::init();
     dotenv::dotenv().ok();
     tracing::info!("started");
+    if let Err(why) = crypt::keys::load() {
+        tracing::error!("{:#}", why);
+        std::process::exit(1);
+    }
     let connection_str = std::env::var("DATABASE_URL").expect("DATABASE_URL NOT SET");
-    let mysql_pool = get_pool(&connection_str).await;
-
-    let static_provider = StaticProvider::new(&std::env::var("MINIO_ROOT_USER").unwrap(), &std::env::var("MINIO_ROOT_PASSWORD").unwrap(), None);
-    let client = ClientBuilder::new(std::env::var("MINIO_URL").unwrap().parse().unwrap())
-        .provider(Some(Box::new(static_provider)))
-        .build()
-        .unwrap();
+    let mysql_pool = match bootstrap::database(&connection_str).await {
+        Ok(pool) => pool,
+        Err(why) => {
+            tracing::error!("{:#}", why);
+            std::process::exit(1);
+        }
+    };
+    if let Err(why) = bootstrap::kdf_salts(&mysql_pool).await {
+        tracing::error!("{:#}", why);
+        std::process::exit(1);
+    }
+    let store = store::from_env();
+    if let Err(why) = bootstrap::store(store.as_ref()).await {
+        tracing::error!("{:#}", why);
+        std::process::exit(1);
+    }
+    if let Err(why) = bootstrap::legacy_vaults(&mysql_pool, store.as_ref()).await {
+        tracing::error!("Could not migrate legacy vaults: {:#}", why);
+        std::process::exit(1);
+    }
 
     let state = AppState {
         pool: mysql_pool,
-        s3_client: client,
+        store,
+        mailer: mail::from_env(),
     };
 
+    tokio::spawn(handlers::auth::expire_tokens(state.pool.clone()));
+    tokio::spawn(handlers::auth::sync_denylist(state.pool.clone()));
+    tokio::spawn(handlers::account::purge_deleted_accounts(
+        state.pool.clone(),
+        state.store.clone(),
+    ));
+    tokio::spawn(handlers::uploads::expire_sessions(
+        state.pool.clone(),
+        state.store.clone(),
+    ));
+
     let app = common::router::get_router(state);
 
-    let listener = tokio::net::TcpListener::bind(std::env::var("SERVICE_URL").unwrap()).await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+    let listener = tokio::net::TcpListener::bind(std::env::var("SERVICE_URL").unwrap())
+        .await
+        .unwrap();
+    // Connection info is where sessions get the client address from
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    .unwrap();
     println!("Hello, world!");
 }
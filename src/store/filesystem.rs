@@ -0,0 +1,243 @@
+use std::{
+    io::ErrorKind,
+    path::{Component, Path, PathBuf},
+};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use chrono::DateTime;
+use futures::{StreamExt, TryStreamExt};
+use tokio::io::AsyncWriteExt;
+use tokio_util::io::ReaderStream;
+
+use super::{ByteStream, ObjectInfo, UploadedPart, VaultStore};
+
+// Parts of unfinished multipart uploads live here, hidden from `list`
+const UPLOADS_DIR: &str = ".uploads";
+
+/// Keeps objects as plain files under `root`, good enough for a single small instance
+pub struct FilesystemStore {
+    root: PathBuf,
+}
+
+impl FilesystemStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn path(&self, key: &str) -> anyhow::Result<PathBuf> {
+        let relative = Path::new(key);
+        if !relative
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+        {
+            anyhow::bail!("Invalid object key: {}", key);
+        }
+        Ok(self.root.join(relative))
+    }
+
+    fn part_path(&self, upload_id: &str, number: u16) -> anyhow::Result<PathBuf> {
+        self.path(&format!("{}/{}/{}", UPLOADS_DIR, upload_id, number))
+    }
+}
+
+#[async_trait]
+impl VaultStore for FilesystemStore {
+    async fn prepare(&self) -> anyhow::Result<()> {
+        tokio::fs::create_dir_all(&self.root).await?;
+        Ok(())
+    }
+
+    async fn put(&self, key: &str, mut body: ByteStream<'_>) -> anyhow::Result<u64> {
+        let path = self.path(key)?;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+
+        // Write next to the target and rename, so readers never see a half-written vault
+        let tmp_path =
+            path.with_extension(format!("tmp-{}", hex::encode(rand::random::<[u8; 8]>())));
+        let mut file = tokio::fs::File::create(&tmp_path).await?;
+        let mut size: u64 = 0;
+        let result: anyhow::Result<()> = async {
+            while let Some(chunk) = body.next().await {
+                let chunk = chunk?;
+                file.write_all(&chunk).await?;
+                size += chunk.len() as u64;
+            }
+            file.sync_all().await?;
+            Ok(())
+        }
+        .await;
+        if let Err(why) = result {
+            let _ = tokio::fs::remove_file(&tmp_path).await;
+            return Err(why);
+        }
+
+        tokio::fs::rename(&tmp_path, &path).await?;
+        Ok(size)
+    }
+
+    async fn get(&self, key: &str) -> anyhow::Result<ByteStream<'static>> {
+        let file = tokio::fs::File::open(self.path(key)?).await?;
+        Ok(ReaderStream::new(file).map_err(anyhow::Error::from).boxed())
+    }
+
+    async fn head(&self, key: &str) -> anyhow::Result<Option<ObjectInfo>> {
+        match tokio::fs::metadata(self.path(key)?).await {
+            Ok(metadata) => Ok(Some(ObjectInfo {
+                key: key.to_owned(),
+                size: metadata.len(),
+                last_modified: metadata.modified().ok().map(DateTime::from),
+            })),
+            Err(why) if why.kind() == ErrorKind::NotFound => Ok(None),
+            Err(why) => Err(why.into()),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> anyhow::Result<()> {
+        match tokio::fs::remove_file(self.path(key)?).await {
+            Err(why) if why.kind() != ErrorKind::NotFound => Err(why.into()),
+            _ => Ok(()),
+        }
+    }
+
+    async fn list(&self, prefix: &str) -> anyhow::Result<Vec<ObjectInfo>> {
+        let mut objects = Vec::new();
+        let mut dirs = vec![self.root.clone()];
+
+        while let Some(dir) = dirs.pop() {
+            let mut entries = match tokio::fs::read_dir(&dir).await {
+                Ok(entries) => entries,
+                Err(why) if why.kind() == ErrorKind::NotFound => continue,
+                Err(why) => return Err(why.into()),
+            };
+            while let Some(entry) = entries.next_entry().await? {
+                let metadata = entry.metadata().await?;
+                if metadata.is_dir() {
+                    if entry.file_name() != UPLOADS_DIR {
+                        dirs.push(entry.path());
+                    }
+                    continue;
+                }
+
+                let key = entry
+                    .path()
+                    .strip_prefix(&self.root)?
+                    .components()
+                    .map(|component| component.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                if key.starts_with(prefix) && !key.contains(".tmp-") {
+                    objects.push(ObjectInfo {
+                        key,
+                        size: metadata.len(),
+                        last_modified: metadata.modified().ok().map(DateTime::from),
+                    });
+                }
+            }
+        }
+
+        Ok(objects)
+    }
+
+    async fn create_upload(&self, _key: &str) -> anyhow::Result<String> {
+        let upload_id = hex::encode(rand::random::<[u8; 16]>());
+        tokio::fs::create_dir_all(self.path(&format!("{}/{}", UPLOADS_DIR, upload_id))?).await?;
+        Ok(upload_id)
+    }
+
+    async fn upload_part(
+        &self,
+        _key: &str,
+        upload_id: &str,
+        number: u16,
+        data: Bytes,
+    ) -> anyhow::Result<UploadedPart> {
+        tokio::fs::write(self.part_path(upload_id, number)?, &data).await?;
+        Ok(UploadedPart {
+            number,
+            etag: number.to_string(),
+            size: data.len() as u64,
+        })
+    }
+
+    async fn complete_upload(
+        &self,
+        key: &str,
+        upload_id: &str,
+        parts: Vec<UploadedPart>,
+    ) -> anyhow::Result<()> {
+        let mut paths = Vec::with_capacity(parts.len());
+        for part in &parts {
+            paths.push(self.part_path(upload_id, part.number)?);
+        }
+
+        let body = futures::stream::iter(paths)
+            .then(|path| async move {
+                let file = tokio::fs::File::open(path).await?;
+                anyhow::Ok(ReaderStream::new(file).map_err(anyhow::Error::from))
+            })
+            .try_flatten()
+            .boxed();
+        self.put(key, body).await?;
+
+        self.abort_upload(key, upload_id).await
+    }
+
+    async fn abort_upload(&self, _key: &str, upload_id: &str) -> anyhow::Result<()> {
+        match tokio::fs::remove_dir_all(self.path(&format!("{}/{}", UPLOADS_DIR, upload_id))?).await
+        {
+            Err(why) if why.kind() != ErrorKind::NotFound => Err(why.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::tests::{multipart, round_trip};
+
+    struct TempRoot(PathBuf);
+
+    impl TempRoot {
+        fn new() -> Self {
+            Self(std::env::temp_dir().join(format!(
+                "vault-store-{}",
+                hex::encode(rand::random::<[u8; 8]>())
+            )))
+        }
+    }
+
+    impl Drop for TempRoot {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[tokio::test]
+    async fn filesystem_round_trip() {
+        let root = TempRoot::new();
+        round_trip(&FilesystemStore::new(&root.0)).await;
+    }
+
+    #[tokio::test]
+    async fn filesystem_multipart() {
+        let root = TempRoot::new();
+        multipart(&FilesystemStore::new(&root.0)).await;
+    }
+
+    #[test]
+    fn path_stays_under_root() {
+        let store = FilesystemStore::new("/srv/vaults");
+        assert_eq!(
+            store.path("1/2/3").unwrap(),
+            PathBuf::from("/srv/vaults/1/2/3")
+        );
+        assert!(store.path("../etc/passwd").is_err());
+        assert!(store.path("1/../../etc/passwd").is_err());
+        assert!(store.path("/etc/passwd").is_err());
+        assert!(store.path("./1/2").is_err());
+    }
+}
@@ -0,0 +1,139 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::RwLock,
+};
+
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use futures::{StreamExt, TryStreamExt};
+
+use super::{ByteStream, ObjectInfo, UploadedPart, VaultStore};
+
+/// Keeps everything in process memory, meant for tests and throwaway instances
+#[derive(Default)]
+pub struct MemoryStore {
+    objects: RwLock<BTreeMap<String, Bytes>>,
+    uploads: RwLock<HashMap<String, BTreeMap<u16, Bytes>>>,
+}
+
+#[async_trait]
+impl VaultStore for MemoryStore {
+    async fn put(&self, key: &str, body: ByteStream<'_>) -> anyhow::Result<u64> {
+        let data = body
+            .try_fold(BytesMut::new(), |mut data, chunk| async move {
+                data.extend_from_slice(&chunk);
+                Ok(data)
+            })
+            .await?
+            .freeze();
+        let size = data.len() as u64;
+        self.objects.write().unwrap().insert(key.to_owned(), data);
+        Ok(size)
+    }
+
+    async fn get(&self, key: &str) -> anyhow::Result<ByteStream<'static>> {
+        let data = self
+            .objects
+            .read()
+            .unwrap()
+            .get(key)
+            .cloned()
+            .ok_or_else(|| anyhow::anyhow!("Object {} does not exist", key))?;
+        Ok(futures::stream::once(async move { Ok(data) }).boxed())
+    }
+
+    async fn head(&self, key: &str) -> anyhow::Result<Option<ObjectInfo>> {
+        Ok(self
+            .objects
+            .read()
+            .unwrap()
+            .get(key)
+            .map(|data| ObjectInfo {
+                key: key.to_owned(),
+                size: data.len() as u64,
+                last_modified: None,
+            }))
+    }
+
+    async fn delete(&self, key: &str) -> anyhow::Result<()> {
+        self.objects.write().unwrap().remove(key);
+        Ok(())
+    }
+
+    async fn list(&self, prefix: &str) -> anyhow::Result<Vec<ObjectInfo>> {
+        Ok(self
+            .objects
+            .read()
+            .unwrap()
+            .range(prefix.to_owned()..)
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .map(|(key, data)| ObjectInfo {
+                key: key.clone(),
+                size: data.len() as u64,
+                last_modified: None,
+            })
+            .collect())
+    }
+
+    async fn create_upload(&self, _key: &str) -> anyhow::Result<String> {
+        let upload_id = hex::encode(rand::random::<[u8; 16]>());
+        self.uploads
+            .write()
+            .unwrap()
+            .insert(upload_id.clone(), BTreeMap::new());
+        Ok(upload_id)
+    }
+
+    async fn upload_part(
+        &self,
+        _key: &str,
+        upload_id: &str,
+        number: u16,
+        data: Bytes,
+    ) -> anyhow::Result<UploadedPart> {
+        let size = data.len() as u64;
+        self.uploads
+            .write()
+            .unwrap()
+            .get_mut(upload_id)
+            .ok_or_else(|| anyhow::anyhow!("Upload {} does not exist", upload_id))?
+            .insert(number, data);
+        Ok(UploadedPart {
+            number,
+            etag: number.to_string(),
+            size,
+        })
+    }
+
+    async fn complete_upload(
+        &self,
+        key: &str,
+        upload_id: &str,
+        parts: Vec<UploadedPart>,
+    ) -> anyhow::Result<()> {
+        let uploaded = self
+            .uploads
+            .write()
+            .unwrap()
+            .remove(upload_id)
+            .ok_or_else(|| anyhow::anyhow!("Upload {} does not exist", upload_id))?;
+
+        let mut data = BytesMut::new();
+        for part in parts {
+            let chunk = uploaded
+                .get(&part.number)
+                .ok_or_else(|| anyhow::anyhow!("Part {} was not uploaded", part.number))?;
+            data.extend_from_slice(chunk);
+        }
+        self.objects
+            .write()
+            .unwrap()
+            .insert(key.to_owned(), data.freeze());
+        Ok(())
+    }
+
+    async fn abort_upload(&self, _key: &str, upload_id: &str) -> anyhow::Result<()> {
+        self.uploads.write().unwrap().remove(upload_id);
+        Ok(())
+    }
+}
@@ -0,0 +1,193 @@
+use ::minio::s3::{
+    builders::CopySource,
+    creds::StaticProvider,
+    error::{Error, ErrorCode},
+    types::{PartInfo, S3Api, ToStream},
+    Client, ClientBuilder,
+};
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::{StreamExt, TryStreamExt};
+
+use super::{put_multipart, ByteStream, ObjectInfo, UploadedPart, VaultStore, MIN_PART_SIZE};
+
+pub struct MinioStore {
+    client: Client,
+    bucket: String,
+    part_size: usize,
+    max_in_flight: usize,
+}
+
+impl MinioStore {
+    pub fn new(client: Client, bucket: impl Into<String>) -> Self {
+        Self {
+            client,
+            bucket: bucket.into(),
+            part_size: 8 * 1024 * 1024,
+            max_in_flight: 4,
+        }
+    }
+
+    pub fn part_size(mut self, part_size: usize) -> Self {
+        self.part_size = part_size.max(MIN_PART_SIZE);
+        self
+    }
+
+    pub fn max_in_flight(mut self, max_in_flight: usize) -> Self {
+        self.max_in_flight = max_in_flight.max(1);
+        self
+    }
+
+    pub fn from_env() -> Self {
+        let static_provider = StaticProvider::new(
+            &std::env::var("MINIO_ROOT_USER").unwrap(),
+            &std::env::var("MINIO_ROOT_PASSWORD").unwrap(),
+            None,
+        );
+        let client = ClientBuilder::new(std::env::var("MINIO_URL").unwrap().parse().unwrap())
+            .provider(Some(Box::new(static_provider)))
+            .build()
+            .unwrap();
+        let bucket = std::env::var("MINIO_BUCKET").unwrap_or("user-storages".to_string());
+        let mut store = Self::new(client, bucket);
+        if let Some(part_size) = std::env::var("UPLOAD_PART_SIZE")
+            .ok()
+            .and_then(|v| v.parse().ok())
+        {
+            store = store.part_size(part_size);
+        }
+        if let Some(max_in_flight) = std::env::var("UPLOAD_CONCURRENCY")
+            .ok()
+            .and_then(|v| v.parse().ok())
+        {
+            store = store.max_in_flight(max_in_flight);
+        }
+        store
+    }
+}
+
+#[async_trait]
+impl VaultStore for MinioStore {
+    async fn prepare(&self) -> anyhow::Result<()> {
+        let response = self.client.bucket_exists(&self.bucket).send().await?;
+        if !response.exists {
+            tracing::info!("Creating bucket {}", self.bucket);
+            self.client.create_bucket(&self.bucket).send().await?;
+        }
+        Ok(())
+    }
+
+    async fn put(&self, key: &str, body: ByteStream<'_>) -> anyhow::Result<u64> {
+        put_multipart(self, key, body, self.part_size, self.max_in_flight).await
+    }
+
+    async fn get(&self, key: &str) -> anyhow::Result<ByteStream<'static>> {
+        let response = self.client.get_object(&self.bucket, key).send().await?;
+        let (stream, _size) = response.content.to_stream().await?;
+        Ok(stream.map_err(anyhow::Error::from).boxed())
+    }
+
+    async fn head(&self, key: &str) -> anyhow::Result<Option<ObjectInfo>> {
+        match self.client.stat_object(&self.bucket, key).send().await {
+            Ok(response) => Ok(Some(ObjectInfo {
+                key: key.to_owned(),
+                size: response.size,
+                last_modified: response.last_modified,
+            })),
+            Err(Error::S3Error(why)) if why.code == ErrorCode::NoSuchKey => Ok(None),
+            Err(why) => Err(why.into()),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> anyhow::Result<()> {
+        self.client.delete_object(&self.bucket, key).send().await?;
+        Ok(())
+    }
+
+    async fn list(&self, prefix: &str) -> anyhow::Result<Vec<ObjectInfo>> {
+        let mut pages = self
+            .client
+            .list_objects(&self.bucket)
+            .prefix(Some(prefix.to_owned()))
+            .recursive(true)
+            .to_stream()
+            .await;
+
+        let mut objects = Vec::new();
+        while let Some(page) = pages.next().await {
+            objects.extend(page?.contents.into_iter().map(|entry| ObjectInfo {
+                key: entry.name,
+                size: entry.size.unwrap_or(0),
+                last_modified: entry.last_modified,
+            }));
+        }
+        Ok(objects)
+    }
+
+    async fn copy(&self, from: &str, to: &str) -> anyhow::Result<()> {
+        self.client
+            .copy_object(&self.bucket, to)
+            .source(CopySource::new(&self.bucket, from)?)
+            .send()
+            .await?;
+        Ok(())
+    }
+
+    async fn create_upload(&self, key: &str) -> anyhow::Result<String> {
+        let response = self
+            .client
+            .create_multipart_upload(&self.bucket, key)
+            .send()
+            .await?;
+        Ok(response.upload_id)
+    }
+
+    async fn upload_part(
+        &self,
+        key: &str,
+        upload_id: &str,
+        number: u16,
+        data: Bytes,
+    ) -> anyhow::Result<UploadedPart> {
+        let size = data.len() as u64;
+        let response = self
+            .client
+            .upload_part(&self.bucket, key, upload_id, number, data.into())
+            .send()
+            .await?;
+        Ok(UploadedPart {
+            number,
+            etag: response.etag,
+            size,
+        })
+    }
+
+    async fn complete_upload(
+        &self,
+        key: &str,
+        upload_id: &str,
+        parts: Vec<UploadedPart>,
+    ) -> anyhow::Result<()> {
+        let parts = parts
+            .into_iter()
+            .map(|part| PartInfo {
+                number: part.number,
+                etag: part.etag,
+                size: part.size,
+            })
+            .collect();
+        self.client
+            .complete_multipart_upload(&self.bucket, key, upload_id, parts)
+            .send()
+            .await?;
+        Ok(())
+    }
+
+    async fn abort_upload(&self, key: &str, upload_id: &str) -> anyhow::Result<()> {
+        self.client
+            .abort_multipart_upload(&self.bucket, key, upload_id)
+            .send()
+            .await?;
+        Ok(())
+    }
+}
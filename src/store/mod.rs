@@ -0,0 +1,315 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use chrono::{DateTime, Utc};
+use futures::{
+    stream::{BoxStream, FuturesUnordered},
+    StreamExt,
+};
+
+pub mod filesystem;
+pub mod memory;
+pub mod minio;
+
+pub type ByteStream<'a> = BoxStream<'a, anyhow::Result<Bytes>>;
+
+// S3 rejects parts smaller than 5 MiB unless it's the last one, and more than 10000 parts
+pub const MIN_PART_SIZE: usize = 5 * 1024 * 1024;
+pub const MAX_PARTS: u16 = 10000;
+
+#[derive(Debug, Clone)]
+pub struct ObjectInfo {
+    pub key: String,
+    pub size: u64,
+    // None where the backend doesn't keep it
+    pub last_modified: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct UploadedPart {
+    pub number: u16,
+    pub etag: String,
+    pub size: u64,
+}
+
+/// Where vault blobs are kept. Keys are `/`-separated relative paths
+#[async_trait]
+pub trait VaultStore: Send + Sync {
+    /// Called once at startup, creates whatever the backend needs (bucket, root directory)
+    async fn prepare(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Streams `body` into `key`, replacing whatever was there. Returns amount of bytes written
+    async fn put(&self, key: &str, body: ByteStream<'_>) -> anyhow::Result<u64>;
+
+    async fn get(&self, key: &str) -> anyhow::Result<ByteStream<'static>>;
+
+    /// Returns `None` if there is no such object
+    async fn head(&self, key: &str) -> anyhow::Result<Option<ObjectInfo>>;
+
+    /// Deleting a missing object is not an error
+    async fn delete(&self, key: &str) -> anyhow::Result<()>;
+
+    async fn list(&self, prefix: &str) -> anyhow::Result<Vec<ObjectInfo>>;
+
+    /// Starts a multipart upload that is assembled into `key` once completed
+    async fn create_upload(&self, key: &str) -> anyhow::Result<String>;
+
+    /// Part numbers start at 1
+    async fn upload_part(
+        &self,
+        key: &str,
+        upload_id: &str,
+        number: u16,
+        data: Bytes,
+    ) -> anyhow::Result<UploadedPart>;
+
+    async fn complete_upload(
+        &self,
+        key: &str,
+        upload_id: &str,
+        parts: Vec<UploadedPart>,
+    ) -> anyhow::Result<()>;
+
+    async fn abort_upload(&self, key: &str, upload_id: &str) -> anyhow::Result<()>;
+
+    async fn copy(&self, from: &str, to: &str) -> anyhow::Result<()> {
+        let body = self.get(from).await?;
+        self.put(to, body).await?;
+        Ok(())
+    }
+}
+
+/// Picks the backend from `STORAGE_BACKEND`: `minio` (default), `filesystem` or `memory`
+pub fn from_env() -> Arc<dyn VaultStore> {
+    let backend = std::env::var("STORAGE_BACKEND").unwrap_or("minio".to_string());
+    match backend.as_str() {
+        "minio" => Arc::new(minio::MinioStore::from_env()),
+        "filesystem" => Arc::new(filesystem::FilesystemStore::new(
+            std::env::var("STORAGE_PATH").expect("STORAGE_PATH NOT SET"),
+        )),
+        "memory" => {
+            tracing::warn!("Using in-memory storage, vaults will be lost on restart");
+            Arc::new(memory::MemoryStore::default())
+        }
+        other => panic!("Unknown STORAGE_BACKEND: {}", other),
+    }
+}
+
+/// Gathers `body` into parts of `part_size` bytes and uploads up to `max_in_flight` of them
+/// at once. The multipart upload is aborted if anything fails
+pub async fn put_multipart<S: VaultStore + ?Sized>(
+    store: &S,
+    key: &str,
+    body: ByteStream<'_>,
+    part_size: usize,
+    max_in_flight: usize,
+) -> anyhow::Result<u64> {
+    let upload_id = store.create_upload(key).await?;
+
+    let result = match upload_parts(store, key, &upload_id, body, part_size, max_in_flight).await {
+        Ok((parts, size)) => store
+            .complete_upload(key, &upload_id, parts)
+            .await
+            .map(|_| size),
+        Err(why) => Err(why),
+    };
+    if result.is_err() {
+        if let Err(why) = store.abort_upload(key, &upload_id).await {
+            tracing::error!("Could not abort upload of {}: {}", key, why);
+        }
+    }
+    result
+}
+
+async fn upload_parts<S: VaultStore + ?Sized>(
+    store: &S,
+    key: &str,
+    upload_id: &str,
+    mut body: ByteStream<'_>,
+    part_size: usize,
+    max_in_flight: usize,
+) -> anyhow::Result<(Vec<UploadedPart>, u64)> {
+    let mut in_flight = FuturesUnordered::new();
+    let mut parts = Vec::new();
+    let mut buffer = BytesMut::with_capacity(part_size);
+    let mut number: u16 = 0;
+    let mut size: u64 = 0;
+
+    loop {
+        // Keep parts moving while waiting for more data from the client
+        let chunk = loop {
+            tokio::select! {
+                chunk = body.next() => break chunk.transpose()?,
+                Some(part) = in_flight.next(), if !in_flight.is_empty() => parts.push(part?),
+            }
+        };
+        let done = chunk.is_none();
+        if let Some(chunk) = chunk {
+            size += chunk.len() as u64;
+            buffer.extend_from_slice(&chunk);
+        }
+
+        while buffer.len() >= part_size || (done && !buffer.is_empty()) {
+            let data = if buffer.len() >= part_size {
+                buffer.split_to(part_size).freeze()
+            } else {
+                buffer.split().freeze()
+            };
+            if number == MAX_PARTS {
+                anyhow::bail!("Upload does not fit into {} parts", MAX_PARTS);
+            }
+            number += 1;
+
+            if in_flight.len() >= max_in_flight {
+                if let Some(part) = in_flight.next().await {
+                    parts.push(part?);
+                }
+            }
+            in_flight.push(store.upload_part(key, upload_id, number, data));
+        }
+
+        if done {
+            break;
+        }
+    }
+    while let Some(part) = in_flight.next().await {
+        parts.push(part?);
+    }
+
+    // Multipart uploads need at least one part, even for an empty vault
+    if number == 0 {
+        parts.push(store.upload_part(key, upload_id, 1, Bytes::new()).await?);
+    }
+    parts.sort_by_key(|part| part.number);
+    Ok((parts, size))
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::TryStreamExt;
+
+    use super::*;
+
+    fn body(data: &'static [u8]) -> ByteStream<'static> {
+        futures::stream::iter(
+            data.chunks(3)
+                .map(|chunk| Ok(Bytes::from_static(chunk)))
+                .collect::<Vec<_>>(),
+        )
+        .boxed()
+    }
+
+    async fn read(store: &dyn VaultStore, key: &str) -> Vec<u8> {
+        store
+            .get(key)
+            .await
+            .unwrap()
+            .try_fold(Vec::new(), |mut data, chunk| async move {
+                data.extend_from_slice(&chunk);
+                Ok(data)
+            })
+            .await
+            .unwrap()
+    }
+
+    async fn keys(store: &dyn VaultStore, prefix: &str) -> Vec<String> {
+        let mut keys: Vec<_> = store
+            .list(prefix)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|object| object.key)
+            .collect();
+        keys.sort();
+        keys
+    }
+
+    pub(super) async fn round_trip(store: &dyn VaultStore) {
+        store.prepare().await.unwrap();
+
+        assert_eq!(store.put("1/a/1", body(b"first vault")).await.unwrap(), 11);
+        store.put("1/a/2", body(b"second")).await.unwrap();
+        store.put("2/a/1", body(b"other user")).await.unwrap();
+        assert_eq!(read(store, "1/a/1").await, b"first vault");
+
+        // Putting again replaces the object
+        store.put("1/a/2", body(b"replaced")).await.unwrap();
+        assert_eq!(read(store, "1/a/2").await, b"replaced");
+        assert_eq!(store.head("1/a/2").await.unwrap().unwrap().size, 8);
+        assert!(store.head("1/a/3").await.unwrap().is_none());
+
+        assert_eq!(keys(store, "1/").await, ["1/a/1", "1/a/2"]);
+        assert_eq!(keys(store, "").await.len(), 3);
+
+        store.copy("1/a/1", "1/b/1").await.unwrap();
+        assert_eq!(read(store, "1/b/1").await, b"first vault");
+
+        store.delete("1/a/1").await.unwrap();
+        store.delete("1/a/1").await.unwrap();
+        assert!(store.head("1/a/1").await.unwrap().is_none());
+        assert!(store.get("1/a/1").await.is_err());
+        assert_eq!(keys(store, "1/").await, ["1/a/2", "1/b/1"]);
+    }
+
+    pub(super) async fn multipart(store: &dyn VaultStore) {
+        store.prepare().await.unwrap();
+
+        let upload_id = store.create_upload("3/a/1").await.unwrap();
+        // Parts can arrive in any order, `complete_upload` decides the order
+        let second = store
+            .upload_part("3/a/1", &upload_id, 2, Bytes::from_static(b"world"))
+            .await
+            .unwrap();
+        let first = store
+            .upload_part("3/a/1", &upload_id, 1, Bytes::from_static(b"hello "))
+            .await
+            .unwrap();
+        assert_eq!(first.size, 6);
+        // Unfinished uploads are not objects yet
+        assert!(store.head("3/a/1").await.unwrap().is_none());
+        assert!(keys(store, "").await.is_empty());
+
+        store
+            .complete_upload("3/a/1", &upload_id, vec![first, second])
+            .await
+            .unwrap();
+        assert_eq!(read(store, "3/a/1").await, b"hello world");
+        assert_eq!(keys(store, "").await, ["3/a/1"]);
+
+        let upload_id = store.create_upload("3/a/2").await.unwrap();
+        let part = store
+            .upload_part("3/a/2", &upload_id, 1, Bytes::from_static(b"dropped"))
+            .await
+            .unwrap();
+        store.abort_upload("3/a/2", &upload_id).await.unwrap();
+        assert!(store
+            .complete_upload("3/a/2", &upload_id, vec![part])
+            .await
+            .is_err());
+        assert!(store.head("3/a/2").await.unwrap().is_none());
+
+        let size = put_multipart(store, "3/a/3", body(b"split into small parts"), 4, 2)
+            .await
+            .unwrap();
+        assert_eq!(size, 22);
+        assert_eq!(read(store, "3/a/3").await, b"split into small parts");
+
+        put_multipart(store, "3/a/4", body(b""), 4, 2)
+            .await
+            .unwrap();
+        assert_eq!(store.head("3/a/4").await.unwrap().unwrap().size, 0);
+    }
+
+    #[tokio::test]
+    async fn memory_round_trip() {
+        round_trip(&memory::MemoryStore::default()).await;
+    }
+
+    #[tokio::test]
+    async fn memory_multipart() {
+        multipart(&memory::MemoryStore::default()).await;
+    }
+}